@@ -0,0 +1,125 @@
+use anyhow::Result;
+use clap::Parser;
+use hacksilver::game::*;
+use hacksilver::internal::*;
+use hacksilver::net::Transport;
+use hacksilver::resources::*;
+
+/// Fill a server with bots, to measure how it holds up.
+///
+/// Prints, every few seconds, the server ticks per second seen by the bots
+/// (falls behind when the server can't keep up) and the bandwidth each bot receives.
+#[derive(Parser)]
+struct LoadtestFlags {
+	/// Server to connect to.
+	#[arg(default_value = "127.0.0.1:3344")]
+	server: String,
+
+	/// Number of bots.
+	#[arg(short, long, default_value_t = 8)]
+	bots: u32,
+
+	/// Transport protocol (tcp|udp), must match the server's.
+	#[arg(long, value_enum, default_value_t)]
+	transport: Transport,
+
+	/// Seconds between bots joining.
+	#[arg(long, default_value_t = 0.2)]
+	join_interval: f32,
+
+	/// Stop after this many seconds (default: run until killed).
+	#[arg(long)]
+	duration: Option<f32>,
+
+	/// Seed for the bots' moves.
+	#[arg(long, default_value_t = 1)]
+	seed: u64,
+}
+
+// Bots tick at about the rate of a real client.
+const TICK: Duration = Duration::from_millis(16);
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(2);
+
+fn main() {
+	env_logger::init();
+	let args = LoadtestFlags::parse();
+
+	debug_warn();
+
+	exit_on_error(main_result(args))
+}
+
+fn main_result(args: LoadtestFlags) -> Result<()> {
+	let assets = AssetsDir::find()?;
+	let start = Instant::now();
+	let mut bots = Vec::<Bot>::new();
+	let mut joined = 0;
+	let mut dropped = 0;
+	let mut next_join = start;
+	let mut report = Report::new(start);
+
+	while args.duration.map(|d| start.elapsed().as_secs_f32() < d).unwrap_or(true) {
+		let tick_start = Instant::now();
+
+		if joined < args.bots && tick_start >= next_join {
+			let name = format!("bot{joined}");
+			match Bot::connect(&assets, &args.server, args.transport, &name, args.seed.wrapping_add(joined as u64)) {
+				Ok(bot) => bots.push(bot),
+				Err(e) => println!("{name}: {e:#}"),
+			}
+			joined += 1;
+			next_join = tick_start + Duration::from_secs_f32(args.join_interval);
+		}
+
+		bots.retain_mut(|bot| match bot.tick(TICK) {
+			Ok(()) => true,
+			Err(e) => {
+				println!("bot {}: disconnected: {e:#}", bot.player_id());
+				dropped += 1;
+				false
+			}
+		});
+
+		if report.due() {
+			report.print(&bots, dropped);
+		}
+
+		if let Some(sleep) = TICK.checked_sub(tick_start.elapsed()) {
+			thread::sleep(sleep)
+		}
+	}
+	Ok(())
+}
+
+// Rates since the previous report.
+struct Report {
+	last: Instant,
+	ticks: u64,
+	bytes: u64,
+}
+
+impl Report {
+	fn new(now: Instant) -> Self {
+		Self { last: now, ticks: 0, bytes: 0 }
+	}
+
+	fn due(&self) -> bool {
+		self.last.elapsed() >= REPORT_INTERVAL
+	}
+
+	fn print(&mut self, bots: &[Bot], dropped: u32) {
+		let secs = self.last.elapsed().as_secs_f64();
+		let ticks = bots.iter().map(Bot::server_ticks).sum::<u64>();
+		let bytes = bots.iter().map(Bot::bytes_received).sum::<u64>();
+		// bots that left take their counts with them, the next report is accurate again.
+		let per_bot = |total: u64, prev: u64| total.saturating_sub(prev) as f64 / secs / f64::max(1.0, bots.len() as f64);
+		println!(
+			"{:>3} bots ({dropped} dropped)  {:>5.1} server ticks/s  {:>7.1} kB/s per bot",
+			bots.len(),
+			per_bot(ticks, self.ticks),
+			per_bot(bytes, self.bytes) / 1000.0
+		);
+		*self = Self { last: Instant::now(), ticks, bytes };
+	}
+}
@@ -2,6 +2,8 @@ use anyhow::Result;
 use clap::Parser;
 use hacksilver::game::*;
 use hacksilver::internal::*;
+use hacksilver::net::NetSimOpts;
+use hacksilver::net::Transport;
 
 /// Play the game by connecting to a server.
 #[derive(Parser)]
@@ -22,6 +24,26 @@ struct PlayFlags {
 	#[arg(short, long)]
 	team: Option<String>,
 
+	/// Join as spectator: fly around or follow players (mouse buttons) without playing.
+	#[arg(long)]
+	spectate: bool,
+
+	/// Override the transport protocol (tcp|udp), must match the server's.
+	#[arg(long, value_enum)]
+	transport: Option<Transport>,
+
+	/// List servers (configured and on the LAN) with their status, then quit.
+	#[arg(long)]
+	list_servers: bool,
+
+	/// Simulate a bad network, e.g. `latency=80,jitter=20,loss=0.05` (see `NetSimOpts`).
+	#[arg(long)]
+	netsim: Option<NetSimOpts>,
+
+	/// Play alone on this map, with a server running in-process (no network needed).
+	#[arg(long, value_name = "MAP")]
+	local: Option<String>,
+
 	/// Force disable sound (overrides settings.toml).
 	#[arg(long)]
 	no_sound: bool,
@@ -45,8 +67,16 @@ fn main_result(args: PlayFlags) -> Result<()> {
 		Err(e) => return Shell::main_loop(default(), default(), move |_| -> Result<NopApp> { Err(anyhow!("{}: {e:#}", args.settings)) }),
 	};
 
+	if args.list_servers {
+		return list_servers(&settings.network.servers);
+	}
+
+	let local = args.local.clone();
 	let settings = settings.with(|s| override_play_settings(s, args));
-	Shell::main_loop(settings.graphics.clone(), settings.controls.clone(), move |ctx| Client::new(ctx, settings))
+	Shell::main_loop(settings.graphics.clone(), settings.controls.clone(), move |ctx| match local {
+		Some(map) => Client::new_local(ctx, settings, &map),
+		None => Client::new(ctx, settings),
+	})
 }
 
 fn load_settings(file: &str) -> Result<Settings> {
@@ -54,6 +84,32 @@ fn load_settings(file: &str) -> Result<Settings> {
 	load_toml(&assets.settings_file(file)?)
 }
 
+fn list_servers(configured: &[String]) -> Result<()> {
+	for server in find_servers(configured, DEFAULT_QUERY_PORT, Duration::from_secs(1))? {
+		let status = &server.status;
+		let playing = status.players.iter().filter(|p| !p.spectator).count();
+		println!(
+			"{:<22} {:>4} ms  {:<12} {playing:>2}/{} players  {:>4.0}s left",
+			server.addr,
+			server.ping.as_millis(),
+			status.map_name,
+			status.max_players,
+			status.time_remaining
+		);
+		for p in &status.players {
+			println!(
+				"    {:<16} {:<6} {:>3} frags {:>3} deaths{}",
+				p.name,
+				p.team,
+				p.frags,
+				p.deaths,
+				if p.spectator { " (spectating)" } else { "" }
+			);
+		}
+	}
+	Ok(())
+}
+
 fn override_play_settings(settings: &mut Settings, flags: PlayFlags) {
 	if flags.no_sound {
 		settings.sound.enabled = false;
@@ -61,12 +117,21 @@ fn override_play_settings(settings: &mut Settings, flags: PlayFlags) {
 	if let Some(server) = flags.server {
 		settings.network.servers = vec![server];
 	}
+	if let Some(transport) = flags.transport {
+		settings.network.transport = transport;
+	}
+	if let Some(sim) = flags.netsim {
+		settings.network.sim = Some(sim);
+	}
 	if let Some(name) = flags.name {
 		settings.player.name = name;
 	}
 	if let Some(team) = flags.team {
 		settings.player.team = team;
 	}
+	if flags.spectate {
+		settings.player.spectate = true;
+	}
 }
 
 // An App that does nothing. Used to if there was an error loading settings.toml.
@@ -2,6 +2,8 @@ use anyhow::Result;
 use clap::Parser;
 use hacksilver::game::*;
 use hacksilver::internal::*;
+use hacksilver::net::NetSimOpts;
+use hacksilver::net::Transport;
 use hacksilver::resources::*;
 
 /// Dedicated server.
@@ -16,6 +18,14 @@ struct ServerFlags {
 
 	#[arg(long)]
 	settings: Option<String>,
+
+	/// Override the transport protocol (tcp|udp).
+	#[arg(long, value_enum)]
+	transport: Option<Transport>,
+
+	/// Simulate a bad network to all clients, e.g. `latency=80,jitter=20,loss=0.05` (see `NetSimOpts`).
+	#[arg(long)]
+	netsim: Option<NetSimOpts>,
 }
 
 fn main() {
@@ -47,5 +57,11 @@ fn override_server_settings(opts: ServerOpts, flags: ServerFlags) -> ServerOpts
 	if let Some(maplist) = flags.maplist {
 		settings.maplist = maplist;
 	}
+	if let Some(transport) = flags.transport {
+		settings.transport = transport;
+	}
+	if let Some(sim) = flags.netsim {
+		settings.netsim = Some(sim);
+	}
 	settings
 }
@@ -0,0 +1,157 @@
+use super::internal::*;
+use std::iter;
+
+/// A headless client that plays by script, for load testing servers (see the `loadtest` binary).
+///
+/// Joins like a real client and keeps its own copy of the world through the same `Replica`,
+/// but has no graphics or sound. Runs forward, turning left and right for a while,
+/// jumping and shooting now and then (which also makes it respawn).
+///
+/// Bots don't download maps: the server's map must be in the local assets.
+pub struct Bot {
+	conn: Conn,
+	assets: AssetsDir,
+	replica: Replica,
+	inputs: Inputs,
+	rng: Xoshiro256PlusPlus,
+	turn: (i32, f32), // mouse motion per tick, for how many more seconds
+	server_ticks: u64,
+}
+
+type Conn = NetPipe<ClientMsg, ServerMsg>;
+
+// As the default `NetworkOpts::interpolation_delay_ms`.
+const INTERPOLATION_DELAY: f64 = 0.1;
+
+const SHOTS_PER_SEC: f32 = 1.5;
+const JUMPS_PER_SEC: f32 = 0.5;
+
+impl Bot {
+	/// Connect to a game server and join as `name`.
+	/// `seed` determines the team and the bot's moves.
+	pub fn connect(assets: &AssetsDir, server: &str, transport: Transport, name: &str, seed: u64) -> Result<Self> {
+		let join_req = Self::join_request(name, seed);
+		let (conn, accepted) = match transport {
+			Transport::Tcp => Client::connect(server, join_req),
+			Transport::Udp => Client::connect_udp(server, join_req),
+		}?;
+		Self::new(assets, conn, accepted, seed)
+	}
+
+	/// A bot on a connection that has already joined (e.g. `NetServer::join_local`).
+	pub fn new(assets: &AssetsDir, conn: Conn, accepted: AcceptedMsg, seed: u64) -> Result<Self> {
+		let world = Self::load_world(assets, accepted.map_switch)?;
+		Ok(Self {
+			conn,
+			assets: assets.clone(),
+			replica: Replica::new(accepted.player_id, world, INTERPOLATION_DELAY),
+			inputs: Inputs::new(default()),
+			rng: Xoshiro256PlusPlus::seed_from_u64(seed),
+			turn: (0, 0.0),
+			server_ticks: 0,
+		})
+	}
+
+	pub fn join_request(name: &str, seed: u64) -> JoinRequest {
+		JoinRequest {
+			protocol_version: PROTOCOL_VERSION,
+			capabilities: Capabilities::SUPPORTED,
+			name: name.into(),
+			avatar_id: 0,
+			team: Some(Team::ALL[(seed % NUM_TEAMS as u64) as usize]),
+			spectator: false,
+			session: None,
+		}
+	}
+
+	fn load_world(assets: &AssetsDir, map_switch: MapSwitch) -> Result<World> {
+		let map_dir = find_map_dir(assets, &map_switch).ok_or_else(|| anyhow!("bot: map {} not found (bots don't download maps)", map_switch.map_name))?;
+		let map = Map::load_dir(&map_dir, &map_switch.map_name)?;
+		Ok(World::new(map, map_switch.entities, Weapons::load(assets)?))
+	}
+
+	/// Handle the messages from the server, play for `dt` and send our moves.
+	/// An error means we got disconnected.
+	pub fn tick(&mut self, dt: Duration) -> Result<()> {
+		let msgs = iter::from_fn(|| self.conn.try_recv()).collect::<Result<Vec<_>>>()?;
+		for msg in msgs {
+			match msg {
+				ServerMsg::SwitchMap(map_switch) => self.switch_map(map_switch)?,
+				ServerMsg::Ping(seq) => self.conn.send(ClientMsg::Pong(seq))?,
+				ServerMsg::MapDownload(_) => (/*never requested*/),
+				msg => {
+					if let ServerMsg::ServerTime(..) = msg {
+						self.server_ticks += 1;
+					}
+					self.replica.apply_server_msg(msg)
+				}
+			}
+		}
+
+		self.script(dt);
+		self.replica.tick(&self.inputs);
+		self.inputs.forget();
+
+		for msg in mem::take(&mut self.replica.pending_diffs) {
+			self.conn.send(msg)?;
+		}
+		Ok(())
+	}
+
+	fn switch_map(&mut self, map_switch: MapSwitch) -> Result<()> {
+		let world = Self::load_world(&self.assets, map_switch)?;
+		let snapshots = mem::take(&mut self.replica.snapshots);
+		self.replica = Replica::new(self.replica.local_player_id, world, INTERPOLATION_DELAY).with(|r| r.snapshots = snapshots);
+		Ok(())
+	}
+
+	// Press buttons and move the mouse as a player would.
+	fn script(&mut self, dt: Duration) {
+		use ElementState::*;
+		let inputs = &mut self.inputs;
+		inputs.tick_time = dt;
+
+		// jump and fire buttons are only held for one tick.
+		for button in [inputs.JUMP, Button::MOUSE1] {
+			if inputs.is_down(button) {
+				inputs.record_button(button, Released);
+			}
+		}
+
+		inputs.record_button(inputs.FORWARD, Pressed); // no-op while held down.
+
+		self.turn.1 -= dt.as_secs_f32();
+		if self.turn.1 <= 0.0 {
+			self.turn = (self.rng.gen_range(-40..=40), self.rng.gen_range(0.5..3.0));
+		}
+		inputs.record_mouse_motion(dvec2(self.turn.0 as f64, 0.0));
+
+		if self.rng.gen::<f32>() < JUMPS_PER_SEC * dt.as_secs_f32() {
+			inputs.record_button(inputs.JUMP, Pressed);
+		}
+		if self.rng.gen::<f32>() < SHOTS_PER_SEC * dt.as_secs_f32() {
+			inputs.record_button(Button::MOUSE1, Pressed);
+		}
+	}
+
+	// __________________________________________________________ accessors
+
+	pub fn player_id(&self) -> ID {
+		self.replica.local_player_id
+	}
+
+	/// The bot's copy of the world.
+	pub fn replica(&self) -> &Replica {
+		&self.replica
+	}
+
+	/// Number of server ticks seen (`ServerMsg::ServerTime`).
+	pub fn server_ticks(&self) -> u64 {
+		self.server_ticks
+	}
+
+	/// Total size on the wire of the messages received from the server.
+	pub fn bytes_received(&self) -> u64 {
+		self.conn.bytes_received()
+	}
+}
@@ -0,0 +1,117 @@
+use super::internal::*;
+
+/// A chat line (`say`, `say_team`, `tell`), sent as `ServerMsg::Chat`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChatMsg {
+	pub sender: Option<ID>, // None: the server console
+	pub channel: ChatChannel,
+	pub text: String,
+}
+
+/// Who a chat line was sent to.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChatChannel {
+	All,
+	Team(Team),
+	Private { to: ID },
+}
+
+impl ChatMsg {
+	/// The line as shown to a player. E.g.:
+	///
+	///   alice: hi
+	///   (Red) alice: go left
+	///   alice -> bob: gg
+	///
+	pub fn format(&self, players: &Players) -> String {
+		let name = |id: Option<ID>| match id {
+			None => "server".into(),
+			Some(id) => players.get(id).map(|p| p.name.clone()).unwrap_or_else(|| format!("{id}")),
+		};
+		let sender = name(self.sender);
+		match self.channel {
+			ChatChannel::All => format!("{sender}: {}", self.text),
+			ChatChannel::Team(team) => format!("({team}) {sender}: {}", self.text),
+			ChatChannel::Private { to } => format!("{sender} -> {}: {}", name(Some(to)), self.text),
+		}
+	}
+}
+
+/// Longest chat line, in characters.
+pub const MAX_CHAT_LEN: usize = 200;
+
+/// Turn what a player said into a single line that fits the HUD:
+/// control characters (including newlines) replaced by spaces, at most `MAX_CHAT_LEN` characters.
+/// So that nobody can e.g. start a new line that looks like it came from someone else.
+pub fn sanitize_chat(text: &str) -> String {
+	text.chars()
+		.map(|c| if c.is_control() { ' ' } else { c })
+		.collect::<String>()
+		.trim()
+		.chars()
+		.take(MAX_CHAT_LEN)
+		.collect()
+}
+
+/// Chat flood protection: each player may send a burst of `CHAT_BURST` lines,
+/// then one every `CHAT_INTERVAL` seconds.
+#[derive(Default)]
+pub struct ChatFlood {
+	allowance: HashMap<ID, (f64, f64)>, // lines left, server time when last spent
+}
+
+const CHAT_BURST: f64 = 5.0;
+const CHAT_INTERVAL: f64 = 2.0;
+
+impl ChatFlood {
+	/// Spend one line for player `id` at server time `now`,
+	/// or tell them to slow down.
+	pub fn spend(&mut self, id: ID, now: f64) -> Result<()> {
+		let (lines, last) = self.allowance.entry(id).or_insert((CHAT_BURST, now));
+		*lines = f64::min(*lines + (now - *last) / CHAT_INTERVAL, CHAT_BURST);
+		*last = now;
+		if *lines < 1.0 {
+			return Err(anyhow!("not so fast: wait {:.0}s before chatting again", ((1.0 - *lines) * CHAT_INTERVAL).ceil()));
+		}
+		*lines -= 1.0;
+		Ok(())
+	}
+
+	pub fn forget(&mut self, id: ID) {
+		self.allowance.remove(&id);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn sanitize() {
+		assert_eq!(sanitize_chat(" hi there "), "hi there");
+		assert_eq!(sanitize_chat("gg\nserver: you are banned"), "gg server: you are banned");
+		assert_eq!(sanitize_chat("\x1b[31mred\x07\r\t"), "[31mred");
+		assert_eq!(sanitize_chat("żółw"), "żółw");
+		assert_eq!(sanitize_chat("\n\n"), "");
+		assert_eq!(sanitize_chat(&"x".repeat(100_000)).len(), MAX_CHAT_LEN);
+	}
+
+	#[test]
+	fn flood() {
+		let mut flood = ChatFlood::default();
+		let alice = ID::default();
+		for _ in 0..5 {
+			flood.spend(alice, 0.0).unwrap();
+		}
+		assert!(flood.spend(alice, 0.0).is_err());
+		assert!(flood.spend(alice, 1.0).is_err());
+		flood.spend(alice, 2.0).unwrap();
+		assert!(flood.spend(alice, 2.0).is_err());
+		flood.spend(alice, 100.0).unwrap();
+
+		flood.forget(alice);
+		for _ in 0..5 {
+			flood.spend(alice, 100.0).unwrap();
+		}
+	}
+}
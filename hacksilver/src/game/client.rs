@@ -1,8 +1,17 @@
 use super::internal::*;
+use std::iter;
 
 pub struct Client {
 	//menu: Menu, // TODO
-	conn: Conn,
+	conn: Result<Conn>, // error explains why we got disconnected
+
+	// For reconnecting: where to, and with which request (carrying our session token).
+	server: Server,
+	join_req: JoinRequest,
+	reconnect: Option<Receiver<Result<(Conn, AcceptedMsg)>>>, // connection attempt in progress
+	next_reconnect: Instant,
+
+	download: Option<(MapDownload, ID)>, // map we switch to once downloaded, with our player ID there
 
 	state: ClientState,
 	zones: ZoneGraph, // where to put this ?????
@@ -15,73 +24,168 @@ pub struct Client {
 
 type Conn = NetPipe<ClientMsg, ServerMsg>;
 
+// Where we're playing.
+#[derive(Clone)]
+enum Server {
+	Remote(String),        // address, connected to over `network.transport`
+	Local(LocalConnector), // running in this process (see `Client::new_local`)
+}
+
+// Time between attempts to reconnect after the connection was lost.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+
 impl Client {
 	pub fn new(ctx: &Arc<GraphicsCtx>, settings: Settings) -> Result<Self> {
 		let server = settings
 			.network
 			.servers
 			.get(0)
-			.ok_or_else(|| anyhow!("no servers specified (flag --server or settings.toml: network > servers)"))?;
+			.ok_or_else(|| anyhow!("no servers specified (flag --server or settings.toml: network > servers)"))?
+			.clone();
+		Self::with_server(ctx, settings, Server::Remote(server))
+	}
+
+	/// Single player: start a server on `map` in this process, and play on it (no sockets involved).
+	pub fn new_local(ctx: &Arc<GraphicsCtx>, settings: Settings, map: &str) -> Result<Self> {
+		LOG.write(format!("Starting local server on {map}..."));
+		let server = NetServer::local(ServerOpts {
+			maplist: vec![map.into()],
+			..default()
+		})?;
+		let connector = server.connector();
+		server.spawn();
+		Self::with_server(ctx, settings, Server::Local(connector))
+	}
 
-		let join_req = JoinRequest {
+	fn with_server(ctx: &Arc<GraphicsCtx>, settings: Settings, server: Server) -> Result<Self> {
+		let mut join_req = JoinRequest {
+			protocol_version: PROTOCOL_VERSION,
+			capabilities: Capabilities::SUPPORTED,
 			name: settings.player.name.clone(),
 			avatar_id: settings.player.avatar,
-			team: settings.player.team.parse()?,
+			team: Team::parse_choice(&settings.player.team)?,
+			spectator: settings.player.spectate,
+			session: None,
 		};
 
-		let (conn, acc) = Self::connect(server, join_req)?;
+		let (conn, acc) = Self::join(&server, settings.network.transport, settings.network.sim.as_ref(), join_req.clone())?;
+		join_req.session = Some(acc.session);
+		if acc.name != join_req.name {
+			LOG.write(format!("joined as {}", acc.name));
+			join_req.name = acc.name.clone(); // so we get the same name should our session expire
+		}
 
+		// Start out on an empty map: loaded right below, or downloaded while the HUD shows progress (see `switch_map`).
 		let eng = GameCtx::new(ctx, settings)?;
-		let (state, zones) = Self::load_state(&eng, acc.map_switch, acc.player_id)?;
+		let world = World::new(Map::empty(&acc.map_switch.map_name), acc.map_switch.entities.clone(), Weapons::load(&eng.assets)?);
+		let state = ClientState::new(&eng, acc.player_id, world);
 
-		Ok(Self {
+		let mut client = Self {
 			eng,
-			conn,
+			conn: Ok(conn),
+			server,
+			join_req,
+			reconnect: None,
+			next_reconnect: Instant::now(),
+			download: None,
 			state,
-			zones,
+			zones: default(),
 			drawcfg: DrawCfg::default(),
 			dbg_overlay: false,
 			fps_overlay: false,
-		})
+		};
+		client.switch_map(acc.map_switch, acc.player_id)?;
+		Ok(client)
 	}
 
-	fn load_state(eng: &GameCtx, map_switch: MapSwitch, player_id: ID) -> Result<(ClientState, ZoneGraph)> {
-		let map_name = &map_switch.map_name;
-		let map = Map::load(&eng.assets, &map_name)?;
+	fn load_state(eng: &GameCtx, map_dir: &MapDir, map_switch: MapSwitch, player_id: ID) -> Result<(ClientState, ZoneGraph)> {
+		let map = Map::load_dir(map_dir, &map_switch.map_name)?;
 		// load zonegraph from disk, or bake if not found
-		let map_dir = eng.assets.map_dir(map_name);
-		let hzones = HZoneGraph::load(&map_dir).unwrap_or_else(|err| {
+		let hzones = HZoneGraph::load(map_dir).unwrap_or_else(|err| {
 			LOG.write(format!("ERROR loading zonegraph: {err}, falling back to live bake"));
 			let todo = BakeOpts::default(); // TODO: replace by Engine !!!
 			HZoneGraph::bake(&todo, &eng.resources.materials, map.data(), Cancel::new())
 		});
 		let zones = ZoneGraph::upload(&eng.graphics, &eng.resources.materials, &map.data().palette, hzones);
-		let world = World::new(map, map_switch.entities);
+		let world = World::new(map, map_switch.entities, Weapons::load(&eng.assets)?);
 		let state = ClientState::new(&eng, player_id, world);
 		Ok((state, zones))
 	}
 
-	fn connect(server: &str, join_req: JoinRequest) -> Result<(Conn, AcceptedMsg)> {
+	// Connect to the server and join, over the given transport (ignored for a local server).
+	// With `sim`, the connection (after joining) goes through a simulated bad network.
+	fn join(server: &Server, transport: Transport, sim: Option<&NetSimOpts>, join_req: JoinRequest) -> Result<(Conn, AcceptedMsg)> {
+		let (conn, accepted) = match (server, transport) {
+			(Server::Remote(addr), Transport::Tcp) => Self::connect(addr, join_req),
+			(Server::Remote(addr), Transport::Udp) => Self::connect_udp(addr, join_req),
+			(Server::Local(connector), _) => Self::connect_local(connector, join_req),
+		}?;
+		match sim {
+			None => Ok((conn, accepted)),
+			Some(sim) => {
+				LOG.write(format!("simulating network: {sim:?}"));
+				Ok((conn.simulate(sim), accepted))
+			}
+		}
+	}
+
+	pub(crate) fn connect(server: &str, join_req: JoinRequest) -> Result<(Conn, AcceptedMsg)> {
 		LOG.write(format!("Connecting to {server}..."));
 		let mut tcp_stream = TcpStream::connect(&server)?;
 		LOG.write(format!("Connected. Joining..."));
 		wireformat::serialize_into(&mut tcp_stream, &join_req)?;
-		let accepted_msg: AcceptedMsg = wireformat::deserialize_from(&mut tcp_stream) //
+		let response: JoinResponse = wireformat::deserialize_from(&mut tcp_stream) //
 			.map_err(|e| anyhow!("reading accept message: {e}"))?;
+		let accepted_msg = Self::accepted_or_err(response)?;
 		let player_id = accepted_msg.player_id;
 		LOG.write(format!("Accepted as player {player_id}"));
-		let conn = NetPipe::new(tcp_stream);
+		let conn = NetPipe::new(tcp_stream, wireformat::DEFAULT_MAX_FRAME);
 		Ok((conn, accepted_msg))
 	}
 
+	// Same handshake as `connect`, over the reliable UDP channel.
+	pub(crate) fn connect_udp(server: &str, join_req: JoinRequest) -> Result<(Conn, AcceptedMsg)> {
+		LOG.write(format!("Connecting to {server} (UDP)..."));
+		let udp_conn = UdpConn::connect(server)?;
+		let mut buf = Vec::new();
+		wireformat::serialize_into(&mut buf, &join_req)?;
+		udp_conn.send_reliable(&buf)?;
+		let response: JoinResponse = wireformat::deserialize_from(&udp_conn.recv()?[..]) //
+			.map_err(|e| anyhow!("reading accept message: {e}"))?;
+		let accepted_msg = Self::accepted_or_err(response)?;
+		let player_id = accepted_msg.player_id;
+		LOG.write(format!("Accepted as player {player_id}"));
+		let conn = NetPipe::udp(udp_conn, wireformat::DEFAULT_MAX_FRAME);
+		Ok((conn, accepted_msg))
+	}
+
+	// Same handshake as `connect`, with a server in this process.
+	fn connect_local(connector: &LocalConnector, join_req: JoinRequest) -> Result<(Conn, AcceptedMsg)> {
+		let (conn, response) = connector.join(join_req)?;
+		let accepted_msg = Self::accepted_or_err(response)?;
+		LOG.write(format!("Accepted as player {}", accepted_msg.player_id));
+		Ok((conn, accepted_msg))
+	}
+
+	// Turn the server's rejection into an error explaining why.
+	fn accepted_or_err(response: JoinResponse) -> Result<AcceptedMsg> {
+		match response {
+			JoinResponse::Accepted(accepted_msg) => Ok(accepted_msg),
+			JoinResponse::Rejected(RejectedMsg { reason }) => Err(anyhow!("server refused to let you join: {reason}")),
+		}
+	}
+
 	//--------------------------------------------------------------------------------  tick
 
 	fn tick(&mut self, inputs: &Inputs) -> StateChange {
-		//if self.conn.is_ok() {
-		self.tick_connected(inputs).expect("TODO: Client: handle tick error");
-		//} else {
-		//self.tick_disconnected(inputs)
-		//}
+		if self.conn.is_ok() {
+			if let Err(e) = self.tick_connected(inputs) {
+				LOG.write(format!("disconnected: {e:#}"));
+				self.conn = Err(e);
+			}
+		} else {
+			self.tick_disconnected(inputs)
+		}
 
 		if inputs.is_down(Button::ESC) {
 			StateChange::ReleaseCursor
@@ -91,20 +195,31 @@ impl Client {
 	}
 
 	fn tick_connected(&mut self, inputs: &Inputs) -> Result<()> {
-		while let Some(msg) = self.conn.try_recv() {
-			match msg? {
+		let conn = self.conn()?;
+		let msgs = iter::from_fn(|| conn.try_recv()).collect::<Result<Vec<_>>>()?;
+		for msg in msgs {
+			match msg {
 				ServerMsg::SwitchMap(map_switch) => {
-					let (state, zones) = Self::load_state(&self.eng, map_switch, self.state.player_id())?;
-					self.state = state;
-					self.zones = zones;
+					let player_id = self.download.as_ref().map(|(_, id)| *id).unwrap_or(self.state.player_id());
+					self.switch_map(map_switch, player_id)?
 				}
+				ServerMsg::MapDownload(msg) => self.handle_map_download(msg)?,
+				ServerMsg::Ping(seq) => self.conn()?.send(ClientMsg::Pong(seq))?,
+				_ if self.download.is_some() => (), // about the map we don't have yet
 				msg => self.state.apply_server_msg(&self.eng, msg),
 			}
 		}
 
 		let diffs = self.state.tick(&self.eng, inputs);
-		for diff in diffs {
-			self.conn.send(diff)?;
+		match &self.download {
+			// keep the old map around while downloading, but don't play on it.
+			Some((download, _)) => self.state.hud.set_text(HUDPos::Center, download.progress(), 1.0),
+			None => {
+				let conn = self.conn()?;
+				for diff in diffs {
+					conn.send(diff)?;
+				}
+			}
 		}
 
 		if self.dbg_overlay {
@@ -117,9 +232,98 @@ impl Client {
 		Ok(())
 	}
 
+	fn conn(&mut self) -> Result<&mut Conn> {
+		self.conn.as_mut().map_err(|e| anyhow!("{e}"))
+	}
+
+	// Load the map the server switched to, or start downloading it if we don't have it.
+	fn switch_map(&mut self, map_switch: MapSwitch, player_id: ID) -> Result<()> {
+		self.download = None;
+		match find_map_dir(&self.eng.assets, &map_switch) {
+			Some(map_dir) => self.load_map(&map_dir, map_switch, player_id),
+			None => {
+				let (download, request) = MapDownload::start(&self.eng.assets, map_switch)?;
+				self.conn()?.send(request)?;
+				self.download = Some((download, player_id));
+				Ok(())
+			}
+		}
+	}
+
+	fn handle_map_download(&mut self, msg: MapDownloadMsg) -> Result<()> {
+		let done = match &mut self.download {
+			Some((download, _)) => download.handle(msg)?,
+			None => return Ok(()), // leftover from a download we no longer need
+		};
+		if done {
+			let (download, player_id) = self.download.take().unwrap(/*just checked*/);
+			LOG.write(format!("downloaded map {}", download.map_switch.map_name));
+			self.load_map(&download.dir, download.map_switch, player_id)?;
+		}
+		Ok(())
+	}
+
+	fn load_map(&mut self, map_dir: &MapDir, map_switch: MapSwitch, player_id: ID) -> Result<()> {
+		let (mut state, zones) = Self::load_state(&self.eng, map_dir, map_switch, player_id)?;
+		state.replica.snapshots = mem::take(&mut self.state.replica.snapshots);
+		self.state = state;
+		self.zones = zones;
+		Ok(())
+	}
+
+	// Keep the game running locally while trying to reconnect in the background.
+	// Our session token lets the server give us back the same player (ID, team, score)
+	// if we're back within its grace period.
 	fn tick_disconnected(&mut self, inputs: &Inputs) {
-		//self.menu.show_info(format!("disconnected: {:?}", self.conn.error()))
-		// TODO: poll for reconnection here / drop to menu
+		let _ = self.state.tick(&self.eng, inputs); // nobody to send diffs to.
+
+		let attempt = self.reconnect.as_ref().map(|recv| recv.try_recv());
+		match attempt {
+			None if Instant::now() >= self.next_reconnect => self.spawn_reconnect(),
+			None | Some(Err(mpsc::TryRecvError::Empty)) => (),
+			Some(Ok(Ok((conn, accepted)))) => {
+				self.reconnect = None;
+				self.conn = Ok(conn);
+				if let Err(e) = self.handle_reconnected(accepted) {
+					self.conn = Err(anyhow!("{e:#}"));
+					self.reconnect_failed(e)
+				}
+			}
+			Some(Ok(Err(e))) => self.reconnect_failed(e),
+			Some(Err(mpsc::TryRecvError::Disconnected)) => self.reconnect_failed(anyhow!("reconnect thread died")),
+		}
+
+		if let Err(e) = &self.conn {
+			self.state.hud.set_text(HUDPos::Center, format!("disconnected: {e}\nreconnecting..."), 1.0);
+		}
+	}
+
+	// Connect in a background thread, so that an unresponsive server does not freeze the game.
+	fn spawn_reconnect(&mut self) {
+		let (send, recv) = channel();
+		let server = self.server.clone();
+		let transport = self.eng.network.transport;
+		let sim = self.eng.network.sim.clone();
+		let join_req = self.join_req.clone();
+		thread::spawn(move || {
+			let _ = send.send(Self::join(&server, transport, sim.as_ref(), join_req)); // error means we're no longer waiting for it
+		});
+		self.reconnect = Some(recv);
+	}
+
+	fn handle_reconnected(&mut self, accepted: AcceptedMsg) -> Result<()> {
+		self.state.replica.snapshots = default(); // the server starts over with full snapshots
+		self.switch_map(accepted.map_switch, accepted.player_id)?;
+		self.join_req.session = Some(accepted.session);
+		self.join_req.name = accepted.name;
+		LOG.write("reconnected");
+		Ok(())
+	}
+
+	fn reconnect_failed(&mut self, e: Error) {
+		LOG.write(format!("reconnect: {e:#}"));
+		self.reconnect = None;
+		self.next_reconnect = Instant::now() + RECONNECT_INTERVAL;
 	}
 
 	//--------------------------------------------------------------------------------  draw
@@ -142,12 +346,12 @@ impl Client {
 	// TODO: in client
 	fn fmt_dbg_overlay(&self) -> String {
 		let state = &self.state;
-		let world = &state.world;
+		let world = state.world();
 		let player = state.local_player();
 		let spawned = player.spawned;
 		let position = player.skeleton.position;
 		let look_dir = player.skeleton.orientation.look_dir();
-		let on_ground = player.skeleton.on_ground(&self.state.world);
+		let on_ground = player.skeleton.on_ground(self.state.world());
 		let effects = world.entities.effects.len();
 
 		format!(
@@ -159,7 +363,7 @@ on_ground: {on_ground}
 others: {}
 effects: {effects}
 "#,
-			pretty(&self.state.world.entities.players.iter().map(|(i, p)| (i, p.position())).collect::<Vec<_>>()),
+			pretty(&self.state.world().entities.players.iter().map(|(i, p)| (i, p.position())).collect::<Vec<_>>()),
 		)
 	}
 
@@ -7,20 +7,23 @@ use super::internal::*;
 /// (see ServerState).
 pub struct ClientState {
 	assets: AssetsDir,
-	pub local_player_id: ID,
-	pub world: World,
+	pub replica: Replica, // our copy of the world
 	pub hud: HUD,
-	pub pending_diffs: Vec<ClientMsg>,
+
+	// Only used if the local player is a spectator.
+	spectator_cam: SpectatorCam,
+
+	muted: Set<ID>, // don't show their chat (`mute` command)
 }
 
 impl ClientState {
 	pub fn new(ctx: &GameCtx, player_id: ID, world: World) -> Self {
 		Self {
 			assets: ctx.assets.clone(),
-			local_player_id: player_id,
-			world,
+			replica: Replica::new(player_id, world, ctx.network.interpolation_delay_ms as f64 / 1000.0),
 			hud: HUD::new(&ctx.graphics),
-			pending_diffs: default(),
+			spectator_cam: default(),
+			muted: default(),
 		}
 	}
 
@@ -30,20 +33,12 @@ impl ClientState {
 	pub fn apply_server_msg(&mut self, ctx: &GameCtx, msg: ServerMsg) {
 		use ServerMsg::*;
 		match msg {
-			AddPlayer(player) => self.handle_add_player(player),
-			MovePlayer(player_id, frame) => self.handle_move_player(player_id, frame),
-			UpdatePlayerPartial(player) => self.handle_update_player_partial(player),
-			UpdatePlayerFull(player) => self.handle_update_player_full(player),
-			ForceMovePlayer(position) => self.handle_force_move_player(position),
-			//UpdateEntity(entity) => self.handle_update_entity(entity),
-			//RemoveEntity(entity_id) => self.handle_remove_entity(entity_id),
-			DropPlayer(player_id) => self.handle_drop_player(player_id),
-			AddEffect(effect) => self.handle_add_effect(effect),
 			PlaySound(sound_effect) => self.play_sound(ctx, &sound_effect),
-			//RequestRespawn(spawn_point) => self.handle_request_respawn(spawn_point),
 			UpdateHUD(update) => self.handle_update_hud(update),
-			SwitchMap(_) => panic!("TODO: SwitchMap currently handled by Client"), //,self.handle_switch_map(map_switch),
+			Chat(chat) => self.handle_chat(chat),
 			Log(msg) => LOG.write(msg),
+			//SwitchMap(map_switch) => self.handle_switch_map(map_switch),
+			msg => self.replica.apply_server_msg(msg),
 		}
 	}
 
@@ -54,65 +49,22 @@ impl ClientState {
 	//	self.world.entities = Entities::default().with(|e| e.players = self.world.entities.players.clone());
 	//}
 
-	fn handle_add_player(&mut self, player: Player) {
-		self.world.entities.players.insert(player.id, player);
-	}
-
-	fn handle_move_player(&mut self, player_id: ID, frame: Frame) {
-		if let Some(p) = self.world.entities.players.get_mut(player_id) {
-			p.skeleton.set_frame(frame)
-		} else {
-			eprintln!("client_state: handle_move_player: player {} does not exist", player_id);
-		}
-	}
-
-	// Update part of player state controlled by server: everything except frame.
-	// Server sends frame anyway (for simplicity), but this is ignored here.
-	// TODO: something like bevy_reflect would allow more fine-grained updates.
-	fn handle_update_player_partial(&mut self, new: Player) {
-		if let Some(old) = self.world.entities.players.get_mut(new.id) {
-			debug_assert!(old.spawned);
-			let mut new = new;
-			new.local = old.local.clone();
-			new.skeleton.set_frame(old.skeleton.frame());
-			*old = new;
-		}
-	}
-
-	// Update the entire player (including frame).
-	// Server will only ask this when de-spawned.
-	fn handle_update_player_full(&mut self, new: Player) {
-		if let Some(player_mut) = self.world.entities.players.get_mut(new.id) {
-			debug_assert!(!player_mut.spawned);
-			*player_mut = new
-		}
-	}
-
-	fn handle_force_move_player(&mut self, position: vec3) {
-		self.local_player_mut().skeleton.position = position;
-	}
-
-	//fn handle_update_entity(&mut self, entity: Entity) {
-	//	self.world.entities.insert(entity.id(), entity);
-	//}
-
-	//fn handle_remove_entity(&mut self, entity_id: EID) {
-	//	self.world.entities.remove(&entity_id);
-	//}
-
-	fn handle_drop_player(&mut self, player_id: ID) {
-		LOG.write("dropping player {player_id}");
-		self.world.entities.players.remove(player_id);
-	}
-
 	// fn handle_request_respawn(&mut self, spawn_point: SpawnPoint) {
 	// 	self.local_player_mut().next_spawn_point = spawn_point.position();
 	// 	self.local_player_mut().skeleton.velocity = vec3::ZERO;
 	// 	self.local_player_mut().skeleton.orientation.pitch = 0.0;
 	// }
 
-	fn handle_add_effect(&mut self, effect: Effect) {
-		self.world.entities.effects.push(effect);
+	// Show chat in the sender's team color (white for the server).
+	fn handle_chat(&mut self, chat: ChatMsg) {
+		if chat.sender.map(|id| self.muted.contains(&id)).unwrap_or(false) {
+			return;
+		}
+		let players = &self.replica.world.entities.players;
+		let line = chat.format(players);
+		let color = chat.sender.and_then(|id| players.get(id)).map(|p| p.team.color_filter()).unwrap_or(vec3::ONES);
+		LOG.write(line.clone());
+		self.hud.chat(line, color);
 	}
 
 	fn handle_update_hud(&mut self, upd: HUDUpdate) {
@@ -140,7 +92,7 @@ impl ClientState {
 		const UNIT_DIST: f32 = 40.0;
 
 		let player = self.local_player();
-		let ear_pos = self.local_player().camera().position;
+		let ear_pos = self.camera().position;
 		let sound_pos = spatial.location;
 		if (ear_pos - sound_pos).len() < 8.0 {
 			// spatial audio does not work / is pointless when sound location is at or very near player location
@@ -160,7 +112,7 @@ impl ClientState {
 		let dir = (pos2 - pos1).normalized();
 		let len = (pos2 - pos1).len();
 		let ray = Ray64::new(pos1.into(), dir.into());
-		let t = self.world.map.intersect_t(&ray).unwrap_or(f64::INFINITY) as f32;
+		let t = self.replica.world.map.intersect_t(&ray).unwrap_or(f64::INFINITY) as f32;
 		t < len
 	}
 
@@ -169,13 +121,17 @@ impl ClientState {
 	#[must_use]
 	pub fn tick(&mut self, ctx: &GameCtx, inputs: &Inputs) -> ClientMsgs {
 		let dt = inputs.dt();
-		self.control_player(inputs, dt);
-		self.extrapolate_other_players(dt);
+		self.replica.tick(inputs);
+		if self.local_player().spectator {
+			self.control_spectator(inputs, dt);
+		}
 		self.animate_footsteps(ctx, dt);
-		self.tick_effects(dt);
+		let player = self.replica.local_player();
+		self.hud.set_health((player.spawned && !player.spectator).then_some(player.health));
+		self.hud.set_weapon(&self.replica.world.weapons.get(player.weapon).name);
 		self.hud.tick(dt);
 
-		let diff = mem::take(&mut self.pending_diffs);
+		let diff = mem::take(&mut self.replica.pending_diffs);
 		self.apply_self_msgs(ctx, &diff);
 		diff
 	}
@@ -193,46 +149,42 @@ impl ClientState {
 		for msg in msgs {
 			match msg {
 				MovePlayerIfSpawned { .. } => (/*already applied locally by control*/),
-				AddEffect(effect) => self.handle_add_effect(effect.clone()),
+				AddEffect(effect) => self.replica.handle_add_effect(effect.clone()),
 				PlaySound(sound) => self.play_sound(ctx, sound),
 				HitPlayer { .. } => (/* handled by server*/),
+				SwitchWeapon(_) => (/*already applied locally by control*/),
+				FireProjectile(_) => (/*simulated by server*/),
 				ReadyToSpawn => (/*handled by server*/),
 				Command(_) => (/*handled by server*/),
+				AckSnapshot(_) => (/*handled by server*/),
+				RequestMap { .. } => (/*handled by server*/),
+				Pong(_) => (/*handled by server*/),
 			}
 		}
 	}
 
-	/// Control a player via keyboard/mouse
-	fn control_player(&mut self, input_state: &Inputs, dt: f32) {
+	/// Spectators only move their camera, nothing is sent to the server.
+	fn control_spectator(&mut self, input_state: &Inputs, dt: f32) {
 		let mut clone = self.local_player().clone();
-		clone.control(&mut self.pending_diffs, input_state, &self.world, dt);
+		self.spectator_cam.control(&mut clone, input_state, &self.replica.world, dt);
 		*self.local_player_mut() = clone;
-	}
-
-	/// Extrapolate other player's positions based on their last know velocity.
-	/// This greatly reduces positional stutter in the face of network latency.
-	fn extrapolate_other_players(&mut self, dt: f32) {
-		for (id, player) in self.world.entities.players.iter_mut() {
-			if id != self.local_player_id {
-				player.skeleton.position += dt * player.skeleton.velocity;
-			}
-		}
+		self.hud.set_text(HUDPos::BottomLeft, self.spectator_cam.describe(&self.replica.world), 1.0);
 	}
 
 	/// Animate the players feet if they are moving.
 	/// This is done locally by each client (do not send feet position over the network all the time).
 	/// Also generate footstep, jump,... sounds locally (do not send these sound effects over the network).
 	fn animate_footsteps(&mut self, ctx: &GameCtx, dt: f32) {
-		for player_id in self.world.entities.players.copied_ids() {
-			let prev = &self.world.entities.players[player_id].local.clone();
-			self.world.entities.players[player_id].animate_feet(dt);
-			let curr = &self.world.entities.players[player_id].local;
+		for player_id in self.replica.world.entities.players.copied_ids() {
+			let prev = &self.replica.world.entities.players[player_id].local.clone();
+			self.replica.world.entities.players[player_id].animate_feet(dt);
+			let curr = &self.replica.world.entities.players[player_id].local;
 			self.make_footstep_sounds(ctx, player_id, prev, curr);
 		}
 	}
 
 	fn make_footstep_sounds(&self, ctx: &GameCtx, player_id: ID, prev: &LocalState, curr: &LocalState) {
-		let speed = self.world.entities.players[player_id].skeleton.velocity;
+		let speed = self.replica.world.entities.players[player_id].skeleton.velocity;
 		let vspeed = speed.y();
 		let walking = { vspeed.abs() < 0.1 && speed != vec3::ZERO };
 
@@ -240,13 +192,13 @@ impl ClientState {
 			if prev.feet_phase.signum() != curr.feet_phase.signum() {
 				// make one's own footsteps less loud
 				// (quite distracting otherwise)
-				let volume = if player_id == self.local_player_id { 0.01 } else { 0.3 };
+				let volume = if player_id == self.replica.local_player_id { 0.01 } else { 0.3 };
 				self.play_sound_spatial(
 					ctx,
 					Self::random_footstep_clip(),
 					volume,
 					&Spatial {
-						location: self.world.entities.players[player_id].position(),
+						location: self.replica.world.entities.players[player_id].position(),
 					},
 				)
 			}
@@ -271,54 +223,63 @@ impl ClientState {
 		])
 	}
 
-	//___________________________________________________________________________ effects
-
-	/// Advance visual effects in time.
-	/// This is done locally (after creation,
-	/// visual effects do not need to synchronize over the network).
-	fn tick_effects(&mut self, dt: f32) {
-		Self::update_effects_ttl(&mut self.world.entities.effects, dt);
-	}
-
-	// decrease effect's TTL by `dt` and remove effects past their TTL.
-	fn update_effects_ttl(effects: &mut Vec<Effect>, dt: f32) {
-		let mut i = 0;
-		while i < effects.len() {
-			effects[i].ttl -= dt;
-			if effects[i].ttl <= 0.0 {
-				effects.swap_remove(i);
-			} else {
-				i += 1;
-			}
-		}
-	}
-
 	// -------------------------------------------------------------------------------- commands
 
 	pub fn command(&mut self, cmd: &str) -> Result<()> {
 		Ok(match &cmd.split_ascii_whitespace().collect::<Vec<_>>()[..] {
 			["hello"] => (),
-			_ => self.pending_diffs.push(ClientMsg::Command(cmd.into())),
+			["mute", name] => {
+				self.muted.insert(self.find_player(name)?);
+				LOG.write(format!("muted {name}"))
+			}
+			["unmute", name] => {
+				self.muted.remove(&self.find_player(name)?);
+				LOG.write(format!("unmuted {name}"))
+			}
+			_ => self.replica.pending_diffs.push(ClientMsg::Command(cmd.into())),
 		})
 	}
 
+	fn find_player(&self, name: &str) -> Result<ID> {
+		let players = &self.replica.world.entities.players;
+		players
+			.iter()
+			.find(|(_, p)| p.name.eq_ignore_ascii_case(name))
+			.map(|(id, _)| id)
+			.ok_or(anyhow!("no such player: {name}"))
+	}
+
 	// __________________________________________________________ accessors
 
 	pub fn world(&self) -> &World {
-		&self.world
+		&self.replica.world
 	}
 
 	/// The player controlled by this client.
 	pub fn local_player(&self) -> &Player {
-		&self.world.entities.players[self.local_player_id]
+		self.replica.local_player()
 	}
 
 	pub fn local_player_mut(&mut self) -> &mut Player {
-		&mut self.world.entities.players[self.local_player_id]
+		self.replica.local_player_mut()
+	}
+
+	/// The player whose point of view we see:
+	/// the local player, or the player followed by a spectator.
+	pub fn camera_player_id(&self) -> ID {
+		match self.local_player().spectator {
+			true => self.spectator_cam.player_id(self.replica.local_player_id),
+			false => self.replica.local_player_id,
+		}
+	}
+
+	pub fn camera(&self) -> Camera {
+		let id = self.camera_player_id();
+		self.replica.world.entities.players.get(id).unwrap_or(self.local_player()).camera()
 	}
 
 	pub fn player_id(&self) -> ID {
-		self.local_player_id
+		self.replica.local_player_id
 	}
 
 	pub fn hud(&self) -> &HUD {
@@ -0,0 +1,123 @@
+use super::internal::*;
+use std::io::BufRead;
+
+/// Command lines typed by the server operator, on stdin or through a local Unix socket
+/// (see `ServerOpts::console_socket`). Polled by `NetServer`, which runs them with full rights
+/// (see `ServerState::handle_console_command`), so that the server can be run under tmux or systemd.
+///
+/// E.g.: `socat - UNIX-CONNECT:server.sock`, then `players`, `kick bob`, `quit`.
+pub struct Console {
+	recv: Receiver<ConsoleCmd>,
+}
+
+/// A command line, and where to send the answer.
+pub struct ConsoleCmd {
+	pub line: String,
+	reply: Option<Sender<String>>, // None: stdout
+}
+
+impl Console {
+	/// Read commands from stdin (if `stdin`), and from `socket` if given.
+	pub fn spawn(stdin: bool, socket: Option<&str>) -> Result<Self> {
+		let (send, recv) = channel();
+		if stdin {
+			Self::spawn_stdin(send.clone());
+		}
+		if let Some(path) = socket {
+			Self::spawn_socket(path, send)?;
+		}
+		Ok(Self { recv })
+	}
+
+	/// Commands typed since the last call (non-blocking).
+	pub fn try_iter(&self) -> impl Iterator<Item = ConsoleCmd> + '_ {
+		self.recv.try_iter()
+	}
+
+	// Under systemd, stdin is usually /dev/null: the thread just ends.
+	fn spawn_stdin(send: Sender<ConsoleCmd>) {
+		thread::spawn(move || {
+			for line in std::io::stdin().lock().lines().map_while(|line| line.ok()) {
+				if send.send(ConsoleCmd { line, reply: None }).is_err() {
+					return; // server quit
+				}
+			}
+		});
+	}
+
+	#[cfg(unix)]
+	fn spawn_socket(path: &str, send: Sender<ConsoleCmd>) -> Result<()> {
+		use std::os::unix::fs::FileTypeExt;
+		// left behind by a previous run (a socket file cannot be re-bound).
+		// Anything else at that path is not ours to delete.
+		match std::fs::symlink_metadata(path) {
+			Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path).map_err(|e| anyhow!("console socket {path}: {e}"))?,
+			Ok(_) => return Err(anyhow!("console socket {path}: file exists and is not a socket")),
+			Err(_) => (),
+		}
+		let listener = Self::bind_private(path).map_err(|e| anyhow!("console socket {path}: {e}"))?;
+		info!("console on {path}");
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				match stream {
+					Err(e) => error!("console: accept: {e}"),
+					Ok(stream) => {
+						let send = send.clone();
+						thread::spawn(move || {
+							if let Err(e) = Self::serve_socket(stream, send) {
+								info!("console: {e}")
+							}
+						});
+					}
+				}
+			}
+		});
+		Ok(())
+	}
+
+	// The console has full rights over the server: only our own user may connect (mode 0600).
+	// Bound in a private directory and only then moved into place, so that nobody can connect in between.
+	#[cfg(unix)]
+	fn bind_private(path: &str) -> Result<std::os::unix::net::UnixListener> {
+		use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+		let dir = PathBuf::from(format!("{path}.{}.tmp", std::process::id()));
+		std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+		let tmp = dir.join("sock");
+		let bound = std::os::unix::net::UnixListener::bind(&tmp).and_then(|listener| {
+			std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600))?;
+			std::fs::rename(&tmp, path)?;
+			Ok(listener)
+		});
+		let _ = std::fs::remove_file(&tmp);
+		let _ = std::fs::remove_dir(&dir);
+		Ok(bound?)
+	}
+
+	#[cfg(not(unix))]
+	fn spawn_socket(path: &str, _send: Sender<ConsoleCmd>) -> Result<()> {
+		Err(anyhow!("console socket {path}: only supported on Unix"))
+	}
+
+	// Answer one command line at a time, until the operator hangs up.
+	#[cfg(unix)]
+	fn serve_socket(stream: std::os::unix::net::UnixStream, send: Sender<ConsoleCmd>) -> Result<()> {
+		let mut out = stream.try_clone()?;
+		for line in BufReader::new(stream).lines() {
+			let (reply, answer) = channel();
+			send.send(ConsoleCmd { line: line?, reply: Some(reply) })?;
+			writeln!(out, "{}", answer.recv()?)?;
+		}
+		Ok(())
+	}
+}
+
+impl ConsoleCmd {
+	pub fn reply(self, answer: String) {
+		match self.reply {
+			None => println!("{answer}"),
+			Some(reply) => {
+				let _ = reply.send(answer); // operator may have hung up.
+			}
+		}
+	}
+}
@@ -3,6 +3,9 @@ use ServerMsg::*;
 
 // World that can only be mutated if diffs are recored.
 // (Typestate pattern to avoid accidental mutation without recording diff).
+//
+// Changes to players are not recorded here: they reach clients through per-tick snapshots
+// (see `ServerData::push_snapshots`). Only teleports need an explicit diff (`force_apply_to_full`).
 pub struct DiffWorld(World);
 
 impl DiffWorld {
@@ -10,55 +13,83 @@ impl DiffWorld {
 		Self(world)
 	}
 
-	pub fn apply_to_player_partial<F: Fn(&mut Player)>(&mut self, diffs: &mut Diffs, player_id: ID, f: F) -> Option<()> {
-		self.0.entities.players.get_mut(player_id).and_then(|player| {
-			Some({
-				f(player);
-				diffs.push(UpdatePlayerPartial(player.clone()).to_all());
-			})
-		})
+	pub fn apply_to_player_partial<F: Fn(&mut Player)>(&mut self, player_id: ID, f: F) -> Option<()> {
+		self.0.entities.players.get_mut(player_id).map(f)
 	}
 
 	pub fn force_apply_to_full<F: Fn(&mut Player)>(&mut self, diffs: &mut Diffs, player_id: ID, f: F) -> Option<()> {
 		self.0.entities.players.get_mut(player_id).and_then(|player| {
 			Some({
 				f(player);
-				diffs.push(UpdatePlayerFull(player.clone()).to_all());
+				// only to those who can see where the player is now: the others learn about the change
+				// through snapshots, which keep far-away players' positions from them (see `ServerData::view_of`).
+				diffs.push(UpdatePlayerFull(player.clone()).to(Addressee::Near(Place::point(player.center()), None)));
 			})
 		})
 	}
 
-	pub fn move_player_if_spawned(&mut self, diffs: &mut Diffs, player_id: ID, frame: Frame) {
+	pub fn move_player_if_spawned(&mut self, history: &mut FrameHistory, time: f64, player_id: ID, frame: Frame) {
 		if let Some(player) = self.0.entities.players.get_mut(player_id) {
 			if player.spawned {
 				player.skeleton.set_frame(frame);
-				diffs.push(MovePlayer(player_id, player.skeleton.frame()).to_not(player_id));
+				history.record(player_id, time, player.skeleton.frame());
 			}
 		}
 	}
 
-	pub fn join_new_player(&mut self, diffs: &mut Diffs, spawn_point: &SpawnPoint, join_msg: JoinRequest) -> (ID, MapSwitch) {
+	pub fn join_new_player(&mut self, spawn_point: &SpawnPoint, join_msg: JoinRequest, team: Team, map_hash: u64) -> (ID, MapSwitch) {
 		// Join new player cannot be done via apply(msg):
 		// we need to add the player before we can get the player ID.
 
-		let player_id = self.0.entities.join_new_player(&spawn_point, join_msg);
-		let player = self.entities.players[player_id].clone();
-		diffs.push(AddPlayer(player).to_all());
+		let player_id = self.0.entities.join_new_player(&spawn_point, join_msg, team);
 
 		let map_switch = MapSwitch {
 			map_name: self.map.name().into(),
+			map_hash,
 			entities: self.entities.clone(),
 		};
 
 		(player_id, map_switch)
 	}
 
-	pub fn drop_player(&mut self, diffs: &mut Diffs, player_id: ID) {
+	/// Put back a player that was previously dropped (see `GameLogic::resume_player`).
+	pub fn rejoin_player(&mut self, player: Player, map_hash: u64) -> MapSwitch {
+		self.0.entities.players.insert(player.id, player);
+		MapSwitch {
+			map_name: self.map.name().into(),
+			map_hash,
+			entities: self.entities.clone(),
+		}
+	}
+
+	pub fn drop_player(&mut self, player_id: ID) {
 		self.0.entities.players.remove(player_id);
-		diffs.push(DropPlayer(player_id).to_not(player_id));
 	}
 
-	pub fn switch_map(&mut self, diffs: &mut Diffs, new_map: Map) {
+	/// Add a projectile, tell clients that can see where it was fired so they can extrapolate its flight.
+	pub fn add_projectile(&mut self, diffs: &mut Diffs, projectile: Projectile) -> ID {
+		let id = self.0.entities.add_projectile(projectile.clone());
+		diffs.push(AddProjectile(id, projectile.clone()).to(Addressee::Near(projectile.place(), None)));
+		id
+	}
+
+	/// Move all projectiles by `dt`. Returns those that exploded (but does not remove them).
+	/// Flight does not need to be diffed: clients extrapolate it the same way.
+	pub fn tick_projectiles(&mut self, dt: f32) -> Vec<(ID, Impact)> {
+		let mut projectiles = mem::take(&mut self.0.entities.projectiles);
+		let impacts = projectiles.iter_mut().filter_map(|(&id, p)| p.tick(&self.0, dt, true).map(|impact| (id, impact))).collect();
+		self.0.entities.projectiles = projectiles;
+		impacts
+	}
+
+	pub fn remove_projectile(&mut self, diffs: &mut Diffs, id: ID) -> Option<Projectile> {
+		let projectile = self.0.entities.projectiles.remove(&id)?;
+		// to all: it may have flown out of view of clients that saw it fired (unknown IDs are ignored).
+		diffs.push(RemoveProjectile(id).to_all());
+		Some(projectile)
+	}
+
+	pub fn switch_map(&mut self, diffs: &mut Diffs, new_map: Map, map_hash: u64) {
 		trace!("mapswitch {}", new_map.name());
 
 		let mut tmp = new_map;
@@ -72,6 +103,7 @@ impl DiffWorld {
 		diffs.push(
 			SwitchMap(MapSwitch {
 				map_name: self.0.map.name().into(),
+				map_hash,
 				entities: self.0.entities.clone(),
 			})
 			.to_all(),
@@ -8,6 +8,10 @@ impl Diffs {
 		self.0.push(msg)
 	}
 
+	pub fn push_front(&mut self, msg: Envelope<ServerMsg>) {
+		self.0.insert(0, msg)
+	}
+
 	pub fn into_iter(self) -> impl Iterator<Item = Envelope<ServerMsg>> {
 		self.0.into_iter()
 	}
@@ -10,19 +10,18 @@ impl Default for DrawCfg {
 
 impl DrawCfg {
 	pub fn draw_gamestate(&self, eng: &GameCtx, zones: &ZoneGraph, viewport_size: uvec2, state: &ClientState) -> SceneGraph {
-		let player = state.local_player();
-		let world = &state.world;
+		let world = state.world();
 		let map = world.map.data();
 		let hud = &state.hud;
 
 		let mut sg = SceneGraph::new(viewport_size).with(|sg| {
-			sg.camera = player.camera();
+			sg.camera = state.camera();
 			sg.bg_color = map.meta.sky_color;
-			sg.sun_dir = state.world.map.data().meta.sun_dir;
-			sg.sun_color = state.world.map.data().meta.sun_color;
+			sg.sun_dir = state.world().map.data().meta.sun_dir;
+			sg.sun_color = state.world().map.data().meta.sun_color;
 		});
 
-		self.draw_world(eng, &mut sg, zones, world, state.local_player_id);
+		self.draw_world(eng, &mut sg, zones, world, state.camera_player_id());
 
 		hud.draw_on(&mut sg); // TODO: editor does not use resources :(
 
@@ -51,6 +50,8 @@ impl DrawCfg {
 				len,
 				color_filter,
 			} => self.draw_particle_beam(sg, rs, start, orientation, len, color_filter, effect.ttl),
+			EffectType::ProjectileTrail { start, orientation, len, .. } => sg.push(rs.effects.particle_beam(start, orientation, len, 1.0 - (effect.ttl / PROJECTILE_TRAIL_TTL))),
+			EffectType::Explosion { pos, radius, .. } => sg.push(rs.effects.explosion(pos, radius, 1.0 - (effect.ttl / EXPLOSION_TTL))),
 		};
 	}
 
@@ -14,12 +14,16 @@ pub enum EffectType {
 	//LaserBeam { start: vec3, orientation: Orientation, len: f32 },
 	ParticleExplosion { pos: vec3, color: vec3 },
 	ParticleBeam { start: vec3, orientation: Orientation, len: f32, color_filter: vec3 },
+	ProjectileTrail { start: vec3, orientation: Orientation, len: f32, color: vec3 },
+	Explosion { pos: vec3, radius: f32, color: vec3 },
 	//Respawn { pos: vec3 },
 }
 
 //pub const LASERBEAM_TTL: f32 = 0.25; // seconds
 pub const PARTICLE_BEAM_TTL: f32 = 0.4; // seconds
 pub const PARTICLE_EXPLOSION_TTL: f32 = 1.2; // seconds
+pub const PROJECTILE_TRAIL_TTL: f32 = 0.6; // seconds
+pub const EXPLOSION_TTL: f32 = 0.8; // seconds
 pub const RESPAWN_TTL: f32 = 1.5; // seconds
 
 impl Effect {
@@ -44,6 +48,44 @@ impl Effect {
 		}
 	}
 
+	/// Where the effect is seen (see `Addressee::Near`).
+	pub fn place(&self) -> Place {
+		match self.typ {
+			EffectType::ParticleExplosion { pos, .. } => Place::point(pos),
+			EffectType::ParticleBeam { start, orientation, len, .. } => Place::segment(start, start + len * orientation.look_dir()),
+			EffectType::ProjectileTrail { start, orientation, len, .. } => Place::segment(start, start + len * orientation.look_dir()),
+			EffectType::Explosion { pos, .. } => Place::point(pos),
+		}
+	}
+
+	/// Smoke left behind by a projectile flying from `start` to `end`
+	/// (added locally by each client, see `Replica::tick_projectiles`).
+	pub fn projectile_trail(start: vec3, end: vec3, color: vec3) -> Self {
+		let delta = end - start;
+		let len = delta.len();
+		let dir = if len > 0.0 { delta / len } else { vec3::EY };
+		Self {
+			ttl: PROJECTILE_TRAIL_TTL,
+			typ: EffectType::ProjectileTrail {
+				start,
+				orientation: Orientation {
+					yaw: f32::atan2(dir.x(), dir.z()),
+					pitch: -f32::asin(dir.y()),
+				},
+				len,
+				color,
+			},
+		}
+	}
+
+	/// A projectile exploding, sized to its splash damage radius.
+	pub fn explosion(pos: vec3, radius: f32, color: vec3) -> Self {
+		Self {
+			ttl: EXPLOSION_TTL,
+			typ: EffectType::Explosion { pos, radius, color },
+		}
+	}
+
 	pub fn particle_beam(start: vec3, orientation: Orientation, len: f32, color_filter: vec3) -> Self {
 		Self {
 			ttl: PARTICLE_BEAM_TTL,
@@ -1,7 +1,7 @@
 use super::internal::*;
 use std::sync::atomic::AtomicUsize;
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize, Hash, PartialEq, Eq, Default)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, Hash, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub struct ID(usize);
 
 static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
@@ -21,16 +21,23 @@ pub struct Entities {
 	pub players: Players,
 	//pub entities: HashMap<EID, Entity>, // TODO: struct Entities. fn insert(Entity), etc.
 	pub effects: Vec<Effect>,
+	pub projectiles: HashMap<ID, Projectile>,
 }
 
 impl Entities {
-	pub fn join_new_player(&mut self, spawn_point: &SpawnPoint, req: JoinRequest) -> ID {
+	pub fn join_new_player(&mut self, spawn_point: &SpawnPoint, req: JoinRequest, team: Team) -> ID {
 		let player_id = self.new_id();
-		let player = Player::new(player_id, spawn_point.position(), spawn_point.orientation(), req.name, req.avatar_id, req.team);
+		let player = Player::new(player_id, spawn_point.position(), spawn_point.orientation(), req.name, req.avatar_id, team).with(|p| p.spectator = req.spectator);
 		self.players.insert(player_id, player);
 		player_id
 	}
 
+	pub fn add_projectile(&mut self, projectile: Projectile) -> ID {
+		let id = self.new_id();
+		self.projectiles.insert(id, projectile);
+		id
+	}
+
 	// A fresh, unique entity number.
 	fn new_id(&mut self) -> ID {
 		//self._next_id.0 += 1;
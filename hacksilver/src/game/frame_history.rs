@@ -0,0 +1,106 @@
+use super::internal::*;
+use std::collections::VecDeque;
+
+/// Ring buffer of recent player `Frame`s, kept by the server
+/// so that hits can be checked against where the shooter saw their victim
+/// (lag compensation, see `GameLogic::handle_hit_player`).
+///
+/// Also kept by clients, to smoothly interpolate other players' movement
+/// (see `ClientState::interpolate_other_players`).
+#[derive(Default)]
+pub struct FrameHistory {
+	by_player: HashMap<ID, VecDeque<(f64, Frame)>>,
+}
+
+/// Seconds of history kept per player.
+/// Hits claimed to be seen further back in the past are rejected.
+pub const MAX_REWIND: f64 = 0.5;
+
+impl FrameHistory {
+	/// Record a player's frame at server time `time`.
+	/// Multiple frames recorded during the same tick overwrite each other.
+	pub fn record(&mut self, player_id: ID, time: f64, frame: Frame) {
+		let frames = self.by_player.entry(player_id).or_default();
+
+		match frames.back_mut() {
+			Some((t, last)) if *t == time => *last = frame,
+			_ => frames.push_back((time, frame)),
+		}
+
+		// keep at least one frame older than MAX_REWIND, so we can interpolate up to the limit.
+		while frames.len() > 2 && frames[1].0 < time - MAX_REWIND {
+			frames.pop_front();
+		}
+	}
+
+	/// Forget a player's history.
+	/// E.g. after a teleport (respawn), so we don't interpolate between the old and new position.
+	pub fn clear(&mut self, player_id: ID) {
+		self.by_player.remove(&player_id);
+	}
+
+	/// Where a player was at server time `time`,
+	/// interpolated between the recorded frames around that time.
+	/// Clamps to the oldest/newest frame if `time` is out of range.
+	pub fn frame_at(&self, player_id: ID, time: f64) -> Option<Frame> {
+		let frames = self.by_player.get(&player_id)?;
+
+		let (t0, f0) = frames.front()?;
+		if time <= *t0 {
+			return Some(f0.clone());
+		}
+
+		for ((t1, f1), (t2, f2)) in frames.iter().zip(frames.iter().skip(1)) {
+			if time >= *t1 && time <= *t2 {
+				let t = ((time - t1) / (t2 - t1)) as f32;
+				return Some(Frame::lerp(f1, f2, t));
+			}
+		}
+
+		frames.back().map(|(_, f)| f.clone())
+	}
+
+	/// The most recently recorded frame, and its time.
+	pub fn latest(&self, player_id: ID) -> Option<(f64, &Frame)> {
+		self.by_player.get(&player_id)?.back().map(|(t, f)| (*t, f))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn frame_at_x(x: f32) -> Frame {
+		Frame {
+			position: vec3(x, 0.0, 0.0),
+			velocity: vec3::ZERO,
+			orientation: default(),
+		}
+	}
+
+	#[test]
+	fn frame_at() {
+		let id = ID::default();
+		let mut h = FrameHistory::default();
+		assert!(h.frame_at(id, 0.0).is_none());
+
+		h.record(id, 1.0, frame_at_x(10.0));
+		h.record(id, 1.1, frame_at_x(20.0));
+		h.record(id, 1.1, frame_at_x(30.0)); // same tick: overwrites
+
+		assert_eq!(h.frame_at(id, 0.5).unwrap().position, vec3(10.0, 0.0, 0.0)); // clamp to oldest
+		assert_eq!(h.frame_at(id, 2.0).unwrap().position, vec3(30.0, 0.0, 0.0)); // clamp to newest
+		assert!((h.frame_at(id, 1.05).unwrap().position.x() - 20.0).abs() < 1e-3);
+	}
+
+	#[test]
+	fn forget_old_frames() {
+		let id = ID::default();
+		let mut h = FrameHistory::default();
+		for i in 0..100 {
+			h.record(id, i as f64 * 0.1, frame_at_x(i as f32));
+		}
+		assert!(h.by_player[&id].len() < 10);
+		assert!((h.frame_at(id, 9.9 - MAX_REWIND).unwrap().position.x() - (99.0 - 5.0)).abs() < 1e-3);
+	}
+}
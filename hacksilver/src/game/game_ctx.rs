@@ -5,6 +5,7 @@ pub struct GameCtx {
 	pub graphics: Arc<GraphicsCtx>,
 	pub resources: ResourcePack,
 	pub sound_pack: SoundPack,
+	pub network: NetworkOpts,
 }
 
 impl GameCtx {
@@ -18,6 +19,7 @@ impl GameCtx {
 			resources,
 			graphics: ctx.clone(),
 			sound_pack,
+			network: settings.network,
 		})
 	}
 }
@@ -8,13 +8,43 @@ pub struct GameLogic {
 	curr_map: usize,
 	scores: Scores,
 	sprees: HashMap<ID, (f32, u32)>,
+	last_hit: HashMap<ID, (f64, f32, u32)>, // view time, weapon cooldown and pellets that hit, of each player's last shot that hit
+	last_fire: HashMap<ID, (f64, f32)>,     // server time and weapon cooldown of each player's last projectile
+	move_budgets: HashMap<ID, MoveBudget>,
+	move_violations: HashMap<ID, (u32, f64)>, // number of rejected moves, time of last correction
+	sessions: HashMap<SessionToken, ID>,
+	disconnected: HashMap<ID, (Player, f64)>, // dropped players, kept for reconnecting until the given server time
+	reconnect_grace: f64,
+	map_manifest: MapManifest, // of the current map
+	uploads: MapUploads,
 	frag_limit: i32,
 	time_playing: f32,
 	time_limit: f32,
+	max_players: u32,
+	team_balance: bool,
 }
 
 const SPREE_TIME: f32 = 3.0;
 
+// A shot may start this far from the shooter's center
+// (the gun nozzle or eye is about half a player height away).
+const MAX_SHOT_ORIGIN_DIST: f32 = Player::HEIGHT;
+
+// Allow successive hits slightly faster than the gun cooldown,
+// to account for jitter in the client's view time.
+const COOLDOWN_TOLERANCE: f64 = 0.8;
+
+// How far (seconds) a client's view time may run ahead of the server clock.
+const MAX_CLOCK_AHEAD: f64 = 0.1;
+
+// A pellet may fly this much (degrees) further off the shooter's look direction than the weapon's spread,
+// as the shooter may have turned a bit between the last move the server got and the shot.
+const AIM_TOLERANCE: f32 = 15.0;
+
+// Correct a player's position at most this often (seconds).
+// Moves sent before the client received the correction are rejected too, no need to correct those again.
+const CORRECTION_INTERVAL: f64 = 0.5;
+
 impl GameLogic {
 	pub fn new(assets: AssetsDir, settings: ServerOpts) -> Result<Self> {
 		println!("server: maplist: {}", settings.maplist.join(", "));
@@ -23,6 +53,7 @@ impl GameLogic {
 			_ => settings.maplist.clone(),
 		};
 		verify_maps(&assets, &maplist)?;
+		let map_manifest = MapManifest::scan(&assets.map_dir(&maplist[0]))?;
 
 		Ok(Self {
 			assets,
@@ -32,7 +63,18 @@ impl GameLogic {
 			frag_limit: settings.frag_limit as i32,
 			time_limit: settings.time_limit as f32,
 			time_playing: 0.0,
+			max_players: settings.max_players,
+			team_balance: settings.team_balance,
+			reconnect_grace: settings.reconnect_grace as f64,
 			sprees: default(),
+			last_hit: default(),
+			last_fire: default(),
+			move_budgets: default(),
+			move_violations: default(),
+			sessions: default(),
+			disconnected: default(),
+			map_manifest,
+			uploads: default(),
 		})
 	}
 
@@ -41,9 +83,31 @@ impl GameLogic {
 	pub fn tick(&mut self, data: &mut ServerData, dt: f32) {
 		self.tick_time_remaining(data, dt);
 		self.tick_next_game(data);
+		self.tick_invulnerability(data, dt);
+		self.tick_projectiles(data, dt);
 
 		self.tick_lava(data);
 		self.tick_killplane(data);
+		self.tick_disconnected(data);
+		self.tick_uploads(data);
+	}
+
+	// count down spawn kill protection.
+	fn tick_invulnerability(&mut self, data: &mut ServerData, dt: f32) {
+		for id in data.players().collect::<SmallVec<[ID; 16]>>() {
+			if data.player(id).map(|p| p.invulnerability_ttl.is_some()).unwrap_or(false) {
+				data.apply_to_player_partial(id, |p| p.invulnerability_ttl = p.invulnerability_ttl.map(|ttl| ttl - dt).filter(|&ttl| ttl > 0.0));
+			}
+		}
+	}
+
+	// fly projectiles, explode those that hit something.
+	fn tick_projectiles(&mut self, data: &mut ServerData, dt: f32) {
+		for (id, impact) in data.tick_projectiles(dt) {
+			if let Some(projectile) = data.remove_projectile(id) {
+				self.explode(data, &projectile, impact);
+			}
+		}
 	}
 
 	fn tick_time_remaining(&mut self, data: &mut ServerData, dt: f32) {
@@ -93,10 +157,11 @@ impl GameLogic {
 		for team in sorted_teams {
 			let _ = writeln!(&mut scores, "\n\nTeam {team}");
 			let _ = writeln!(&mut scores, "___________________________________________\n");
-			for id in data.players() {
+			for id in data.playing_players() {
 				if data.player(id).map(|p| p.team) == Some(team) {
 					let score = self.scores.by_player(id);
-					let _ = writeln!(&mut scores, "{:+20}: {:2} frags | {:2} deaths", data.must_name(id), score.frags, score.deaths);
+					let ping = data.ping(id).map(|rtt| format!("{:3} ms", rtt.as_millis())).unwrap_or("  ? ms".into());
+					let _ = writeln!(&mut scores, "{:+20}: {:2} frags | {:2} deaths | {ping}", data.must_name(id), score.frags, score.deaths);
 				}
 			}
 		}
@@ -151,24 +216,273 @@ impl GameLogic {
 		}
 	}
 
+	// forget disconnected players who did not reconnect in time.
+	fn tick_disconnected(&mut self, data: &ServerData) {
+		let now = data.time();
+		let expired = self.disconnected.iter().filter(|(_, (_, until))| *until <= now).map(|(&id, _)| id).collect::<SmallVec<[ID; 4]>>();
+		for id in expired {
+			info!("{id}: did not reconnect in time, forgetting");
+			self.disconnected.remove(&id);
+			self.sessions.retain(|_, session_id| *session_id != id);
+			self.scores.drop_player(id);
+		}
+	}
+
+	// send the next chunks of map downloads.
+	fn tick_uploads(&mut self, data: &mut ServerData) {
+		for (id, msg) in self.uploads.tick(&self.assets.map_dir(self.curr_map_name())) {
+			data.push_no_apply(MapDownload(msg).to_just(id));
+		}
+	}
+
+	//-------------------------------------------------------------------------------- movement
+
+	// Handle a client moving their player.
+	//
+	// Movement is simulated client-side for responsiveness,
+	// but we don't trust clients not to teleport, speed or walk through walls:
+	// each move is checked against the previous one.
+	// Implausible moves are dropped, and the client is corrected back to the last accepted position.
+	pub fn handle_move_player(&mut self, data: &mut ServerData, player_id: ID, frame: Frame) -> Option<()> {
+		if !data.player(player_id)?.spawned {
+			return None; // server has taken over control.
+		}
+
+		match self.validate_move(data, player_id, &frame) {
+			Ok(()) => data.move_player_if_spawned(player_id, frame),
+			Err(reason) => self.reject_move(data, player_id, reason),
+		}
+
+		Some(())
+	}
+
+	// Check that a client's move is plausible (see `handle_move_player`).
+	fn validate_move(&mut self, data: &ServerData, player_id: ID, frame: &Frame) -> Result<()> {
+		let player = data.player(player_id).ok_or(anyhow!("no such player"))?;
+		if !frame.position.is_finite() || !frame.velocity.is_finite() || !frame.orientation.yaw.is_finite() || !frame.orientation.pitch.is_finite() {
+			return Err(anyhow!("malformed frame"));
+		}
+
+		// walking through walls.
+		// Players may legitimately be partially inside a block while climbing stairs,
+		// or when already stuck (then the client pushes them up, see `Skeleton::tick_rescue`).
+		let skeleton = &player.skeleton;
+		let inside = |pos: vec3| !skeleton.pos_ok(&data.world, pos) && !skeleton.pos_ok(&data.world, pos + STAIR_HEIGHT * vec3::EY);
+		if inside(frame.position) && !inside(skeleton.position) {
+			return Err(anyhow!("moved into a wall at {}", frame.position));
+		}
+
+		// moving through a (thin) wall to a free spot behind it:
+		// the center must have a free path (it is half a player away from walls, stairs or ceilings).
+		let delta = frame.position - skeleton.position;
+		if delta != vec3::ZERO && !inside(skeleton.position) {
+			let ray = Ray64::new(player.center().into(), delta.normalized().into());
+			if let Some(t) = data.world.map.intersect_t(&ray).filter(|&t| t < delta.len() as f64) {
+				return Err(anyhow!("moved through a wall at {}", ray.at(t).to_f32()));
+			}
+		}
+
+		// moving too far, flying or hovering (see `MoveBudget`).
+		let now = data.time();
+		let on_ground = skeleton.clone().with(|s| s.position = frame.position).on_ground(&data.world);
+		self.move_budgets.entry(player_id).or_insert_with(|| MoveBudget::new(now)).spend(now, delta, on_ground)
+	}
+
+	// Drop an implausible move, send the player back to where we last accepted them.
+	// Log the first and every 10th violation.
+	fn reject_move(&mut self, data: &mut ServerData, player_id: ID, reason: Error) {
+		let now = data.time();
+		let (count, last_correction) = self.move_violations.entry(player_id).or_insert((0, f64::NEG_INFINITY));
+		*count += 1;
+		match *count == 1 || *count % 10 == 0 {
+			true => warn!("{} ({player_id}): {count} implausible move(s), last: {reason}", data.must_name(player_id)),
+			false => trace!("rejecting move by {player_id}: {reason}"),
+		}
+
+		if now - *last_correction >= CORRECTION_INTERVAL {
+			*last_correction = now;
+			data.apply_to_player_full(player_id, |_| ());
+		}
+	}
+
 	//-------------------------------------------------------------------------------- scoring
 
 	// Handle a client saying they just shot a player.
-	// We trust clients not to lie about this.
 	//
 	// Hitting players is computed client-side for latency reasons:
 	// a client always sees other players at a location that lags slightly behind.
 	// If a client hits a player where they see them on their screen, then it should
 	// count as a hit regardless of latency.
 	// Otherwise players with more than about 30ms latency would be at a noticeable disadvantage.
-	pub fn handle_hit_player(&mut self, data: &mut ServerData, actor: ID, victim: ID) -> Option<()> {
-		trace!("{actor} hit {victim}");
+	//
+	// We don't trust clients not to lie about this, though:
+	// the server rewinds all players to where the shooter saw them (`HitClaim::view_time`),
+	// and re-traces the shot. The hit only counts if it really hits the claimed victim.
+	pub fn handle_hit_player(&mut self, data: &mut ServerData, actor: ID, hit: HitClaim) -> Option<()> {
+		trace!("{actor} claims hit on {}", hit.victim);
+
+		let hit_pos = match self.validate_hit(data, actor, &hit) {
+			Ok(pos) => pos,
+			Err(reason) => {
+				info!("rejecting hit {actor} -> {}: {reason}", hit.victim);
+				return None;
+			}
+		};
+		let &Weapon {
+			cooldown, damage, headshot_damage, ..
+		} = data.world.weapons.get(data.player(actor)?.weapon);
+		let pellets = match self.last_hit.get(&actor) {
+			Some(&(last, _, pellets)) if last == hit.view_time => pellets + 1, // same shot
+			_ => 1,
+		};
+		self.last_hit.insert(actor, (hit.view_time, cooldown, pellets));
 
-		self.active_kill(data, actor, victim)?;
+		let headshot = data.hits_head_rewound(hit.victim, hit_pos, hit.view_time);
+		let damage = if headshot { headshot_damage } else { damage };
+		self.damage(data, actor, hit.victim, damage, headshot)
+	}
+
+	// Check that a client's hit claim is plausible (see `handle_hit_player`).
+	// Returns where the shot hit the victim.
+	fn validate_hit(&self, data: &ServerData, actor: ID, hit: &HitClaim) -> Result<vec3> {
+		let shooter = data.player(actor).ok_or(anyhow!("no such shooter"))?;
+		if !shooter.spawned {
+			return Err(anyhow!("shooter not spawned"));
+		}
+
+		// view time must lie within the rewind window
+		// (or slightly in the future, as the client's clock runs freely between `ServerTime` updates).
+		let now = data.time();
+		if !(hit.view_time <= now + MAX_CLOCK_AHEAD && hit.view_time >= now - MAX_REWIND) {
+			return Err(anyhow!("view time {:.3} outside of rewind window (now: {now:.3})", hit.view_time));
+		}
+
+		// shooting faster than the gun allows (the gun that fired the previous shot, the player may have switched since),
+		// or hitting with more pellets than it fires.
+		let weapon = data.world.weapons.get(shooter.weapon);
+		if weapon.kind != WeaponKind::Hitscan {
+			return Err(anyhow!("{} fires projectiles", weapon.name));
+		}
+		if let Some(&(last, cooldown, pellets)) = self.last_hit.get(&actor) {
+			if hit.view_time == last && pellets >= weapon.pellets {
+				return Err(anyhow!("more hits than pellets"));
+			}
+			if hit.view_time != last && hit.view_time - last < COOLDOWN_TOLERANCE * cooldown as f64 {
+				return Err(anyhow!("gun still cooling down"));
+			}
+		}
+
+		validate_line_of_fire(shooter, &hit.line_of_fire)?;
 
+		// pellets fly where the shooter is looking, give or take the weapon's spread.
+		let off = shooter.orientation().look_dir().dot(hit.line_of_fire.dir.to_f32()).clamp(-1.0, 1.0).acos();
+		if off > (weapon.spread + AIM_TOLERANCE) * DEG {
+			return Err(anyhow!("line of fire {:.0}° off the shooter's look direction", off / DEG));
+		}
+
+		// re-trace the shot against the world as the shooter saw it.
+		let ray = Ray64::new(hit.line_of_fire.start, hit.line_of_fire.dir.normalized());
+		match data.intersect_rewound(actor, &ray, hit.view_time) {
+			Some((t, Some(id))) if id == hit.victim && t <= weapon.range as f64 => Ok(ray.at(t).to_f32()),
+			Some((_, Some(id))) if id == hit.victim => Err(anyhow!("victim out of range")),
+			_ => Err(anyhow!("line of fire does not hit victim")),
+		}
+	}
+
+	// Handle a client firing a projectile weapon (see `ClientMsg::FireProjectile`).
+	// Unlike hitscan shots, projectiles are simulated by the server (see `tick_projectiles`),
+	// so there is nothing to rewind: the projectile starts flying when the message arrives.
+	pub fn handle_fire_projectile(&mut self, data: &mut ServerData, actor: ID, line_of_fire: Ray64) -> Option<()> {
+		if let Err(reason) = self.validate_fire(data, actor, &line_of_fire) {
+			info!("rejecting projectile by {actor}: {reason}");
+			return None;
+		}
+		let weapon_idx = data.player(actor)?.weapon;
+		let weapon = data.world.weapons.get(weapon_idx);
+		self.last_fire.insert(actor, (data.time(), weapon.cooldown));
+		let projectile = Projectile::new(actor, weapon_idx, weapon, &line_of_fire);
+		let id = data.add_projectile(projectile);
+		trace!("{actor} fired projectile {id}");
 		Some(())
 	}
 
+	// Check that a client may fire a projectile (see `handle_fire_projectile`).
+	fn validate_fire(&self, data: &ServerData, actor: ID, line_of_fire: &Ray64) -> Result<()> {
+		let shooter = data.player(actor).ok_or(anyhow!("no such shooter"))?;
+		if !shooter.spawned {
+			return Err(anyhow!("shooter not spawned"));
+		}
+		let weapon = data.world.weapons.get(shooter.weapon);
+		if weapon.kind != WeaponKind::Projectile {
+			return Err(anyhow!("{} does not fire projectiles", weapon.name));
+		}
+		// messages arrive in bursts (network jitter), allow some slack on the cooldown.
+		if let Some(&(last, cooldown)) = self.last_fire.get(&actor) {
+			if data.time() - last < COOLDOWN_TOLERANCE * cooldown as f64 {
+				return Err(anyhow!("gun still cooling down"));
+			}
+		}
+		validate_line_of_fire(shooter, line_of_fire)
+	}
+
+	// A projectile exploded: hurt and push away everyone within its splash radius,
+	// falling off with distance (see `splash_falloff`). A direct hit in the head counts as a headshot.
+	//
+	// Knockback pushes everyone, including the owner (rocket jumps) and teammates.
+	// Damage follows the usual rules (see `damage`): no friendly fire, and no hurting yourself.
+	fn explode(&mut self, data: &mut ServerData, projectile: &Projectile, impact: Impact) {
+		let Impact { pos, victim } = impact;
+		let weapon = data.world.weapons.get(projectile.weapon).clone();
+		let color = match (weapon.color, data.player(projectile.owner)) {
+			(Some(color), _) => color,
+			(None, Some(owner)) => owner.team.color_filter(),
+			(None, None) => vec3::ONES,
+		};
+		data.add_effect(Effect::explosion(pos, weapon.splash_radius, color));
+		data.push_no_apply(PlaySound(SoundEffect::spatial(weapon.impact_sound().to_owned(), pos, 3.0)).to(Near(Place::point(pos), None)));
+
+		let now = data.time();
+		for id in data.spawned_player_ids() {
+			let Some(player) = data.player(id) else { continue };
+			let falloff = match victim == Some(id) {
+				true => 1.0,
+				false => splash_falloff(pos, weapon.splash_radius, &player.skeleton.bounds()),
+			};
+			if falloff == 0.0 {
+				continue;
+			}
+
+			// push away from the explosion, and always a bit up:
+			// walking on the ground would otherwise cancel horizontal knockback right away.
+			let speed = falloff * weapon.knockback;
+			if speed > 0.0 {
+				let dir = (player.center() - pos).normalized();
+				let dir = if dir.is_finite() { dir } else { vec3::EY };
+				let kick = (speed * dir).with(|k| k[Y] = f32::max(k[Y], 0.5 * speed));
+				self.move_budgets.entry(id).or_insert_with(|| MoveBudget::new(now)).grant_knockback(now, kick);
+				data.knockback(id, kick);
+			}
+
+			let headshot = victim == Some(id) && data.hits_head_rewound(id, pos, now);
+			let damage = match headshot {
+				true => weapon.headshot_damage,
+				false => (falloff * weapon.damage as f32).round() as i32,
+			};
+			if damage > 0 {
+				self.damage(data, projectile.owner, id, damage, headshot);
+			}
+		}
+	}
+
+	/// A player picked another weapon (see `ClientMsg::SwitchWeapon`).
+	pub fn handle_switch_weapon(&mut self, data: &mut ServerData, player_id: ID, weapon: u8) {
+		if weapon as usize >= data.world.weapons.len() {
+			info!("{player_id}: no such weapon: {weapon}");
+			return;
+		}
+		data.apply_to_player_partial(player_id, |p| p.weapon = weapon);
+	}
+
 	pub fn suicide(&mut self, data: &mut ServerData, victim: ID, msg: &str) -> Option<()> {
 		if data.player(victim)?.spawned {
 			trace!("{victim} suicide");
@@ -183,15 +497,35 @@ impl GameLogic {
 		Some(())
 	}
 
-	/// Someone killed someone else
-	fn active_kill(&mut self, data: &mut ServerData, actor: ID, victim: ID) -> Option<()> {
-		let actor_team = data.player(actor)?.team;
-		let vicitm_team = data.player(victim)?.team;
-
-		if actor_team == vicitm_team {
+	/// Someone shot someone else: take `damage` off the victim's health,
+	/// frag them when it runs out. Teammates and invulnerable players take no damage.
+	fn damage(&mut self, data: &mut ServerData, actor: ID, victim: ID, damage: i32, headshot: bool) -> Option<()> {
+		let target = data.player(victim)?;
+		if data.player(actor)?.team == target.team {
 			trace!("friendly fire {actor} -> {victim}");
 			return None;
 		}
+		if target.invulnerability_ttl.is_some() {
+			trace!("{victim} is invulnerable");
+			return None;
+		}
+
+		let health = target.health - damage;
+		trace!("{actor} hit {victim} for {damage} (headshot: {headshot}), health left: {health}");
+		data.apply_to_player_partial(victim, |p| p.health = health)?;
+		if headshot {
+			self.scores.by_player(actor).headshots += 1;
+		}
+
+		if health <= 0 {
+			self.active_kill(data, actor, victim)?;
+		}
+		Some(())
+	}
+
+	/// Someone killed someone else
+	fn active_kill(&mut self, data: &mut ServerData, actor: ID, victim: ID) -> Option<()> {
+		let actor_team = data.player(actor)?.team;
 
 		trace!("{actor} killed {victim}");
 
@@ -313,16 +647,45 @@ impl GameLogic {
 		self.time_limit - self.time_playing
 	}
 
+	/// Map, players and scores, for status queries.
+	/// `nonce`, `game_port` and `transport` are filled out by the network layer.
+	pub fn status(&self, data: &ServerData) -> ServerStatus {
+		let scores = self.scores.iter().collect::<HashMap<_, _>>();
+		ServerStatus {
+			nonce: 0,
+			protocol_version: PROTOCOL_VERSION,
+			game_port: 0,
+			transport: default(),
+			map_name: self.curr_map_name().into(),
+			players: data
+				.players()
+				.filter_map(|id| data.player(id))
+				.map(|p| PlayerStatus {
+					name: p.name.clone(),
+					team: p.team,
+					spectator: p.spectator,
+					frags: scores.get(&p.id).map(|s| s.frags).unwrap_or_default(),
+					deaths: scores.get(&p.id).map(|s| s.deaths).unwrap_or_default(),
+					ping_ms: data.ping(p.id).map(|rtt| rtt.as_millis() as u32),
+				})
+				.collect(),
+			team_scores: self.scores.by_team,
+			time_remaining: self.time_remaining(),
+			max_players: self.max_players,
+		}
+	}
+
 	//-------------------------------------------------------------------------------- respawn
 
 	pub fn handle_ready_to_respawn(&mut self, data: &mut ServerData, player_id: ID) {
 		trace!("ready_to_respawn: {player_id}");
 		let spawn_point = self.pick_spawn_point(&data.world);
 		data.apply_to_player_full(player_id, |p| {
-			if !p.spawned {
+			if !p.spawned && !p.spectator {
 				trace!("respawn {player_id}");
 				// client could request spawn multiple times in a network race.
 				p.spawned = true;
+				p.health = Player::MAX_HEALTH;
 				p.skeleton.position = spawn_point.position();
 				p.skeleton.orientation = spawn_point.orientation();
 				p.invulnerability_ttl = self.invul_ttl(); // spawn kill protection
@@ -341,15 +704,103 @@ impl GameLogic {
 
 	//-------------------------------------------------------------------------------- join/drop/switch players
 
-	pub fn join_new_player(&mut self, data: &mut ServerData, join_msg: JoinRequest) -> (ID, MapSwitch) {
+	/// Check whether a player may join, before calling `join_new_player`.
+	pub fn check_join(&self, data: &ServerData, join_msg: &JoinRequest) -> Result<(), RejectReason> {
+		use RejectReason::*;
+		check_protocol_version(join_msg.protocol_version)?;
+		if self.can_resume(join_msg.session) {
+			return Ok(()); // slot was kept for them.
+		}
+		// slots of disconnected players are still taken, and so are their names.
+		// spectators don't need a slot.
+		if !join_msg.spectator && data.playing_players().count() + self.disconnected.len() >= self.max_players as usize {
+			return Err(ServerFull { max_players: self.max_players });
+		}
+		Ok(())
+	}
+
+	/// Join a new player under a sanitized, unique version of the name they asked for
+	/// (see `sanitize_name`, `unique_name`).
+	pub fn join_new_player(&mut self, data: &mut ServerData, mut join_msg: JoinRequest) -> (ID, SessionToken, MapSwitch) {
+		join_msg.name = self.unique_name(data, &sanitize_name(&join_msg.name), None);
 		let spawn_point = self.pick_spawn_point(&data.world);
-		let (player_id, map_switch) = data.join_new_player(&spawn_point, join_msg);
-		self.scores.join_new_player(player_id);
+		let team = join_msg.team.unwrap_or_else(|| self.smallest_team(data, None));
+		let (player_id, map_switch) = data.join_new_player(&spawn_point, join_msg, team, self.map_manifest.hash());
+		let session = SessionToken::random();
+		self.sessions.insert(session, player_id);
+		if !data.player(player_id).map(|p| p.spectator).unwrap_or(false) {
+			self.scores.join_new_player(player_id);
+			self.broadcast_scores(data);
+		}
+		(player_id, session, map_switch)
+	}
+
+	// `name`, made unique among all players (except `except`), including disconnected ones,
+	// who may still come back under their name.
+	fn unique_name(&self, data: &ServerData, name: &str, except: Option<ID>) -> String {
+		unique_name(name, |name| {
+			data.player_by_name(name).filter(|&id| Some(id) != except).is_some() || self.disconnected.values().any(|(p, _)| p.name.eq_ignore_ascii_case(name))
+		})
+	}
+
+	/// The player whose session this is, connected or not.
+	pub fn session_player(&self, session: Option<SessionToken>) -> Option<ID> {
+		session.and_then(|s| self.sessions.get(&s)).copied()
+	}
+
+	fn can_resume(&self, session: Option<SessionToken>) -> bool {
+		session.and_then(|s| self.sessions.get(&s)).map(|id| self.disconnected.contains_key(id)).unwrap_or(false)
+	}
+
+	/// Give a reconnecting player back their slot (same ID, team and score),
+	/// if they were disconnected less than `reconnect_grace` seconds ago.
+	pub fn resume_player(&mut self, data: &mut ServerData, session: Option<SessionToken>) -> Option<(ID, SessionToken, MapSwitch)> {
+		let session = session?;
+		let player_id = *self.sessions.get(&session)?;
+		let (player, _) = self.disconnected.remove(&player_id)?;
+		let map_switch = data.rejoin_player(player, self.map_manifest.hash());
 		self.broadcast_scores(data);
-		(player_id, map_switch)
+		Some((player_id, session, map_switch))
 	}
 
-	pub fn drop_player(&mut self, _player_id: ID) {}
+	/// Remove a disconnected player from the game,
+	/// but keep their slot and score for `reconnect_grace` seconds (see `resume_player`).
+	pub fn drop_player(&mut self, data: &mut ServerData, player_id: ID) {
+		// spectators have no slot or score to keep.
+		if let Some(player) = data.player(player_id).filter(|p| !p.spectator) {
+			let player = player.clone().with(|p| p.spawned = false);
+			self.disconnected.insert(player_id, (player, data.time() + self.reconnect_grace));
+		}
+		data.drop_player(player_id);
+
+		self.last_hit.remove(&player_id);
+		self.last_fire.remove(&player_id);
+		self.move_budgets.remove(&player_id);
+		self.move_violations.remove(&player_id);
+		self.uploads.cancel(player_id);
+
+		self.balance_teams(data);
+	}
+
+	/// Remove a player from the game for good:
+	/// unlike `drop_player`, their slot and score are not kept for reconnecting.
+	pub fn kick_player(&mut self, data: &mut ServerData, player_id: ID) {
+		self.drop_player(data, player_id);
+		self.disconnected.remove(&player_id);
+		self.sessions.retain(|_, session_id| *session_id != player_id);
+		self.scores.drop_player(player_id);
+		self.broadcast_scores(data);
+	}
+
+	/// Handle a client asking for the files of the current map that it doesn't have (see `MapUploads`).
+	pub fn handle_request_map(&mut self, data: &mut ServerData, player_id: ID, map_name: &str, have: &MapManifest) {
+		if map_name != self.curr_map_name() {
+			// client will get a new MapSwitch.
+			return info!("{player_id} requested map {map_name}, but the current map is {}", self.curr_map_name());
+		}
+		let start = self.uploads.start(player_id, map_name, &self.map_manifest, have);
+		data.push_no_apply(MapDownload(start).to_just(player_id));
+	}
 
 	fn switch_map(&mut self, data: &mut ServerData, map_idx: usize) -> Result<()> {
 		for id in data.players() {
@@ -360,12 +811,14 @@ impl GameLogic {
 
 		let map_name = &self.maplist.get(map_idx).ok_or(bug())?;
 		let new_map = Map::load(&self.assets, map_name)?;
+		self.map_manifest = MapManifest::scan(&self.assets.map_dir(map_name))?;
+		self.uploads.cancel_all(); // clients will request the new map.
 
-		data.switch_map(new_map);
+		data.switch_map(new_map, self.map_manifest.hash());
 		self.curr_map = map_idx;
 
 		self.time_playing = 0.0;
-		self.scores.reset(data.players());
+		self.scores.reset(data.playing_players().chain(self.disconnected.keys().copied()));
 		self.broadcast_scores(data);
 
 		data.hud_announce(All, self.curr_map_name().to_owned());
@@ -373,13 +826,117 @@ impl GameLogic {
 		Ok(())
 	}
 
+	pub fn maplist(&self) -> &[String] {
+		&self.maplist
+	}
+
 	// name of the currently active map
 	pub fn curr_map_name(&self) -> &str {
 		&self.maplist.get(self.curr_map).map(String::as_str).unwrap_or("???")
 	}
 
+	//-------------------------------------------------------------------------------- teams
+
+	// Number of (connected, non-spectating) players per team, not counting `except`.
+	fn team_sizes(&self, data: &ServerData, except: Option<ID>) -> [usize; NUM_TEAMS] {
+		let mut sizes = [0; NUM_TEAMS];
+		for id in data.playing_players().filter(|&id| Some(id) != except) {
+			if let Some(player) = data.player(id) {
+				sizes[player.team as usize] += 1;
+			}
+		}
+		sizes
+	}
+
+	/// The team with the fewest players (not counting `except`).
+	/// Of equally small teams, the one with the lowest score.
+	pub fn smallest_team(&self, data: &ServerData, except: Option<ID>) -> Team {
+		let sizes = self.team_sizes(data, except);
+		Team::ALL
+			.into_iter()
+			.min_by_key(|&team| (sizes[team as usize], self.scores.by_team[team as usize]))
+			.unwrap_or(Team::Red)
+	}
+
+	// With `team_balance`: while a team has 2 or more players more than another,
+	// move its most recently joined player to the smallest team.
+	fn balance_teams(&mut self, data: &mut ServerData) {
+		if !self.team_balance {
+			return;
+		}
+		loop {
+			let sizes = self.team_sizes(data, None);
+			let smallest = self.smallest_team(data, None);
+			let largest = Team::ALL.into_iter().max_by_key(|&team| sizes[team as usize]).unwrap_or(Team::Red);
+			if sizes[largest as usize] < sizes[smallest as usize] + 2 {
+				return;
+			}
+			let newest = data.playing_players().filter(|&id| data.player(id).map(|p| p.team) == Some(largest)).max();
+			match newest {
+				None => return,
+				Some(id) => {
+					let msg = format!("{} moved to team {smallest} to balance the teams", data.must_name(id));
+					self.move_to_team(data, id, smallest, msg);
+				}
+			}
+		}
+	}
+
+	// Put a player on another team. If playing, they respawn there (without penalty).
+	// Everyone gets `msg`.
+	fn move_to_team(&mut self, data: &mut ServerData, player_id: ID, team: Team, msg: String) {
+		let spawn_point = self.pick_spawn_point(&data.world);
+		let invulnerability_ttl = self.invul_ttl();
+		data.apply_to_player_full(player_id, |p| {
+			p.team = team;
+			if p.spawned {
+				p.skeleton.position = spawn_point.position();
+				p.skeleton.orientation = spawn_point.orientation();
+				p.invulnerability_ttl = invulnerability_ttl;
+			}
+		});
+		self.sprees.remove(&player_id);
+		data.log(msg.clone());
+		data.hud_announce(All, msg);
+		self.broadcast_scores(data);
+	}
+
 	//-------------------------------------------------------------------------------- commands
 
+	/// Respond to text command "team blue" (`None`: "team auto").
+	pub fn switch_team_cmd(&mut self, data: &mut ServerData, player_id: ID, choice: Option<Team>) -> Result<()> {
+		let player = data.player(player_id).ok_or(anyhow!("no such player"))?;
+		if player.spectator {
+			return Err(anyhow!("spectators are not on a team"));
+		}
+		let old = player.team;
+		let team = choice.unwrap_or_else(|| self.smallest_team(data, Some(player_id)));
+		if team == old {
+			return Err(anyhow!("already on team {team}"));
+		}
+		let sizes = self.team_sizes(data, Some(player_id));
+		if self.team_balance && sizes[team as usize] > sizes[old as usize] {
+			return Err(anyhow!("team {team} has more players, teams would be uneven"));
+		}
+		let msg = format!("{} switched to team {team}", data.must_name(player_id));
+		self.move_to_team(data, player_id, team, msg);
+		Ok(())
+	}
+
+	/// Respond to text command "name new_name".
+	pub fn rename_cmd(&mut self, data: &mut ServerData, player_id: ID, new_name: &str) -> Result<()> {
+		let old = data.must_name(player_id).to_owned();
+		let new = self.unique_name(data, &sanitize_name(new_name), Some(player_id));
+		if new == old {
+			return Err(anyhow!("already named {new}"));
+		}
+		data.apply_to_player_full(player_id, |p| p.name = new.clone()).ok_or(anyhow!("no such player"))?;
+		let msg = format!("{old} is now known as {new}");
+		data.log(msg.clone());
+		data.hud_announce(All, msg);
+		Ok(())
+	}
+
 	/// Respond to text command "switch my_map".
 	pub fn switch_map_cmd(&mut self, data: &mut ServerData, map_name: &str) -> Result<()> {
 		let map_idx = self
@@ -390,6 +947,34 @@ impl GameLogic {
 
 		self.switch_map(data, map_idx)
 	}
+
+	/// Respond to text command "restart": play the current map again, from zero scores.
+	pub fn restart_map(&mut self, data: &mut ServerData) -> Result<()> {
+		self.switch_map(data, self.curr_map)
+	}
+
+	/// Respond to text command "fraglimit 20".
+	pub fn set_frag_limit(&mut self, data: &mut ServerData, frag_limit: u32) {
+		self.frag_limit = frag_limit as i32;
+		self.broadcast_scores(data);
+	}
+
+	/// Respond to text command "timelimit 300".
+	pub fn set_time_limit(&mut self, data: &mut ServerData, seconds: u32) {
+		self.time_limit = seconds as f32;
+		self.broadcast_scores(data);
+	}
+}
+
+// A line of fire must be well-formed and start at the shooter.
+fn validate_line_of_fire(shooter: &Player, ray: &Ray64) -> Result<()> {
+	if !ray.start.is_finite() || !ray.dir.is_finite() || (ray.dir.len() - 1.0).abs() > 1e-3 {
+		return Err(anyhow!("malformed line of fire"));
+	}
+	if (ray.start.to_f32() - shooter.center()).len() > MAX_SHOT_ORIGIN_DIST {
+		return Err(anyhow!("line of fire does not start at shooter"));
+	}
+	Ok(())
 }
 
 fn verify_maps(assets: &AssetsDir, maplist: &[String]) -> Result<()> {
@@ -397,6 +982,7 @@ fn verify_maps(assets: &AssetsDir, maplist: &[String]) -> Result<()> {
 		return Err(anyhow!("server: maplist: need at least one map"));
 	}
 	for map_name in maplist {
+		check_map_name(map_name)?;
 		if let Err(e) = Map::load(assets, map_name) {
 			return Err(anyhow!("map {} failed verification: {}", map_name, e));
 		}
@@ -0,0 +1,59 @@
+use super::internal::*;
+
+/// Where something happens, for interest management (see `Addressee::Near`):
+/// a point (sound, explosion) or a line segment (particle beam).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Place {
+	pub start: vec3,
+	pub end: vec3,
+}
+
+impl Place {
+	pub fn point(pos: vec3) -> Self {
+		Self { start: pos, end: pos }
+	}
+
+	pub fn segment(start: vec3, end: vec3) -> Self {
+		Self { start, end }
+	}
+
+	/// The point of the place nearest to `pos`.
+	pub fn closest_to(&self, pos: vec3) -> vec3 {
+		let dir = self.end - self.start;
+		let len2 = dir.len2();
+		if len2 == 0.0 {
+			return self.start;
+		}
+		let t = ((pos - self.start).dot(dir) / len2).clamp(0.0, 1.0);
+		self.start + t * dir
+	}
+
+	/// Can a player at `viewer` see or hear what happens here?
+	/// I.e. is part of the place within `radius` zones from the viewer's zone (see `zones_apart`).
+	pub fn perceivable_from(&self, viewer: vec3, radius: u32) -> bool {
+		zones_apart(viewer.floor(), self.closest_to(viewer).floor()) <= radius
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn perceivable_from() {
+		let z = ZONE_SIZE as f32;
+		let here = vec3(10.0, 10.0, 10.0);
+		let far = vec3(10.0 + 5.0 * z, 10.0, 10.0);
+
+		assert!(Place::point(here).perceivable_from(here, 0));
+		assert!(Place::point(here + vec3(z, z, 0.0)).perceivable_from(here, 1));
+		assert!(!Place::point(here + vec3(z, 0.0, 0.0)).perceivable_from(here, 0));
+		assert!(!Place::point(far).perceivable_from(here, 2));
+
+		// a beam passing by from afar.
+		let beam = Place::segment(far, here - vec3(z, 0.0, 0.0));
+		assert_eq!(beam.closest_to(here), vec3(10.0, 10.0, 10.0));
+		assert!(beam.perceivable_from(here, 0));
+		assert!(!Place::segment(far, far + vec3(0.0, z, 0.0)).perceivable_from(here, 2));
+	}
+}
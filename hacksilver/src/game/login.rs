@@ -0,0 +1,72 @@
+use super::internal::*;
+use std::net::IpAddr;
+
+/// Password guessing protection for `login`: after `MAX_LOGIN_FAILURES` wrong passwords
+/// from an IP address, logins from there are refused until `LOGIN_LOCKOUT` seconds after the last one.
+///
+/// Keyed by address rather than by player, so that reconnecting does not start over.
+#[derive(Default)]
+pub struct LoginLockout {
+	failures: HashMap<IpAddr, (u32, f64)>, // wrong passwords, server time of the last one
+}
+
+const MAX_LOGIN_FAILURES: u32 = 3;
+const LOGIN_LOCKOUT: f64 = 300.0;
+
+impl LoginLockout {
+	/// Check a `password` sent from `ip` at server time `now` against the `expected` one.
+	/// While `ip` is locked out, even the right password is refused.
+	pub fn check(&mut self, ip: IpAddr, now: f64, password: &str, expected: &str) -> Result<()> {
+		self.failures.retain(|_, (_, last)| now - *last < LOGIN_LOCKOUT);
+		if self.locked_out(ip, now) {
+			return Err(anyhow!("too many failed logins, try again later"));
+		}
+		if !constant_time_eq(password.as_bytes(), expected.as_bytes()) {
+			let (failures, last) = self.failures.entry(ip).or_insert((0, now));
+			*failures += 1;
+			*last = now;
+			return Err(anyhow!("wrong password"));
+		}
+		self.failures.remove(&ip);
+		Ok(())
+	}
+
+	/// Has `ip` had too many failed logins lately?
+	pub fn locked_out(&self, ip: IpAddr, now: f64) -> bool {
+		self.failures.get(&ip).is_some_and(|&(failures, last)| failures >= MAX_LOGIN_FAILURES && now - last < LOGIN_LOCKOUT)
+	}
+}
+
+// Compare without stopping at the first difference,
+// so that response times don't tell how much of a guessed password was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn lockout() {
+		let mut lockout = LoginLockout::default();
+		let (mallory, alice) = (IpAddr::from([10, 0, 0, 1]), IpAddr::from([10, 0, 0, 2]));
+		for _ in 0..MAX_LOGIN_FAILURES {
+			assert!(lockout.check(mallory, 0.0, "hunter2", "secret").is_err());
+		}
+		assert!(lockout.locked_out(mallory, 1.0));
+		assert!(lockout.check(mallory, 1.0, "secret", "secret").is_err());
+		lockout.check(alice, 1.0, "secret", "secret").unwrap();
+
+		assert!(!lockout.locked_out(mallory, LOGIN_LOCKOUT + 1.0));
+		lockout.check(mallory, LOGIN_LOCKOUT + 1.0, "secret", "secret").unwrap();
+	}
+
+	#[test]
+	fn constant_time() {
+		assert!(constant_time_eq(b"secret", b"secret"));
+		assert!(!constant_time_eq(b"secret", b"secreT"));
+		assert!(!constant_time_eq(b"secret", b"secret2"));
+		assert!(!constant_time_eq(b"", b"secret"));
+	}
+}
@@ -0,0 +1,236 @@
+use super::internal::*;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+
+/// A map being downloaded from the server into the map cache (client side).
+/// See `ClientMsg::RequestMap`, `MapDownloadMsg`.
+pub struct MapDownload {
+	pub map_switch: MapSwitch, // to load once done
+	pub dir: MapDir,
+	manifest: MapManifest,
+	total_bytes: u64,
+	received: u64,
+}
+
+/// Bytes sent per client per server tick (about 2 MB/s at 30 ms ticks).
+const UPLOAD_BYTES_PER_TICK: usize = 64 * 1024;
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// The directory holding the exact version of the map requested by `map_switch`, if we have it:
+/// either the player's own map, or a previously downloaded one.
+/// None for map names that are not valid file names (see `check_map_name`).
+pub fn find_map_dir(assets: &AssetsDir, map_switch: &MapSwitch) -> Option<MapDir> {
+	let name = &map_switch.map_name;
+	check_map_name(name).ok()?;
+	[assets.map_dir(name), assets.cached_map_dir(name).ok()?]
+		.into_iter()
+		.find(|dir| dir.exists() && MapManifest::scan(dir).map(|m| m.hash() == map_switch.map_hash).unwrap_or(false))
+}
+
+impl MapDownload {
+	/// Start downloading the map requested by `map_switch`.
+	/// Files left in the cache by an earlier download are kept if still up to date.
+	/// Returns the request to send to the server.
+	pub fn start(assets: &AssetsDir, map_switch: MapSwitch) -> Result<(Self, ClientMsg)> {
+		let dir = assets.cached_map_dir(&map_switch.map_name)?;
+		fs::create_dir_all(dir.lightmap_dir()).map_err(|e| anyhow!("create map cache: {e}"))?;
+		let have = MapManifest::scan(&dir)?;
+		let request = ClientMsg::RequestMap {
+			map_name: map_switch.map_name.clone(),
+			have,
+		};
+		LOG.write(format!("downloading map {}...", map_switch.map_name));
+		let download = Self {
+			map_switch,
+			dir,
+			manifest: default(),
+			total_bytes: 0,
+			received: 0,
+		};
+		Ok((download, request))
+	}
+
+	/// Handle part of the download. Returns `true` once the map is complete.
+	pub fn handle(&mut self, msg: MapDownloadMsg) -> Result<bool> {
+		use MapDownloadMsg::*;
+		match msg {
+			Start { map_name, manifest, total_bytes } => {
+				if map_name != self.map_switch.map_name {
+					return Err(anyhow!("downloading {}: server sent map {map_name}", self.map_switch.map_name));
+				}
+				self.remove_stale_files(&manifest)?;
+				self.manifest = manifest;
+				self.total_bytes = total_bytes;
+				Ok(false)
+			}
+			Chunk { path, offset, data } => {
+				self.write_chunk(&path, offset, &data)?;
+				self.received += data.len() as u64;
+				Ok(false)
+			}
+			Done => match MapManifest::scan(&self.dir)?.hash() == self.map_switch.map_hash {
+				true => Ok(true),
+				false => Err(anyhow!("downloaded map {} does not match the server's", self.map_switch.map_name)),
+			},
+		}
+	}
+
+	/// E.g. "downloading map deck: 1200 / 2400 KiB".
+	pub fn progress(&self) -> String {
+		format!("downloading map {}: {} / {} KiB", self.map_switch.map_name, self.received / 1024, self.total_bytes / 1024)
+	}
+
+	// Remove files from an earlier version of the map.
+	fn remove_stale_files(&self, manifest: &MapManifest) -> Result<()> {
+		for file in MapManifest::scan(&self.dir)?.files {
+			if !manifest.contains(&file.path) {
+				fs::remove_file(self.dir.file(&file.path)).map_err(|e| anyhow!("remove stale map file {}: {e}", file.path))?;
+			}
+		}
+		Ok(())
+	}
+
+	fn write_chunk(&self, path: &str, offset: u64, data: &[u8]) -> Result<()> {
+		// only write the files announced in `Start`, never outside of the map directory.
+		if !self.manifest.contains(path) || !is_plain_relative_path(path) {
+			return Err(anyhow!("map download: unexpected file {path:?}"));
+		}
+
+		let file = self.dir.file(path);
+		let mut f = match offset {
+			0 => File::create(&file),
+			_ => fs::OpenOptions::new().append(true).open(&file),
+		}
+		.map_err(|e| anyhow!("write {file:?}: {e}"))?;
+
+		if f.metadata()?.len() != offset {
+			return Err(anyhow!("map download: {path}: chunk at offset {offset} out of order"));
+		}
+		Ok(f.write_all(data)?)
+	}
+}
+
+// `a/b.png`, but not `../x`, `/x`, `a//b`, `..\x`, `C:x`,... (on any OS, the server's manifest can't be trusted).
+fn is_plain_relative_path(path: &str) -> bool {
+	!path.is_empty()
+		&& !path.contains(['\\', ':'])
+		&& path.split('/').all(|part| !part.is_empty() && part != "." && part != "..")
+		&& Path::new(path).components().all(|c| matches!(c, std::path::Component::Normal(_)))
+}
+
+/// Map files being sent to clients (server side).
+#[derive(Default)]
+pub struct MapUploads {
+	by_client: HashMap<ID, MapUpload>,
+}
+
+struct MapUpload {
+	files: VecDeque<MapFile>,
+	current: Option<(String, Vec<u8>, usize)>, // file being sent: path, content, bytes sent so far
+}
+
+impl MapUploads {
+	/// Start sending client `id` the files of the current map (`manifest`) that it does not `have`.
+	/// Returns the message starting the download.
+	pub fn start(&mut self, id: ID, map_name: &str, manifest: &MapManifest, have: &MapManifest) -> MapDownloadMsg {
+		let files = manifest.missing_in(have);
+		let total_bytes = files.iter().map(|f| f.size).sum();
+		info!("{id}: sending {} map files ({} KiB)", files.len(), total_bytes / 1024);
+		self.by_client.insert(id, MapUpload { files: files.into(), current: None });
+		MapDownloadMsg::Start {
+			map_name: map_name.into(),
+			manifest: manifest.clone(),
+			total_bytes,
+		}
+	}
+
+	/// The next chunks to send, at most `UPLOAD_BYTES_PER_TICK` per client.
+	/// Files are read from `map_dir`.
+	pub fn tick(&mut self, map_dir: &MapDir) -> Vec<(ID, MapDownloadMsg)> {
+		let mut msgs = vec![];
+		let mut done = vec![];
+
+		for (&id, upload) in &mut self.by_client {
+			let mut budget = UPLOAD_BYTES_PER_TICK;
+			while budget > 0 {
+				match upload.next_chunk(map_dir, usize::min(budget, CHUNK_SIZE)) {
+					Ok(Some(chunk)) => {
+						if let MapDownloadMsg::Chunk { data, .. } = &chunk {
+							budget -= usize::max(1, data.len());
+						}
+						msgs.push((id, chunk))
+					}
+					Ok(None) => {
+						msgs.push((id, MapDownloadMsg::Done));
+						done.push(id);
+						break;
+					}
+					Err(e) => {
+						// the client will notice the map is incomplete.
+						error!("{id}: map upload: {e:#}");
+						msgs.push((id, MapDownloadMsg::Done));
+						done.push(id);
+						break;
+					}
+				}
+			}
+		}
+
+		for id in done {
+			self.by_client.remove(&id);
+		}
+		msgs
+	}
+
+	pub fn cancel(&mut self, id: ID) {
+		self.by_client.remove(&id);
+	}
+
+	/// Stop all uploads (e.g. because the map changed, clients will request the new one).
+	pub fn cancel_all(&mut self) {
+		self.by_client.clear();
+	}
+}
+
+impl MapUpload {
+	// The next chunk of at most `max_len` bytes, or `None` if all files have been sent.
+	fn next_chunk(&mut self, map_dir: &MapDir, max_len: usize) -> Result<Option<MapDownloadMsg>> {
+		if self.current.is_none() {
+			match self.files.pop_front() {
+				None => return Ok(None),
+				Some(file) => {
+					let data = fs::read(map_dir.file(&file.path)).map_err(|e| anyhow!("read {}: {e}", file.path))?;
+					self.current = Some((file.path, data, 0));
+				}
+			}
+		}
+
+		let (path, data, sent) = self.current.as_mut().unwrap(/*just set*/);
+		let len = usize::min(max_len, data.len() - *sent);
+		let chunk = MapDownloadMsg::Chunk {
+			path: path.clone(),
+			offset: *sent as u64,
+			data: data[*sent..(*sent + len)].to_vec(),
+		};
+		*sent += len;
+		if *sent == data.len() {
+			self.current = None;
+		}
+		Ok(Some(chunk))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn plain_relative_paths() {
+		assert!(is_plain_relative_path("deck.ron"));
+		assert!(is_plain_relative_path("textures/wall.png"));
+		for path in ["", "/etc/passwd", "../x", "a/../../x", "a//b", "./a", "a/", "..\\..\\x", "a\\b", "C:x", "C:\\x", "\\\\server\\x"] {
+			assert!(!is_plain_relative_path(path), "{path:?}");
+		}
+	}
+}
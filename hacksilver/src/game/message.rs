@@ -7,30 +7,152 @@ pub struct Envelope<T> {
 	pub msg: T,
 }
 
-/// Who to send a message to: Just one player, All but one player, All players.
+/// Who to send a message to: Just one player, All but one player, All players,
+/// or the players who can perceive a place (except one, if given).
+///
+/// `Near` is for effects, spatial sounds and teleports: it saves bandwidth, and keeps far-away players' positions
+/// from clients (see `ServerOpts::interest_radius`, and `ServerData::view_of` for snapshots).
 #[derive(Copy, Clone)]
 pub enum Addressee {
 	Just(ID),
 	Not(ID),
 	All,
+	Near(Place, Option<ID>),
 }
 
 pub type ClientMsgs = Vec<ClientMsg>;
 pub type ServerMsgs = Vec<Envelope<ServerMsg>>;
 
+/// Version of the client-server protocol implemented by this build.
+/// Bump on every incompatible change to `ClientMsg`, `ServerMsg` or their contents.
+pub const PROTOCOL_VERSION: u32 = 17;
+
+/// Oldest client protocol version still accepted by this server.
+pub const MIN_PROTOCOL_VERSION: u32 = 17;
+
 /// Initial message sent by client when first joining a server.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct JoinRequest {
+	// Protocol version and capabilities must remain the first fields,
+	// so that servers can always decode them to reject incompatible clients.
+	pub protocol_version: u32,
+	pub capabilities: Capabilities,
+
 	pub name: String, // Player's nickname
 	pub avatar_id: u8,
-	pub team: Team,
+	pub team: Option<Team>, // None: auto, join the smallest team (see `GameLogic::smallest_team`)
+	pub spectator: bool,    // only watch: never spawn, don't take a player slot, not scored.
+
+	// Set when reconnecting: resume the session handed out by `AcceptedMsg`,
+	// getting back the same player (ID, team, score) if the server still remembers it.
+	pub session: Option<SessionToken>,
 }
 
-/// Initial message sent by client when first joining a server.
+impl JoinRequest {
+	/// Decode a join request from a frame's payload (see `wireformat::read_frame`).
+	///
+	/// The protocol version is read and checked on its own first: other versions may lay out
+	/// the rest of the request differently, but should still learn why they cannot join
+	/// rather than see the connection drop.
+	pub fn decode(payload: &[u8]) -> Result<Result<Self, RejectReason>> {
+		let version = payload.get(..4).ok_or(anyhow!("join request too short: {} bytes", payload.len()))?;
+		if let Err(reason) = check_protocol_version(u32::from_le_bytes(version.try_into().unwrap())) {
+			return Ok(Err(reason));
+		}
+		Ok(Ok(wireformat::decode(payload)?))
+	}
+}
+
+/// Can a client speaking protocol `version` join this server?
+pub fn check_protocol_version(version: u32) -> Result<(), RejectReason> {
+	if version < MIN_PROTOCOL_VERSION {
+		return Err(RejectReason::VersionTooOld {
+			client: version,
+			min_supported: MIN_PROTOCOL_VERSION,
+		});
+	}
+	if version > PROTOCOL_VERSION {
+		return Err(RejectReason::VersionTooNew {
+			client: version,
+			server: PROTOCOL_VERSION,
+		});
+	}
+	Ok(())
+}
+
+/// Secret handed to a client on joining, allowing it to resume its session after a disconnect.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SessionToken(u64);
+
+impl SessionToken {
+	pub fn random() -> Self {
+		Self(rand::random())
+	}
+}
+
+/// Optional protocol features, negotiated during the join handshake:
+/// the client sends what it supports in `JoinRequest`,
+/// the server answers with the subset that will be used in `AcceptedMsg`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+	pub const NONE: Self = Self(0);
+
+	/// All capabilities implemented by this build.
+	pub const SUPPORTED: Self = Self::NONE;
+
+	pub fn contains(self, other: Self) -> bool {
+		self.0 & other.0 == other.0
+	}
+
+	/// Capabilities supported by both `self` and `other`.
+	pub fn intersection(self, other: Self) -> Self {
+		Self(self.0 & other.0)
+	}
+}
+
+/// Server's answer to a `JoinRequest`.
+#[derive(Serialize, Deserialize)]
+pub enum JoinResponse {
+	Accepted(AcceptedMsg),
+	Rejected(RejectedMsg),
+}
+
+/// Sent by server when a client was allowed to join.
 #[derive(Serialize, Deserialize)]
 pub struct AcceptedMsg {
 	pub player_id: ID,
+	pub session: SessionToken,
+	pub capabilities: Capabilities,
 	pub map_switch: MapSwitch,
+	pub name: String, // the name the server gave us, may differ from the requested one (see `sanitize_name`, `unique_name`)
+}
+
+/// Sent by server when a client was not allowed to join.
+/// The server closes the connection right after.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RejectedMsg {
+	pub reason: RejectReason,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum RejectReason {
+	VersionTooOld {
+		client: u32,
+		min_supported: u32,
+	},
+	VersionTooNew {
+		client: u32,
+		server: u32,
+	},
+	ServerFull {
+		max_players: u32,
+	},
+	/// Unused: name clashes are now resolved with a suffix (see `unique_name`).
+	/// Kept so that the variants after it keep their encoding: only append new ones.
+	NameTaken(String),
+	Banned,
 }
 
 /// Subsequent messages sent by Client after the initial JoinMsg.
@@ -52,22 +174,47 @@ pub enum ClientMsg {
 	// Start a sound effect.
 	PlaySound(SoundEffect),
 
-	// I have shot player with ID `victim`.
-	HitPlayer(ID),
+	// I have shot a player (validated by the server, see `HitClaim`).
+	// One claim per pellet that hit.
+	HitPlayer(HitClaim),
+
+	// I now hold this weapon (index in `World::weapons`).
+	SwitchWeapon(u8),
+
+	// I fired my projectile weapon (see `WeaponKind::Projectile`) along this line.
+	// The server spawns the projectile and decides what it hits (see `GameLogic::handle_fire_projectile`).
+	FireProjectile(Ray64),
 
 	// Send a CLI command to the server.
 	Command(String),
+
+	// I don't have the map from the last `MapSwitch` (or a different version of it),
+	// please send me the files I'm missing. The server answers with `ServerMsg::MapDownload`.
+	RequestMap { map_name: String, have: MapManifest },
+
+	// I have received the snapshot at this tick (see `ServerMsg::UpdatePlayers`).
+	// The server will send future snapshots relative to it.
+	AckSnapshot(Tick),
+
+	// Answer to `ServerMsg::Ping`, with the same number. Sent right away (not in the next frame).
+	Pong(u32),
+}
+
+/// A client's claim to have shot a player.
+///
+/// Carries enough information for the server to re-play the shot
+/// against the world as the shooter saw it (see `GameLogic::handle_hit_player`).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HitClaim {
+	pub victim: ID,
+	pub line_of_fire: Ray64,
+	// Server time (see `ServerMsg::ServerTime`) of the world as the shooter saw it.
+	pub view_time: f64,
 }
 
 /// Messages sent by Server.
 #[derive(Serialize, Deserialize, Clone)]
 pub enum ServerMsg {
-	/// Server tells client to add a new player to the game.
-	AddPlayer(Player),
-
-	/// Server tells client to remove a player from the game.
-	DropPlayer(ID),
-
 	/// Server tells client to change maps.
 	/// (Server will first have de-spawned. Will force respawn after mapswitch).
 	SwitchMap(MapSwitch),
@@ -75,21 +222,44 @@ pub enum ServerMsg {
 	// ??? TODO: remove
 	ForceMovePlayer(vec3),
 
-	// Server tells client to update the position, orientation, velocity of *other* players.
-	MovePlayer(ID, Frame),
-
-	// Server tells client to update their player, except for position, orientation, velocity
-	// which is controlled locally.
-	UpdatePlayerPartial(Player),
+	// Sent every tick: the changes to all players since the last snapshot acknowledged by the client
+	// (see `ClientMsg::AckSnapshot`). Players appear and disappear through snapshots.
+	// The client's own position, orientation, velocity are not included,
+	// they are controlled locally.
+	UpdatePlayers(SnapshotDelta),
 
 	// Server tells client to update *everything*, even postion, orientation, velocity
 	// which would normally be controlled locally. The server will only do so when the player
-	// is despawned.
+	// is despawned, or to correct an implausible move (see `GameLogic::handle_move_player`).
 	UpdatePlayerFull(Player),
 
 	AddEffect(Effect),
 	PlaySound(SoundEffect),
 	UpdateHUD(HUDUpdate),
+
+	// A projectile was fired. Clients extrapolate its flight until `RemoveProjectile` (see `Projectile`).
+	AddProjectile(ID, Projectile),
+	// A projectile exploded (the explosion is a separate `AddEffect`).
+	RemoveProjectile(ID),
+
+	// Add this to the local player's velocity: pushed by an explosion (see `Weapon::knockback`).
+	Knockback(vec3),
+
+	// Files requested by `ClientMsg::RequestMap`.
+	MapDownload(MapDownloadMsg),
+
+	// Sent first in every tick's batch of messages: the tick number (increases by one every tick,
+	// see `ServerOpts::tick_rate`) and the server time (seconds) at which the frames
+	// in the following `UpdatePlayers` were recorded. Clients echo the time back in `HitClaim::view_time`.
+	ServerTime(Tick, f64),
+
+	// Sent periodically (see `NetServer::tick_heartbeats`). Clients answer with `ClientMsg::Pong`.
+	// Measures round-trip time, and lets the server notice clients that went away.
+	Ping(u32),
+
+	// `say`, `say_team` and `tell` (see `ServerState::handle_chat_cmd`).
+	Chat(ChatMsg),
+
 	Log(String),
 }
 
@@ -99,9 +269,21 @@ pub enum ServerMsg {
 #[derive(Serialize, Deserialize, Clone)]
 pub struct MapSwitch {
 	pub map_name: String,
+	pub map_hash: u64, // see `MapManifest::hash`: clients must have this exact version of the map.
 	pub entities: Entities,
 }
 
+/// A map download is streamed over several messages, a few per server tick
+/// (so as not to starve the game traffic):
+/// `Start`, then `Chunk`s of each missing file in order, then `Done`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum MapDownloadMsg {
+	// All files of the map (so that stale files can be removed), and how many bytes will follow.
+	Start { map_name: String, manifest: MapManifest, total_bytes: u64 },
+	Chunk { path: String, offset: u64, data: Vec<u8> },
+	Done,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct HUDUpdate {
 	pub pos: HUDPos,
@@ -109,6 +291,35 @@ pub struct HUDUpdate {
 	pub ttl_sec: f32,
 }
 
+impl fmt::Display for RejectReason {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		use RejectReason::*;
+		match self {
+			VersionTooOld { client, min_supported } => write!(f, "game version too old (protocol {client}, server needs at least {min_supported}), please update"),
+			VersionTooNew { client, server } => write!(f, "game version newer than server's (protocol {client}, server has {server})"),
+			ServerFull { max_players } => write!(f, "server full ({max_players} players)"),
+			NameTaken(name) => write!(f, "name `{name}` already taken"),
+			Banned => f.write_str("you are banned from this server"),
+		}
+	}
+}
+
+impl Delivery for ClientMsg {
+	// Moves and acks are superseded by the next one, no need to re-send.
+	// Neither are pongs (see `ServerMsg::Ping`).
+	fn is_reliable(&self) -> bool {
+		!matches!(self, ClientMsg::MovePlayerIfSpawned(_) | ClientMsg::AckSnapshot(_) | ClientMsg::Pong(_))
+	}
+}
+
+impl Delivery for ServerMsg {
+	// Lost snapshots are superseded by the next one, which is relative to a snapshot the client did receive.
+	// Re-sent pings would measure the re-send delay.
+	fn is_reliable(&self) -> bool {
+		!matches!(self, ServerMsg::UpdatePlayers(_) | ServerMsg::Ping(_))
+	}
+}
+
 impl ServerMsg {
 	pub fn to_all(self) -> Envelope<Self> {
 		self.to(Addressee::All)
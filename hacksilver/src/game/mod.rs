@@ -1,5 +1,8 @@
+mod bot;
+mod chat;
 mod client;
 mod client_state;
+mod console;
 mod diff_world;
 mod diffs;
 mod drawing;
@@ -7,26 +10,42 @@ mod effect;
 mod entities;
 mod entity;
 mod entity_data;
+mod frame_history;
 mod game_ctx;
 mod game_logic;
+mod interest;
 mod internal;
 mod keyboard_control;
+mod login;
+mod map_download;
 mod message;
+mod move_check;
 mod net_server;
 mod player;
+mod player_name;
 mod players;
 mod primitives;
+mod projectile;
+mod replica;
 mod scores;
+mod server_commands;
 mod server_data;
 mod server_opts;
+mod server_query;
 mod server_state;
+mod snapshot;
 mod sound_effect;
 mod spawn_point;
+mod spectator;
 mod team;
+mod weapon;
 mod world;
 
+pub use bot::*;
+pub use chat::*;
 pub use client::*;
 pub use client_state::*;
+pub use console::*;
 pub use diff_world::*;
 pub use diffs::*;
 pub use drawing::*;
@@ -34,19 +53,32 @@ pub use effect::*;
 pub use entities::*;
 pub use entity::*;
 pub use entity_data::*;
+pub use frame_history::*;
 pub use game_ctx::*;
 pub use game_logic::*;
+pub use interest::*;
 pub use keyboard_control::*;
+pub use login::*;
+pub use map_download::*;
 pub use message::ServerMsg;
 pub use message::*;
+pub use move_check::*;
 pub use net_server::*;
 pub use player::*;
+pub use player_name::*;
 pub use players::*;
 pub use primitives::*;
+pub use projectile::*;
+pub use replica::*;
 pub use scores::*;
+pub use server_commands::*;
 pub use server_data::*;
 pub use server_opts::*;
+pub use server_query::*;
 pub use server_state::*;
+pub use snapshot::*;
 pub use sound_effect::*;
 pub use spawn_point::*;
+pub use spectator::*;
+pub use weapon::*;
 pub use world::*;
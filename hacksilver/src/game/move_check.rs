@@ -0,0 +1,218 @@
+use super::internal::*;
+
+/// How far a player may plausibly have moved, refilled over server time at the maximum speeds allowed by the physics.
+///
+/// Moves arrive in bursts (network jitter), so rather than checking each move against the time since the previous one,
+/// each move spends from a budget that accumulates for at most `MAX_BURST` seconds.
+///
+/// Gravity is modelled coarsely: the upward budget only refills when landing on the ground
+/// (one jump's worth, so no flying up), and players who stay in the air for longer than `MAX_HANG_TIME`
+/// must be falling (no hovering).
+///
+/// Knockback allows extra movement for `KNOCKBACK_TIME` (see `grant_knockback`).
+pub struct MoveBudget {
+	time: f64,
+	horizontal: f32,
+	up: f32,
+	down: f32,
+	airborne_since: Option<f64>, // server time of the first accepted move in the air
+	knockback: [f32; 3],         // extra horizontal, up, down budget allowed by recent knockback
+	knockback_until: f64,        // server time when `knockback` expires
+}
+
+/// Seconds of movement budget that can be saved up, e.g. while a client's moves are stuck in the network.
+pub const MAX_BURST: f32 = 0.5;
+
+// Air control can make players slightly faster than walking, allow some slack.
+const SPEED_TOLERANCE: f32 = 1.5;
+
+// Speed of free fall from the top of a 256 voxel high map (`sqrt(2 * G * h)`), with some slack.
+const MAX_FALL_SPEED: f32 = 250.0;
+
+// A player pushed by an explosion flies for about this long before landing or air drag slows them down.
+const KNOCKBACK_TIME: f32 = 1.0;
+
+// Longest a player may stay in the air without falling: the top of a rocket jump, with some slack.
+const MAX_HANG_TIME: f64 = 2.0;
+
+const MAX_HORIZONTAL_SPEED: f32 = SPEED_TOLERANCE * Player::WALK_SPEED;
+
+// Top of a jump (`JUMP_SPEED² / 2G`), with some slack.
+const MAX_JUMP_HEIGHT: f32 = SPEED_TOLERANCE * Player::JUMP_SPEED * Player::JUMP_SPEED / (2.0 * G);
+
+impl MoveBudget {
+	/// Full budget at server time `time`.
+	pub fn new(time: f64) -> Self {
+		Self {
+			time,
+			horizontal: MAX_HORIZONTAL_SPEED * MAX_BURST,
+			up: MAX_JUMP_HEIGHT,
+			down: MAX_FALL_SPEED * MAX_BURST,
+			airborne_since: None,
+			knockback: [0.0; 3],
+			knockback_until: time,
+		}
+	}
+
+	/// Spend budget for moving by `delta` at server time `time`,
+	/// ending up standing on the ground or not (`on_ground`, see `Skeleton::on_ground`).
+	/// Returns an error and spends nothing if the move is too far, or hovers in the air.
+	pub fn spend(&mut self, time: f64, delta: vec3, on_ground: bool) -> Result<()> {
+		self.refill(time);
+
+		let horizontal = delta.with(|d| d[Y] = 0.0).len();
+		let up = f32::max(0.0, delta.y());
+		let down = f32::max(0.0, -delta.y());
+
+		if horizontal > self.horizontal {
+			return Err(anyhow!("moved {horizontal:.1} horizontally, allowed {:.1}", self.horizontal));
+		}
+		if up > self.up {
+			return Err(anyhow!("moved {up:.1} up, allowed {:.1}", self.up));
+		}
+		if down > self.down {
+			return Err(anyhow!("moved {down:.1} down, allowed {:.1}", self.down));
+		}
+		if let Some(since) = self.airborne_since.filter(|_| !on_ground) {
+			if time - since > MAX_HANG_TIME && delta.y() >= 0.0 {
+				return Err(anyhow!("in the air for {:.1}s without falling", time - since));
+			}
+		}
+
+		self.horizontal -= horizontal;
+		self.up -= up;
+		self.down -= down;
+		self.airborne_since = match on_ground {
+			true => None,
+			false => Some(self.airborne_since.unwrap_or(time)),
+		};
+		if on_ground {
+			self.up = f32::max(self.up, MAX_JUMP_HEIGHT);
+		}
+		Ok(())
+	}
+
+	/// Allow the extra movement caused by being pushed with `velocity` at server time `time`
+	/// (see `ServerMsg::Knockback`), on top of the usual budget, for `KNOCKBACK_TIME`.
+	///
+	/// Knockbacks don't add up beyond the strongest one: the budget is capped at `MAX_BURST`'s worth
+	/// plus one knockback, so that e.g. rocket jumping in place can't save up budget for a teleport.
+	pub fn grant_knockback(&mut self, time: f64, velocity: vec3) {
+		self.refill(time);
+		let push = [
+			velocity.with(|v| v[Y] = 0.0).len() * KNOCKBACK_TIME,
+			f32::max(0.0, velocity.y()) * KNOCKBACK_TIME,
+			f32::max(0.0, -velocity.y()) * KNOCKBACK_TIME,
+		];
+		for (extra, push) in self.knockback.iter_mut().zip(push) {
+			*extra = f32::max(*extra, push);
+		}
+		self.knockback_until = time + KNOCKBACK_TIME as f64;
+
+		let [horizontal, up, down] = push;
+		self.horizontal = f32::min(self.horizontal + horizontal, MAX_HORIZONTAL_SPEED * MAX_BURST + self.knockback[0]);
+		self.up = f32::min(self.up + up, MAX_JUMP_HEIGHT + self.knockback[1]);
+		self.down = f32::min(self.down + down, MAX_FALL_SPEED * MAX_BURST + self.knockback[2]);
+		self.airborne_since = None; // flying up again
+	}
+
+	// Budget above the maximum burst (granted by knockback) is kept until spent, or until the knockback expires.
+	fn refill(&mut self, time: f64) {
+		let dt = f64::max(0.0, time - self.time) as f32;
+		self.time = f64::max(self.time, time);
+		if self.time >= self.knockback_until {
+			self.knockback = [0.0; 3];
+		}
+		let [horizontal, up, down] = self.knockback;
+		let refill = |budget: f32, speed: f32, extra: f32| f32::min(f32::max(budget, f32::min(budget + speed * dt, speed * MAX_BURST)), speed * MAX_BURST + extra);
+		self.horizontal = refill(self.horizontal, MAX_HORIZONTAL_SPEED, horizontal);
+		self.up = f32::min(self.up, MAX_JUMP_HEIGHT + up);
+		self.down = refill(self.down, MAX_FALL_SPEED, down);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn walking_is_ok() {
+		let mut b = MoveBudget::new(0.0);
+		let dt = 0.016;
+		for i in 1..1000 {
+			b.spend(i as f64 * dt, vec3(Player::WALK_SPEED * dt as f32, 0.0, 0.0), true).unwrap();
+		}
+	}
+
+	#[test]
+	fn speeding_is_not_ok() {
+		let mut b = MoveBudget::new(0.0);
+		let dt = 0.016;
+		let too_fast = 2.0 * Player::WALK_SPEED * dt as f32;
+		assert!((1..1000).any(|i| b.spend(i as f64 * dt, vec3(0.0, 0.0, too_fast), true).is_err()));
+	}
+
+	#[test]
+	fn teleport_is_not_ok() {
+		let mut b = MoveBudget::new(0.0);
+		assert!(b.spend(10.0, vec3(0.0, 100.0, 0.0), false).is_err());
+		assert!(b.spend(10.0, vec3(100.0, 0.0, 0.0), true).is_err());
+		b.spend(10.0, vec3(0.0, -100.0, 0.0), true).unwrap(); // falling
+	}
+
+	#[test]
+	fn flying_is_not_ok() {
+		let mut b = MoveBudget::new(0.0);
+		let dt = 0.016;
+		let up = Player::JUMP_SPEED * dt as f32;
+		assert!((1..1000).any(|i| b.spend(i as f64 * dt, vec3(0.0, up, 0.0), false).is_err()));
+
+		// no higher than a jump per landing.
+		let mut b = MoveBudget::new(0.0);
+		b.spend(1.0, vec3(0.0, -1.0, 0.0), true).unwrap();
+		assert!(b.spend(1.5, vec3(0.0, 2.0 * MAX_JUMP_HEIGHT / SPEED_TOLERANCE, 0.0), false).is_err());
+
+		// jumping again and again is fine.
+		let mut b = MoveBudget::new(0.0);
+		for i in 1..1000 {
+			// in the air for `2 * JUMP_SPEED / G`: 60 moves.
+			let on_ground = i % 60 == 0;
+			let dy = (Player::JUMP_SPEED - G * (i % 60) as f32 * dt as f32) * dt as f32;
+			b.spend(i as f64 * dt, vec3(0.0, dy, 0.0), on_ground).unwrap();
+		}
+	}
+
+	#[test]
+	fn hovering_is_not_ok() {
+		let mut b = MoveBudget::new(0.0);
+		let dt = 0.016;
+		let walk = vec3(Player::WALK_SPEED * dt as f32, 0.0, 0.0);
+		assert!((1..1000).any(|i| b.spend(i as f64 * dt, walk, false).is_err()));
+		b.spend(1000.0 * dt, walk - vec3::EY, false).unwrap(); // falling
+	}
+
+	#[test]
+	fn knockback_is_ok() {
+		let mut b = MoveBudget::new(0.0);
+		b.grant_knockback(0.0, vec3(100.0, 50.0, 0.0));
+		b.spend(0.5, vec3(100.0, 50.0, 0.0), false).unwrap();
+		assert!(b.spend(0.5, vec3(100.0, 0.0, 0.0), false).is_err());
+	}
+
+	#[test]
+	fn knockback_does_not_accumulate() {
+		// rocket jumping in place, again and again.
+		let mut b = MoveBudget::new(0.0);
+		for i in 0..100 {
+			b.grant_knockback(i as f64 * 0.1, vec3(100.0, 50.0, 0.0));
+		}
+		assert!(b.spend(10.0, vec3(200.0, 0.0, 0.0), false).is_err());
+		b.spend(10.0, vec3(100.0, 50.0, 0.0), false).unwrap();
+
+		// unspent knockback budget expires.
+		let mut b = MoveBudget::new(0.0);
+		b.grant_knockback(0.0, vec3(100.0, 50.0, 0.0));
+		assert!(b.spend(KNOCKBACK_TIME as f64 + 0.1, vec3(100.0, 0.0, 0.0), true).is_err());
+		assert!(b.spend(KNOCKBACK_TIME as f64 + 0.1, vec3(0.0, 50.0, 0.0), false).is_err());
+	}
+}
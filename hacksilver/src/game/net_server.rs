@@ -1,5 +1,8 @@
 use super::internal::*;
-use std::sync::mpsc::TryRecvError;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::SocketAddr;
+use std::sync::atomic;
 
 /// Network RPC & driver layer on top of `ServerState`.
 ///
@@ -7,54 +10,244 @@ use std::sync::mpsc::TryRecvError;
 ///		Listening for incoming connections
 /// 	Dropping players from disconnected connections
 /// 	Ticking `Severstate` main loop
-/// 	Forwarding `ServerMsg`s to subsets of clients (Just(player)/Not(player)/All)
+/// 	Forwarding `ServerMsg`s to subsets of clients (Just(player)/Not(player)/All/Near(place))
 ///
 pub struct NetServer {
-	listen: Receiver<TcpStream>, // incoming connections are sent here
-	clients: HashMap<ID, NetPipe>,
+	listen: Receiver<Incoming>, // incoming connections are sent here
+	local: Sender<Incoming>,    // in-process clients join here (see `connector`)
+	clients: HashMap<ID, ClientConn>,
 	tick_duration: Duration,
+	banned: Set<IpAddr>,
+	query: Option<QueryListener>, // answers status queries, unless the port was taken
+	console: Option<Console>,     // operator commands, when serving on the network
+	game_port: u16,
+	transport: Transport,
+	max_frame: u32,
+	max_msgs_per_sec: u32,
+	max_bytes_per_sec: u32,
+	idle_timeout: Duration,
+	netsim: Option<NetSimOpts>,
 
 	state: ServerState,
 }
 
 type NetPipe = crate::net::NetPipe<ServerMsg, ClientMsg>;
 
+// A joined client's connection.
+struct ClientConn {
+	pipe: NetPipe,
+	addr: SocketAddr,
+	rate_limit: RateLimit,
+	bytes_counted: u64, // by `rate_limit` so far
+
+	last_heard: Instant,
+	ping: (u32, Instant),  // number and send time of the last `ServerMsg::Ping`
+	rtt: Option<Duration>, // smoothed round-trip time
+}
+
+// Time between `ServerMsg::Ping`s to each client.
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+const MAX_TICK_RATE: u32 = 1000;
+
+// When ticks run late (e.g. the server was suspended), at most this many are caught up on,
+// the rest are skipped (the game runs slow for a moment rather than fast-forwarding).
+const MAX_CATCH_UP_TICKS: u32 = 10;
+
+// Time a new client has to send its `JoinRequest` (all of it, not per read).
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Connections waiting for their `JoinRequest` (one thread each), per IP address and in total.
+// More are closed right away.
+const MAX_HANDSHAKES_PER_IP: u32 = 4;
+const MAX_HANDSHAKES: u32 = 64;
+
+// A new client connection, with its (not yet decoded) `JoinRequest`, waiting for the response.
+//
+// Join requests are read by the listen loops (see `spawn_handshake`), not by the server loop,
+// so that a slow or silent client can't hold up the game.
+enum Incoming {
+	Tcp(TcpStream, Vec<u8>),
+	Udp(UdpConn, Vec<u8>),
+	Local(JoinRequest, Sender<LocalJoin>), // in-process client
+}
+
+// Counts handshakes in progress (see `MAX_HANDSHAKES`), shared by a listen loop and its handshake threads.
+#[derive(Clone, Default)]
+struct HandshakeSlots(Arc<Mutex<HashMap<IpAddr, u32>>>);
+
+// A handshake in progress from an IP address, counted until dropped.
+struct HandshakeSlot(HandshakeSlots, IpAddr);
+
+impl HandshakeSlots {
+	fn take(&self, ip: IpAddr) -> Option<HandshakeSlot> {
+		let mut counts = self.0.lock().unwrap();
+		let total = counts.values().sum::<u32>();
+		let count = counts.entry(ip).or_default();
+		if *count >= MAX_HANDSHAKES_PER_IP || total >= MAX_HANDSHAKES {
+			return None;
+		}
+		*count += 1;
+		Some(HandshakeSlot(self.clone(), ip))
+	}
+}
+
+impl Drop for HandshakeSlot {
+	fn drop(&mut self) {
+		let mut counts = self.0 .0.lock().unwrap();
+		if let Some(count) = counts.get_mut(&self.1) {
+			*count -= 1;
+			if *count == 0 {
+				counts.remove(&self.1);
+			}
+		}
+	}
+}
+
+// Reads from a TCP stream until `deadline`, however slowly the bytes trickle in.
+struct DeadlineReader<'s> {
+	stream: &'s TcpStream,
+	deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		let left = self.deadline.saturating_duration_since(Instant::now());
+		if left.is_zero() {
+			return Err(std::io::ErrorKind::TimedOut.into());
+		}
+		self.stream.set_read_timeout(Some(left))?;
+		self.stream.read(buf)
+	}
+}
+
+/// Client end of an in-process connection, and the server's response to joining.
+pub type LocalJoin = (crate::net::NetPipe<ClientMsg, ServerMsg>, JoinResponse);
+
+/// Lets clients in the same process join a `NetServer` running on another thread
+/// (see `NetServer::local`), talking over a loopback `NetPipe` instead of sockets.
+#[derive(Clone)]
+pub struct LocalConnector(Sender<Incoming>);
+
 impl NetServer {
 	/// Serve incoming connections on `opts.addr`.
-	/// Only returns in case of error.
+	/// Returns when the operator types `quit` (see `Console`), or in case of error.
 	pub fn listen_and_serve(opts: ServerOpts) -> Result<()> {
-		Self::new(opts)?.serve_loop()
+		Self::bind(opts)?.serve_loop()
+	}
+
+	/// Listen on `opts.addr` (port 0: any free port, see `game_port`),
+	/// but let the caller run the server with `spawn` or `tick`. For tests.
+	pub fn bind(opts: ServerOpts) -> Result<Self> {
+		Self::new(opts, true)
+	}
+
+	/// A server for clients in the same process only (single player, tests):
+	/// binds no sockets, clients join via `connector` or `join_local`.
+	/// Run it with `spawn`, or drive it tick by tick with `tick`.
+	pub fn local(opts: ServerOpts) -> Result<Self> {
+		Self::new(opts, false)
 	}
 
-	fn new(opts: ServerOpts) -> Result<Self> {
-		let listen_for_conn = Self::spawn_listen_loop(&opts.addr)?;
-		let tick_duration = Duration::from_millis(30); // TODO
+	// With `network`: listen for clients on `opts.addr`, and for status queries.
+	fn new(opts: ServerOpts, network: bool) -> Result<Self> {
+		let (local, listen_for_conn) = channel();
+		let mut query = None;
+		let mut console = None;
+		let mut game_port = opts.addr.parse::<SocketAddr>().map(|addr| addr.port()).map_err(|e| anyhow!("addr: `{}`: {e}", opts.addr))?;
+		if network {
+			let addr = match opts.transport {
+				Transport::Tcp => Self::spawn_listen_loop(&opts.addr, opts.max_message_bytes, local.clone())?,
+				Transport::Udp => Self::spawn_udp_listen_loop(&opts.addr, opts.max_message_bytes, local.clone())?,
+			};
+			game_port = addr.port();
+			// not fatal: e.g. a second server on the same host can still be played on, just not discovered.
+			query = QueryListener::bind(opts.query_port).map_err(|e| warn!("status queries disabled: {e:#}")).ok();
+			console = Some(Console::spawn(!opts.no_stdin_console, opts.console_socket.as_deref())?);
+		}
+		if !(1..=MAX_TICK_RATE).contains(&opts.tick_rate) {
+			return Err(anyhow!("tick_rate: {} ticks/s, must be 1-{MAX_TICK_RATE}", opts.tick_rate));
+		}
+		let tick_duration = Duration::from_secs_f64(1.0 / opts.tick_rate as f64);
+		let banned = opts.banned.iter().map(|ip| ip.parse().map_err(|e| anyhow!("banned: `{ip}`: {e}"))).collect::<Result<_>>()?;
+		let transport = opts.transport;
+		let (max_frame, max_msgs_per_sec, max_bytes_per_sec) = (opts.max_message_bytes, opts.max_msgs_per_sec, opts.max_bytes_per_sec);
+		let idle_timeout = Duration::from_secs(opts.idle_timeout as u64);
+		let netsim = opts.netsim.clone();
+		if let Some(sim) = &netsim {
+			warn!("simulating network: {sim:?}");
+		}
 
 		Ok(Self {
 			listen: listen_for_conn,
+			local,
 			clients: HashMap::default(),
 			state: ServerState::new(opts)?,
 			tick_duration,
+			banned,
+			query,
+			console,
+			game_port,
+			transport,
+			max_frame,
+			max_msgs_per_sec,
+			max_bytes_per_sec,
+			idle_timeout,
+			netsim,
 		})
 	}
 
-	fn serve_loop(&mut self) -> Result<()> {
-		loop {
-			let start = Instant::now();
+	/// Run the server loop on a background thread, until the process exits.
+	pub fn spawn(mut self) {
+		thread::spawn(move || {
+			if let Err(e) = self.serve_loop() {
+				error!("server: {e:#}")
+			}
+		});
+	}
+
+	/// Port clients connect to for playing.
+	pub fn game_port(&self) -> u16 {
+		self.game_port
+	}
 
+	/// Lets in-process clients join, from any thread.
+	pub fn connector(&self) -> LocalConnector {
+		LocalConnector(self.local.clone())
+	}
+
+	// Fixed timestep: tick every `tick_duration`, on schedule regardless of how long ticks take.
+	// Late ticks run back-to-back until the server has caught up.
+	fn serve_loop(&mut self) -> Result<()> {
+		let mut next_tick = Instant::now();
+		while !self.state.quit_requested() {
 			self.tick()?;
 
+			next_tick += self.tick_duration;
 			let now = Instant::now();
-			let elapsed = now - start;
-			if let Some(sleep) = self.tick_duration.checked_sub(elapsed) {
-				thread::sleep(sleep)
+			match next_tick.checked_duration_since(now) {
+				Some(sleep) => thread::sleep(sleep),
+				None => {
+					let behind = now - next_tick;
+					if behind > self.tick_duration * MAX_CATCH_UP_TICKS {
+						warn!("server is {behind:?} behind, skipping ticks");
+						next_tick = now;
+					}
+				}
 			}
 		}
+		Ok(())
 	}
 
-	fn tick(&mut self) -> Result<()> {
-		self.tick_listen()?;
+	/// Advance the game by one tick: accept new clients, handle their messages,
+	/// update the game and send out the changes.
+	pub fn tick(&mut self) -> Result<()> {
+		self.tick_listen();
+		self.tick_console();
 		self.tick_client_msgs()?;
+		self.tick_kicks();
+		self.tick_heartbeats();
+		self.tick_queries();
 		let diffs = self.state.handle_tick(self.tick_duration.as_secs_f32());
 		self.flush_diffs(diffs);
 		Ok(())
@@ -63,48 +256,158 @@ impl NetServer {
 	//-------------------------------------------------------------------------------- accept new clients
 
 	// check for incoming connections (non-blocking)
-	fn tick_listen(&mut self) -> Result<()> {
-		match self.listen.try_recv() {
-			Ok(tcp_stream) => Ok(self.handle_conn(tcp_stream)),
-			Err(TryRecvError::Empty) => Ok(()),
-			Err(TryRecvError::Disconnected) => Err(anyhow!("server: listen thread died")),
+	// (never disconnected: we hold on to a sender for local clients)
+	fn tick_listen(&mut self) {
+		if let Ok(conn) = self.listen.try_recv() {
+			self.handle_conn(conn)
 		}
 	}
 
 	// Handle a new client connection.
-	fn handle_conn(&mut self, tcp_stream: TcpStream) {
-		if let Err(e) = self.handle_conn_with_result(tcp_stream) {
+	fn handle_conn(&mut self, conn: Incoming) {
+		let result = match conn {
+			Incoming::Tcp(tcp_stream, join_req) => self.handle_tcp_conn(tcp_stream, &join_req),
+			Incoming::Udp(udp_conn, join_req) => self.handle_udp_conn(udp_conn, &join_req),
+			Incoming::Local(join_req, reply) => {
+				let _ = reply.send(self.join_local(join_req)); // error means the client is no longer waiting, it will be dropped on the next send.
+				Ok(())
+			}
+		};
+		if let Err(e) = result {
 			error!("handle_conn: error: {}", e)
 		}
 	}
 
 	// add new player to the game, send them the full state.
-	fn handle_conn_with_result(&mut self, mut tcp_stream: TcpStream) -> Result<()> {
+	fn handle_tcp_conn(&mut self, mut tcp_stream: TcpStream, join_req: &[u8]) -> Result<()> {
 		// Perform a handshake:
-		//  * Client sends JoinMsg with player info
-		//  * Server sends AcceptMsg with client ID and map to load.
-		let join_msg: JoinRequest = wireformat::deserialize_from(&mut tcp_stream)?;
-		let name = join_msg.name.clone();
-		let (player_id, map_switch) = self.state.join_new_player(join_msg);
-		info!("accepting {:?} ({:?}) as {}", tcp_stream.local_addr().unwrap(), name, player_id);
-		wireformat::serialize_into(&mut tcp_stream, &AcceptedMsg { player_id, map_switch })?;
-		let pipe = NetPipe::new(tcp_stream);
-		assert!(self.clients.insert(player_id, pipe).is_none());
+		//  * Client sends JoinMsg with protocol version and player info (already read by `spawn_handshake`)
+		//  * Server sends AcceptMsg with client ID and map to load,
+		//    or RejectMsg with the reason why the client cannot join (and hangs up).
+		let addr = tcp_stream.peer_addr()?;
+		let response = self.handle_join_request(addr, join_req)?;
+		wireformat::serialize_into(&mut tcp_stream, &response)?;
+		if let JoinResponse::Accepted(accepted) = response {
+			let pipe = NetPipe::new(tcp_stream, self.max_frame);
+			self.add_client(accepted.player_id, addr, pipe);
+		}
+		Ok(())
+	}
+
+	// Same handshake as `handle_tcp_conn`, over the reliable UDP channel.
+	fn handle_udp_conn(&mut self, conn: UdpConn, join_req: &[u8]) -> Result<()> {
+		let response = self.handle_join_request(conn.peer_addr(), join_req)?;
+		let mut buf = Vec::new();
+		wireformat::serialize_into(&mut buf, &response)?;
+		conn.send_reliable(&buf)?;
+		if let JoinResponse::Accepted(accepted) = response {
+			let addr = conn.peer_addr();
+			let pipe = NetPipe::udp(conn, self.max_frame);
+			self.add_client(accepted.player_id, addr, pipe);
+		}
 		Ok(())
 	}
 
+	/// Join an in-process client right away, without waiting for the next tick
+	/// (unlike `LocalConnector::join`). For tests.
+	pub fn join_local(&mut self, join_req: JoinRequest) -> LocalJoin {
+		let (pipe, client_pipe) = NetPipe::loopback();
+		let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+		let response = self.join_new_player(addr, join_req);
+		if let JoinResponse::Accepted(accepted) = &response {
+			self.add_client(accepted.player_id, addr, pipe);
+		}
+		(client_pipe, response)
+	}
+
+	fn add_client(&mut self, id: ID, addr: SocketAddr, pipe: NetPipe) {
+		let pipe = match &self.netsim {
+			None => pipe,
+			// different (but reproducible) conditions per client.
+			Some(sim) => pipe.simulate(&sim.clone().with(|sim| sim.seed = sim.seed.wrapping_add(2 * self.clients.len() as u64))),
+		};
+		let now = Instant::now();
+		let client = ClientConn {
+			pipe,
+			addr,
+			rate_limit: RateLimit::new(now, self.max_msgs_per_sec, self.max_bytes_per_sec),
+			bytes_counted: 0,
+			last_heard: now,
+			ping: (0, now),
+			rtt: None,
+		};
+		// a reconnecting client takes over its session from a connection we did not notice was dead yet.
+		if let Some(old) = self.clients.insert(id, client) {
+			info!("client {id}: connection from {} replaced by {addr}", old.addr);
+		}
+	}
+
+	// Answer a remote client's `JoinRequest` (see `JoinRequest::decode`).
+	fn handle_join_request(&mut self, addr: SocketAddr, payload: &[u8]) -> Result<JoinResponse> {
+		match JoinRequest::decode(payload)? {
+			Ok(join_msg) => Ok(self.join_new_player(addr, join_msg)),
+			Err(reason) => {
+				info!("rejecting {addr}: {reason}");
+				Ok(JoinResponse::Rejected(RejectedMsg { reason }))
+			}
+		}
+	}
+
+	fn join_new_player(&mut self, addr: SocketAddr, join_msg: JoinRequest) -> JoinResponse {
+		let name = join_msg.name.clone();
+		let result = match self.banned.contains(&addr.ip()) {
+			true => Err(RejectedMsg { reason: RejectReason::Banned }),
+			false => self.state.join_new_player(addr.ip(), join_msg),
+		};
+		match result {
+			Ok(accepted) => {
+				info!("accepting {addr} ({name:?}) as {}", accepted.player_id);
+				JoinResponse::Accepted(accepted)
+			}
+			Err(rejected) => {
+				info!("rejecting {addr} ({name:?}): {}", rejected.reason);
+				JoinResponse::Rejected(rejected)
+			}
+		}
+	}
+
+	//-------------------------------------------------------------------------------- status queries
+
+	// answer status queries (non-blocking).
+	fn tick_queries(&mut self) {
+		if let Some(query) = &mut self.query {
+			query.answer(|| {
+				self.state.status().with(|s| {
+					s.game_port = self.game_port;
+					s.transport = self.transport;
+				})
+			})
+		}
+	}
+
 	//-------------------------------------------------------------------------------- client messages
 
 	// Incoming client messages, if any, are forwarded to `ServerState` for handling.
-	// Closed connections or bad wire data cause the client to be dropped and removed from the game.
+	// Closed connections, bad wire data (e.g. oversized messages) or exceeding the rate limits
+	// cause the client to be dropped and removed from the game.
 	fn tick_client_msgs(&mut self) -> Result<()> {
+		let now = Instant::now();
 		let mut drop = vec![];
-		for (id, pipe) in &mut self.clients {
-			while let Some(msg) = pipe.try_recv() {
-				match msg {
-					Ok(msg) => self.state.handle_client_msg(*id, msg),
+		for (id, client) in &mut self.clients {
+			while let Some(msg) = client.pipe.try_recv() {
+				match msg.and_then(|msg| client.check_rate(now).map(|()| msg)) {
+					Ok(ClientMsg::Pong(seq)) => {
+						client.last_heard = now;
+						if let Some(rtt) = client.handle_pong(seq, now) {
+							self.state.set_ping(*id, rtt)
+						}
+					}
+					Ok(msg) => {
+						client.last_heard = now;
+						self.state.handle_client_msg(*id, msg)
+					}
 					Err(e) => {
-						error!("error reading from client {id}: {e}, dropping client.");
+						error!("error reading from client {id}: {e:#}, dropping client.");
 						drop.push(*id);
 						break;
 					}
@@ -119,6 +422,83 @@ impl NetServer {
 		Ok(())
 	}
 
+	//-------------------------------------------------------------------------------- console
+
+	// Run the operator's commands (non-blocking).
+	fn tick_console(&mut self) {
+		let cmds = match &self.console {
+			Some(console) => console.try_iter().collect::<Vec<_>>(),
+			None => return,
+		};
+		for cmd in cmds {
+			let answer = self.console_command(&cmd.line);
+			cmd.reply(answer);
+		}
+	}
+
+	/// Run a command with the rights of the server console, return the answer for the operator.
+	/// Kicks etc. take effect on the next `tick`.
+	pub fn console_command(&mut self, cmd: &str) -> String {
+		let answer = match self.state.handle_console_command(cmd) {
+			Ok(answer) if answer.is_empty() => "ok".into(),
+			Ok(answer) => answer,
+			Err(e) => format!("error: {e:#}"),
+		};
+		if self.state.quit_requested() {
+			info!("quit requested by the operator");
+			for id in self.clients.keys().copied().collect::<Vec<_>>() {
+				self.send_to(id, ServerMsg::Log("server shutting down".into()));
+			}
+		}
+		answer
+	}
+
+	pub fn quit_requested(&self) -> bool {
+		self.state.quit_requested()
+	}
+
+	//-------------------------------------------------------------------------------- kicks
+
+	// Disconnect players kicked by an admin (already removed from the game),
+	// telling them why. Banned players' IP addresses may not join again.
+	fn tick_kicks(&mut self) {
+		for kick in self.state.take_kicks() {
+			if let Some(mut client) = self.clients.remove(&kick.player_id) {
+				info!("kicking client {} ({}): {}", kick.player_id, client.addr, kick.reason);
+				if kick.ban {
+					self.banned.insert(client.addr.ip());
+				}
+				let _ = client.pipe.send(ServerMsg::Log(kick.reason)); // still delivered before hanging up.
+			}
+		}
+	}
+
+	//-------------------------------------------------------------------------------- heartbeats
+
+	// Ping clients every `PING_INTERVAL`.
+	// Drop clients we have not heard from for `idle_timeout`
+	// (e.g. half-open TCP connections, which would otherwise go unnoticed for minutes).
+	fn tick_heartbeats(&mut self) {
+		let now = Instant::now();
+		let mut drop = vec![];
+		let mut ping = vec![];
+		for (&id, client) in &mut self.clients {
+			if now - client.last_heard > self.idle_timeout {
+				error!("client {id}: nothing received for {:?}, dropping client.", self.idle_timeout);
+				drop.push(id);
+			} else if now - client.ping.1 >= PING_INTERVAL {
+				ping.push((id, client.start_ping(now)));
+			}
+		}
+
+		for id in drop {
+			self.handle_drop_client(id)
+		}
+		for (id, seq) in ping {
+			self.send_to(id, ServerMsg::Ping(seq))
+		}
+	}
+
 	//-------------------------------------------------------------------------------- clients disconnect
 
 	// Handle a dropped connection event.
@@ -135,25 +515,26 @@ impl NetServer {
 	fn flush_diffs(&mut self, diffs: Diffs) {
 		let client_ids = self.clients.keys().copied().collect::<SmallVec<[ID; 16]>>();
 		for msg in diffs.into_iter() {
-			for client_id in Self::addressees(&client_ids, msg.to) {
+			for client_id in self.addressees(&client_ids, msg.to) {
 				self.send_to(client_id, msg.msg.clone())
 			}
 		}
 	}
 
-	// expand Addressee (Just/Not/All) into list of matching client IDs.
-	fn addressees(clients: &[ID], a: Addressee) -> SmallVec<[ID; 8]> {
+	// expand Addressee (Just/Not/All/Near) into list of matching client IDs.
+	fn addressees(&self, clients: &[ID], a: Addressee) -> SmallVec<[ID; 8]> {
 		match a {
 			Addressee::Just(id) => smallvec![id],
 			Addressee::Not(id) => clients.into_iter().copied().filter(|&i| i != id).collect(),
 			Addressee::All => clients.iter().copied().collect(),
+			Addressee::Near(place, not) => clients.iter().copied().filter(|&i| Some(i) != not && self.state.perceives(i, &place)).collect(),
 		}
 	}
 
 	// send a message to just one player
 	fn send_to(&mut self, player_id: ID, msg: ServerMsg) {
 		if let Some(client) = self.clients.get_mut(&player_id) {
-			match client.send(msg) {
+			match client.pipe.send(msg) {
 				Err(e) => {
 					error!("send_to {player_id}: {e}");
 					self.handle_drop_client(player_id)
@@ -167,26 +548,134 @@ impl NetServer {
 
 	// Spawn a loop that accepts incoming TCP connections on `address`,
 	// sends them over a channel for non-blocking access by the server's main thread.
-	fn spawn_listen_loop(address: &str) -> Result<Receiver<TcpStream>> {
-		let (send, recv) = channel();
+	fn spawn_listen_loop(address: &str, max_frame: u32, send: Sender<Incoming>) -> Result<SocketAddr> {
 		println!("------------------------------------");
 		println!(" Listening on {address}");
 		println!("------------------------------------");
 		let listener = TcpListener::bind(address)?;
+		let addr = listener.local_addr()?;
+		let quit = Arc::new(atomic::AtomicBool::new(false));
+		let slots = HandshakeSlots::default();
 		thread::spawn(move || {
 			for stream in listener.incoming() {
-				match stream {
-					Err(e) => error!("accept: {e}"), // client failed to connect, server carries on.
-					Ok(tcp_stream) => {
-						info!("accepted connection {}", tcp_stream.peer_addr().unwrap());
-						if send.send(tcp_stream).is_err() {
-							info!("listen: quitting");
-							return; // server quit, so stop worker thread.
-						}
+				if quit.load(atomic::Ordering::Relaxed) {
+					info!("listen: quitting");
+					return; // server quit, so stop worker thread.
+				}
+				let tcp_stream = match stream {
+					Err(e) => {
+						error!("accept: {e}"); // client failed to connect, server carries on.
+						continue;
 					}
+					Ok(tcp_stream) => tcp_stream,
+				};
+				let Ok(peer) = tcp_stream.peer_addr() else { continue };
+				let Some(slot) = slots.take(peer.ip()) else {
+					info!("{peer}: too many connections waiting to join, closing");
+					continue;
+				};
+				info!("accepted connection {peer}");
+				Self::spawn_handshake(peer.to_string(), slot, send.clone(), quit.clone(), move || {
+					let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+					let join_req = wireformat::read_frame(DeadlineReader { stream: &tcp_stream, deadline }, max_frame)?;
+					tcp_stream.set_read_timeout(None)?;
+					Ok(Incoming::Tcp(tcp_stream, join_req))
+				});
+			}
+		});
+		Ok(addr)
+	}
+
+	// Like `spawn_listen_loop`, but for UDP connections.
+	fn spawn_udp_listen_loop(address: &str, max_frame: u32, send: Sender<Incoming>) -> Result<SocketAddr> {
+		println!("------------------------------------");
+		println!(" Listening on {address} (UDP)");
+		println!("------------------------------------");
+		let listener = UdpListener::bind(address)?;
+		let addr = listener.local_addr()?;
+		let quit = Arc::new(atomic::AtomicBool::new(false));
+		let slots = HandshakeSlots::default();
+		thread::spawn(move || {
+			for conn in listener.incoming() {
+				if quit.load(atomic::Ordering::Relaxed) {
+					info!("listen: quitting");
+					return; // server quit, so stop worker thread.
 				}
+				let peer = conn.peer_addr();
+				let Some(slot) = slots.take(peer.ip()) else {
+					info!("{peer}: too many connections waiting to join, closing");
+					continue;
+				};
+				info!("accepted connection {peer}");
+				Self::spawn_handshake(peer.to_string(), slot, send.clone(), quit.clone(), move || {
+					let join_req = wireformat::read_frame(&conn.recv_timeout(HANDSHAKE_TIMEOUT)?[..], max_frame)?;
+					Ok(Incoming::Udp(conn, join_req))
+				});
 			}
 		});
-		Ok(recv)
+		Ok(addr)
+	}
+
+	// Wait for a new connection's `JoinRequest` on a thread of its own,
+	// then hand the connection to the server loop (see `handle_conn`).
+	// Clients that don't send one within `HANDSHAKE_TIMEOUT` are dropped.
+	// The handshake counts against `MAX_HANDSHAKES` until then (`slot`).
+	// Sets `quit` when the server is no longer listening.
+	fn spawn_handshake(peer: String, slot: HandshakeSlot, send: Sender<Incoming>, quit: Arc<atomic::AtomicBool>, read_join_req: impl FnOnce() -> Result<Incoming> + Send + 'static) {
+		thread::spawn(move || {
+			let result = read_join_req();
+			drop(slot);
+			match result {
+				Err(e) => info!("{peer}: no join request: {e}"),
+				Ok(incoming) => {
+					if send.send(incoming).is_err() {
+						quit.store(true, atomic::Ordering::Relaxed);
+					}
+				}
+			}
+		});
+	}
+}
+
+impl LocalConnector {
+	/// Join the server, like over the network.
+	/// Blocks until the server handles the request (on its next tick).
+	pub fn join(&self, join_req: JoinRequest) -> Result<LocalJoin> {
+		let (send, recv) = channel();
+		self.0.send(Incoming::Local(join_req, send)).map_err(|_| anyhow!("local server quit"))?;
+		recv.recv().map_err(|_| anyhow!("local server quit"))
+	}
+}
+
+impl ClientConn {
+	// Count the message just received against the rate limits.
+	fn check_rate(&mut self, now: Instant) -> Result<()> {
+		let bytes = self.pipe.bytes_received();
+		let result = self.rate_limit.spend(now, 1, bytes - self.bytes_counted);
+		self.bytes_counted = bytes;
+		result.context("rate limit exceeded")
+	}
+
+	// Number for the next ping, sent now.
+	fn start_ping(&mut self, now: Instant) -> u32 {
+		self.ping = (self.ping.0.wrapping_add(1), now);
+		self.ping.0
+	}
+
+	// Update the round-trip time estimate with the answer to ping `seq`.
+	// Answers to earlier pings (i.e. slower than `PING_INTERVAL`) are ignored.
+	// Measured at tick granularity, i.e. includes the time a message waits for the next server tick.
+	fn handle_pong(&mut self, seq: u32, now: Instant) -> Option<Duration> {
+		let (ping_seq, sent) = self.ping;
+		if seq != ping_seq {
+			return None;
+		}
+		let sample = now - sent;
+		let rtt = match self.rtt {
+			None => sample,
+			Some(rtt) => rtt.mul_f32(0.75) + sample.mul_f32(0.25),
+		};
+		self.rtt = Some(rtt);
+		Some(rtt)
 	}
 }
@@ -14,12 +14,14 @@ pub struct Player {
 	pub avatar_id: u8, // determines which avatar model is drawn (gl_client.rs).
 	pub team: Team,
 	pub health: i32,
-	pub spawned: bool, // playing or waiting for respawn?
+	pub spawned: bool,   // playing or waiting for respawn?
+	pub spectator: bool, // never spawns (see `SpectatorCam`)
 	//pub powerup: Option<EKind>,
 	pub invulnerability_ttl: Option<f32>, // seconds of invulnerability left
 
 	// controlled locally, synced to server:
 	pub skeleton: Skeleton, // fully determines player position
+	pub weapon: u8,         // index in `World::weapons`
 
 	// controlled locally, not synced:
 	pub local: LocalState,
@@ -38,7 +40,6 @@ pub struct LocalState {
 
 const FEET_ANIM_SPEED: f32 = 12.0;
 const FEET_ANIM_DAMP: f32 = 6.0;
-const DEFAULT_GUN_COOLDOWN: f32 = 0.7; // seconds
 
 impl Player {
 	// Note: total height is intended to be just under 16.0 units (equivalent 2m).
@@ -48,7 +49,8 @@ impl Player {
 	pub const WIDTH: f32 = 4.0;
 	pub const CAM_HEIGHT: f32 = Self::HEIGHT - Self::HEAD_HEIGHT / 2.0;
 	pub const WALK_SPEED: f32 = 48.0;
-	const JUMP_SPEED: f32 = 48.0;
+	pub const JUMP_SPEED: f32 = 48.0;
+	pub const MAX_HEALTH: i32 = 100;
 
 	pub fn new(id: ID, position: vec3, orientation: Orientation, name: String, avatar_id: u8, team: Team) -> Self {
 		Self {
@@ -56,10 +58,12 @@ impl Player {
 			name,
 			avatar_id,
 			spawned: false,
-			health: 100,
+			spectator: false,
+			health: Self::MAX_HEALTH,
 			team,
 			invulnerability_ttl: None,
 			skeleton: Skeleton::new(position, orientation, Self::WIDTH, Self::HEIGHT),
+			weapon: 0,
 			local: default(),
 		}
 	}
@@ -68,11 +72,13 @@ impl Player {
 
 	/// Record a diff for controlling this player with keyboard/mouse input.
 	/// Called on a clone of the World's player (!so need to be careful for self-interaction!).
-	pub fn control(&mut self, upd: &mut ClientMsgs, input_state: &InputState, world: &World, dt: f32) {
+	/// `view_time` is the server time of the world as currently seen by the player (see `HitClaim`).
+	pub fn control(&mut self, upd: &mut ClientMsgs, input_state: &InputState, world: &World, view_time: f64, dt: f32) {
 		if self.spawned {
 			self.control_movement(upd, input_state, world, dt);
-			self.control_shooting(upd, input_state, world, dt);
-		upd.push(MovePlayerIfSpawned(self.skeleton.frame()));
+			self.control_weapon(upd, input_state, world);
+			self.control_shooting(upd, input_state, world, view_time, dt);
+			upd.push(MovePlayerIfSpawned(self.skeleton.frame()));
 		} else {
 			self.set_orientation(input_state);
 			if input_state.is_pressed(Button::MOUSE1) {
@@ -83,7 +89,21 @@ impl Player {
 
 	// __________________ shooting
 
-	fn control_shooting(&mut self, upd: &mut ClientMsgs, input_state: &InputState, world: &World, dt: f32) {
+	// Number keys pick a weapon, the mouse wheel cycles through them.
+	fn control_weapon(&mut self, upd: &mut ClientMsgs, input_state: &InputState, world: &World) {
+		let n = world.weapons.len() as i32;
+		let weapon = match (input_state.number_pressed(), input_state.mouse_wheel_delta()) {
+			(Some(key), _) if (key as i32) <= n => key - 1,
+			(_, 0) => return,
+			(_, delta) => (self.weapon as i32 + delta).rem_euclid(n) as u8,
+		};
+		if weapon != self.weapon {
+			self.weapon = weapon;
+			upd.push(SwitchWeapon(weapon));
+		}
+	}
+
+	fn control_shooting(&mut self, upd: &mut ClientMsgs, input_state: &InputState, world: &World, view_time: f64, dt: f32) {
 		// not allowed to shoot if gun is still cooling down.
 		self.local.gun_cooldown -= dt;
 		if self.local.gun_cooldown > 0.0 {
@@ -91,42 +111,58 @@ impl Player {
 		}
 
 		if input_state.is_pressed(Button::MOUSE1) {
-			self.shoot(upd, world, dt)
+			self.shoot(upd, world, view_time, dt)
 		} // else if input_state.is_down(Button::MOUSE1) && self.can_shoot_berserk(world) {
 		 //	self.shoot(upd, world, dt)
 		 //}
 	}
 
-	fn shoot(&mut self, upd: &mut ClientMsgs, world: &World, _dt: f32) {
+	// Fire the current weapon: each pellet flies in its own direction (see `Weapon::spread`),
+	// up to the weapon's range.
+	fn shoot(&mut self, upd: &mut ClientMsgs, world: &World, view_time: f64, _dt: f32) {
 		// shooting, so gun will need to cool down before next shot is allowed.
 		self.local.gun_cooldown = self.gun_cooldown(world);
 
+		let weapon = world.weapons.get(self.weapon);
 		let line_of_fire = self.line_of_fire(world);
 		let start = line_of_fire.start.to_f32();
-		let end = self.shoot_at(world);
-		let delta = end - start;
-		let len = delta.len();
-		let dir = delta.safe_normalized();
-		let orientation = Orientation {
-			yaw: f32::atan2(dir.x(), dir.z()),
-			pitch: -f32::asin(dir.y()),
-		};
+		let color = weapon.color.unwrap_or(self.team.color_filter());
+
+		upd.push(ClientMsg::PlaySound(SoundEffect::spatial(weapon.fire_sound().to_owned(), self.position(), 30.0)));
 
-		upd.push(ClientMsg::AddEffect(Effect::particle_beam(start, orientation, len, self.team.color_filter())));
-
-		upd.push(ClientMsg::PlaySound(SoundEffect::spatial(
-			pick_random_clip(&["bang1", "bang2", "bang3", "bang4"]),
-			self.position(),
-			30.0,
-		)));
-		upd.push(ClientMsg::PlaySound(SoundEffect::spatial(
-			pick_random_clip(&["ricochet1", "ricochet2", "ricochet3", "ricochet4"]),
-			end,
-			1.0,
-		)));
-
-		if let Some((_, Some(victim_id))) = world.intersect_except_player(self.id, &line_of_fire) {
-			upd.push(HitPlayer(victim_id));
+		// the server flies projectiles, and tells everyone what they hit.
+		if weapon.kind == WeaponKind::Projectile {
+			upd.push(FireProjectile(line_of_fire));
+			return;
+		}
+
+		let mut impact = None;
+		for _ in 0..weapon.pellets {
+			let dir = weapon.pellet_dir(line_of_fire.dir.to_f32());
+			let pellet = Ray64::new(line_of_fire.start, dir.into());
+			let hit = world.intersect_except_player(self.id, &pellet).filter(|&(t, _)| t <= weapon.range as f64);
+			let len = hit.map(|(t, _)| t as f32).unwrap_or(weapon.range);
+			let orientation = Orientation {
+				yaw: f32::atan2(dir.x(), dir.z()),
+				pitch: -f32::asin(dir.y()),
+			};
+			upd.push(ClientMsg::AddEffect(Effect::particle_beam(start, orientation, len, color)));
+
+			if let Some((t, victim)) = hit {
+				impact.get_or_insert(pellet.at(t).to_f32());
+				if let Some(victim) = victim {
+					upd.push(HitPlayer(HitClaim {
+						victim,
+						line_of_fire: pellet,
+						view_time,
+					}));
+				}
+			}
+		}
+
+		// one impact sound per shot, not per pellet.
+		if let Some(impact) = impact {
+			upd.push(ClientMsg::PlaySound(SoundEffect::spatial(weapon.impact_sound().to_owned(), impact, 1.0)));
 		}
 
 		// effect when shooting lava
@@ -152,21 +188,7 @@ impl Player {
 	//}
 
 	fn gun_cooldown(&self, world: &World) -> f32 {
-		DEFAULT_GUN_COOLDOWN
-		// use EKind::*;
-		// const FAST: f32 = 0.05;
-		// match self.powerup {
-		// 	Some(CowboyHat) => FAST,
-		// 	Some(BerserkerHelmet) => FAST,
-		// 	Some(PartyHat) => {
-		// 		if self.is_on_lava(world) {
-		// 			FAST
-		// 		} else {
-		// 			DEFAULT_GUN_COOLDOWN
-		// 		}
-		// 	}
-		// 	_ => DEFAULT_GUN_COOLDOWN,
-		// }
+		world.weapons.get(self.weapon).cooldown
 	}
 
 	// __________________ movement
@@ -228,7 +250,7 @@ impl Player {
 		self.skeleton.position = position
 	}
 
-	fn set_orientation(&mut self, inputs: &Inputs) {
+	pub fn set_orientation(&mut self, inputs: &Inputs) {
 		let mouse_sens = 0.00075;
 		self.skeleton.orientation.yaw = wrap_angle(self.skeleton.orientation.yaw - inputs.mouse_delta().x() * mouse_sens);
 		self.skeleton.orientation.pitch = (self.skeleton.orientation.pitch + inputs.mouse_delta().y() * mouse_sens).clamp(-89.0 * DEG, 89.0 * DEG);
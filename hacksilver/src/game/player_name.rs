@@ -0,0 +1,68 @@
+/// Longest allowed player name, in characters.
+pub const MAX_NAME_LEN: usize = 16;
+
+/// Name given to players who did not pick a usable one.
+const DEFAULT_NAME: &str = "player";
+
+/// Turn whatever a client asked to be called into a name that
+/// fits the HUD and can be typed in text commands (`kill <name>`, `tell <name> ...`):
+/// printable ASCII only, no spaces (replaced by `_`), at most `MAX_NAME_LEN` characters.
+pub fn sanitize_name(name: &str) -> String {
+	let name = name
+		.trim()
+		.chars()
+		.filter_map(|c| match c {
+			' ' => Some('_'),
+			c if c.is_ascii_graphic() => Some(c),
+			_ => None,
+		})
+		.take(MAX_NAME_LEN)
+		.collect::<String>();
+	match name.is_empty() {
+		true => DEFAULT_NAME.into(),
+		false => name,
+	}
+}
+
+/// `name`, or `name` with a number appended (`alice2`, `alice3`, ...)
+/// if `taken(name)` (names are compared case-insensitively by the caller).
+/// The result is still at most `MAX_NAME_LEN` characters.
+pub fn unique_name(name: &str, taken: impl Fn(&str) -> bool) -> String {
+	if !taken(name) {
+		return name.into();
+	}
+	(2..)
+		.map(|i| {
+			let suffix = i.to_string();
+			let base = name.chars().take(MAX_NAME_LEN - suffix.len()).collect::<String>();
+			base + &suffix
+		})
+		.find(|candidate| !taken(candidate))
+		.unwrap()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn sanitize() {
+		assert_eq!(sanitize_name("alice"), "alice");
+		assert_eq!(sanitize_name("  big bob "), "big_bob");
+		assert_eq!(sanitize_name("\x1b[31mred\x07"), "[31mred");
+		assert_eq!(sanitize_name("żółw"), "w");
+		assert_eq!(sanitize_name(""), "player");
+		assert_eq!(sanitize_name("\n\t"), "player");
+		assert_eq!(sanitize_name(&"x".repeat(300)).len(), MAX_NAME_LEN);
+	}
+
+	#[test]
+	fn unique() {
+		let taken = ["alice", "alice2", "bob", "xxxxxxxxxxxxxxxx"];
+		let taken = |name: &str| taken.iter().any(|t| t.eq_ignore_ascii_case(name));
+		assert_eq!(unique_name("carol", taken), "carol");
+		assert_eq!(unique_name("bob", taken), "bob2");
+		assert_eq!(unique_name("alice", taken), "alice3");
+		assert_eq!(unique_name("xxxxxxxxxxxxxxxx", taken), "xxxxxxxxxxxxxxx2");
+	}
+}
@@ -0,0 +1,91 @@
+use super::internal::*;
+
+/// A rocket, grenade,... in flight, fired by a `WeaponKind::Projectile` weapon.
+///
+/// Simulated by the server (see `GameLogic::tick_projectiles`), which tells clients when one
+/// is fired (`ServerMsg::AddProjectile`) and when it explodes (`ServerMsg::RemoveProjectile`).
+/// In between, clients extrapolate the flight themselves (it only depends on the map and gravity).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Projectile {
+	pub owner: ID,
+	pub weapon: u8, // index in `World::weapons`
+	pub position: vec3,
+	pub velocity: vec3,
+	pub ttl: f32, // seconds until it explodes on its own (see `Weapon::fuse`)
+}
+
+/// Where a projectile exploded, and the player it hit directly, if any.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Impact {
+	pub pos: vec3,
+	pub victim: Option<ID>,
+}
+
+impl Projectile {
+	/// Fired by `owner` along `line_of_fire`.
+	pub fn new(owner: ID, weapon_idx: u8, weapon: &Weapon, line_of_fire: &Ray64) -> Self {
+		Self {
+			owner,
+			weapon: weapon_idx,
+			position: line_of_fire.start.to_f32(),
+			velocity: weapon.speed * line_of_fire.dir.to_f32().normalized(),
+			ttl: weapon.fuse(),
+		}
+	}
+
+	/// Fly for `dt` seconds, falling under the weapon's gravity.
+	/// Returns where it explodes if it hits the map or, if `hit_players`, a player other than its owner,
+	/// or when its fuse runs out.
+	///
+	/// Clients don't hit players: they show them in the past (see `Replica::render_time`),
+	/// so only the server can tell.
+	pub fn tick(&mut self, world: &World, dt: f32, hit_players: bool) -> Option<Impact> {
+		self.ttl -= dt;
+		self.velocity[Y] -= world.weapons.get(self.weapon).gravity * G * dt;
+
+		let delta = self.velocity * dt;
+		if delta != vec3::ZERO {
+			let ray = Ray64::new(self.position.into(), delta.normalized().into());
+			let hit = match hit_players {
+				true => world.intersect_except_player(self.owner, &ray),
+				false => world.map.intersect_t(&ray).map(|t| (t, None)),
+			};
+			if let Some((t, victim)) = hit.filter(|&(t, _)| t <= delta.len() as f64) {
+				self.position = ray.at(t).to_f32();
+				return Some(Impact { pos: self.position, victim });
+			}
+		}
+		self.position += delta;
+
+		match self.ttl <= 0.0 {
+			true => Some(Impact { pos: self.position, victim: None }),
+			false => None,
+		}
+	}
+
+	/// Seen from where, for interest management (see `Addressee::Near`).
+	pub fn place(&self) -> Place {
+		Place::point(self.position)
+	}
+}
+
+/// How hard an explosion of `radius` at `pos` hits a player with `bounds`:
+/// 1 at the center, falling off linearly to 0 at `radius` from the nearest point of the player.
+pub fn splash_falloff(pos: vec3, radius: f32, bounds: &BoundingBox<f32>) -> f32 {
+	let nearest = pos.zip(bounds.min, f32::max).zip(bounds.max, f32::min);
+	f32::max(0.0, 1.0 - (nearest - pos).len() / radius)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn falloff() {
+		let bounds = BoundingBox::new(vec3(0.0, 0.0, 0.0), vec3(4.0, 16.0, 4.0));
+		assert_eq!(splash_falloff(vec3(2.0, 8.0, 2.0), 10.0, &bounds), 1.0);
+		assert_eq!(splash_falloff(vec3(2.0, -5.0, 2.0), 10.0, &bounds), 0.5);
+		assert_eq!(splash_falloff(vec3(9.0, 8.0, 2.0), 10.0, &bounds), 0.5);
+		assert_eq!(splash_falloff(vec3(20.0, 8.0, 2.0), 10.0, &bounds), 0.0);
+	}
+}
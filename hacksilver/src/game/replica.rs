@@ -0,0 +1,282 @@
+use super::internal::*;
+
+/// A client's copy of the World, kept in sync with the server through `ServerMsg`s.
+///
+/// This is the headless part of a client: no graphics, sound or HUD.
+/// `ClientState` adds those on top, `Bot` uses it as is.
+pub struct Replica {
+	pub local_player_id: ID,
+	pub world: World,
+	pub pending_diffs: Vec<ClientMsg>,
+
+	// Snapshots received from the server, baselines for the next `UpdatePlayers`.
+	// Carried over on map switch.
+	pub snapshots: SnapshotHistory,
+
+	// Estimated server time of the world as we see it:
+	// last `ServerTime` received, advanced by local time since.
+	server_time: f64,
+	server_tick: Tick, // last `ServerTime` received
+
+	// Other players' frames as received from the server, timestamped with server time.
+	// They are shown `interpolation_delay` seconds in the past, interpolated between these frames.
+	remote_frames: FrameHistory,
+	interpolation_delay: f64,
+}
+
+/// Other players are extrapolated for at most this many seconds
+/// when no new frames arrive (e.g. network hiccup), then they freeze.
+const MAX_EXTRAPOLATE: f64 = 0.25;
+
+impl Replica {
+	/// `interpolation_delay` in seconds, see `NetworkOpts::interpolation_delay_ms`.
+	pub fn new(player_id: ID, world: World, interpolation_delay: f64) -> Self {
+		Self {
+			local_player_id: player_id,
+			world,
+			pending_diffs: default(),
+			snapshots: default(),
+			server_time: 0.0,
+			server_tick: 0,
+			remote_frames: default(),
+			interpolation_delay,
+		}
+	}
+
+	// __________________________________________________________ remote control
+
+	/// Apply a diff to the world.
+	/// Messages that don't change the world (sound, HUD, log) are left to the caller.
+	pub fn apply_server_msg(&mut self, msg: ServerMsg) {
+		use ServerMsg::*;
+		match msg {
+			UpdatePlayers(delta) => self.handle_update_players(delta),
+			UpdatePlayerFull(player) => self.handle_update_player_full(player),
+			ForceMovePlayer(position) => self.handle_force_move_player(position),
+			//UpdateEntity(entity) => self.handle_update_entity(entity),
+			//RemoveEntity(entity_id) => self.handle_remove_entity(entity_id),
+			AddEffect(effect) => self.handle_add_effect(effect),
+			AddProjectile(id, projectile) => self.handle_add_projectile(id, projectile),
+			RemoveProjectile(id) => self.handle_remove_projectile(id),
+			Knockback(velocity) => self.handle_knockback(velocity),
+			ServerTime(tick, time) => self.handle_server_time(tick, time),
+			PlaySound(_) | UpdateHUD(_) | Chat(_) | Log(_) => (/*presentation only, see ClientState*/),
+			SwitchMap(_) => panic!("TODO: SwitchMap currently handled by Client"),
+			MapDownload(_) => panic!("MapDownload is handled by Client"),
+			Ping(_) => panic!("Ping is handled by Client"),
+		}
+	}
+
+	// Reconstruct the server's latest snapshot from the delta and our copy of its baseline,
+	// update the players that changed and acknowledge.
+	// Position, orientation, velocity of the local player are controlled locally and left alone.
+	fn handle_update_players(&mut self, delta: SnapshotDelta) {
+		if self.snapshots.latest().map(|s| s.tick >= delta.tick).unwrap_or(false) {
+			return; // arrived out of order, already have something newer.
+		}
+		let baseline = match delta.baseline {
+			None => None,
+			Some(tick) => match self.snapshots.get(tick) {
+				Some(baseline) => Some(baseline),
+				None => return LOG.write(format!("snapshot {}: missing baseline {tick}", delta.tick)),
+			},
+		};
+		let snapshot = delta.apply(baseline);
+
+		if delta.baseline.is_none() {
+			for id in self.world.entities.players.copied_ids() {
+				if !snapshot.players.contains(id) {
+					self.world.entities.players.remove(id);
+				}
+			}
+		}
+		for &id in &delta.removed {
+			self.world.entities.players.remove(id);
+			self.remote_frames.clear(id);
+		}
+		for PlayerDelta { id, spawned, .. } in &delta.players {
+			if *spawned == Some(true) {
+				self.remote_frames.clear(*id); // (re)appeared: don't interpolate from where they were last seen.
+			}
+			let mut new = snapshot.players[*id].clone();
+			if let Some(old) = self.world.entities.players.get(*id) {
+				new.local = old.local.clone();
+				if *id == self.local_player_id {
+					new.skeleton.set_frame(old.skeleton.frame());
+					new.weapon = old.weapon; // switched locally, the server may not know yet
+				}
+			}
+			self.world.entities.players.insert(*id, new);
+		}
+
+		// Record every spawned player's frame, not just changed ones:
+		// a player that stood still since the last snapshot should not appear to drift.
+		for (id, player) in snapshot.players.iter() {
+			if id != self.local_player_id && player.spawned {
+				self.remote_frames.record(id, self.server_time, player.skeleton.frame());
+			}
+		}
+
+		if let Some(tick) = delta.baseline {
+			self.snapshots.forget_before(tick); // server will not use older baselines anymore
+		}
+		self.snapshots.push(snapshot, MAX_SNAPSHOTS);
+		self.pending_diffs.push(ClientMsg::AckSnapshot(delta.tick));
+	}
+
+	fn handle_server_time(&mut self, tick: Tick, time: f64) {
+		if tick > self.server_tick {
+			self.server_tick = tick;
+			self.server_time = time;
+		}
+	}
+
+	// Update the entire player (including frame).
+	// Server will only ask this when de-spawned, or to correct an implausible move.
+	fn handle_update_player_full(&mut self, new: Player) {
+		self.remote_frames.clear(new.id); // teleported: don't interpolate from the old position.
+		if let Some(player_mut) = self.world.entities.players.get_mut(new.id) {
+			*player_mut = new
+		}
+	}
+
+	fn handle_force_move_player(&mut self, position: vec3) {
+		self.local_player_mut().skeleton.position = position;
+	}
+
+	//fn handle_update_entity(&mut self, entity: Entity) {
+	//	self.world.entities.insert(entity.id(), entity);
+	//}
+
+	//fn handle_remove_entity(&mut self, entity_id: EID) {
+	//	self.world.entities.remove(&entity_id);
+	//}
+
+	pub fn handle_add_effect(&mut self, effect: Effect) {
+		self.world.entities.effects.push(effect);
+	}
+
+	fn handle_add_projectile(&mut self, id: ID, projectile: Projectile) {
+		self.world.entities.projectiles.insert(id, projectile);
+	}
+
+	// May already have been removed when it hit the map locally (see `tick_projectiles`).
+	fn handle_remove_projectile(&mut self, id: ID) {
+		self.world.entities.projectiles.remove(&id);
+	}
+
+	fn handle_knockback(&mut self, velocity: vec3) {
+		let player = self.local_player_mut();
+		if player.spawned {
+			player.skeleton.velocity += velocity;
+		}
+	}
+
+	// __________________________________________________________ local control
+
+	/// Advance local time by `inputs.dt()`, and control the local player (unless spectating).
+	/// The resulting messages for the server are queued in `pending_diffs`.
+	pub fn tick(&mut self, inputs: &Inputs) {
+		let dt = inputs.dt();
+		self.server_time += dt as f64;
+		if !self.local_player().spectator {
+			self.control_player(inputs, dt);
+		}
+		self.interpolate_other_players();
+		self.tick_projectiles(dt);
+		self.tick_effects(dt);
+	}
+
+	/// Control a player via keyboard/mouse
+	fn control_player(&mut self, input_state: &Inputs, dt: f32) {
+		let mut clone = self.local_player().clone();
+		let view_time = self.render_time();
+		clone.control(&mut self.pending_diffs, input_state, &self.world, view_time, dt);
+		*self.local_player_mut() = clone;
+	}
+
+	/// Server time at which other players are shown.
+	/// This is also the time at which we see them when shooting (see `HitClaim::view_time`).
+	pub fn render_time(&self) -> f64 {
+		self.server_time - self.interpolation_delay
+	}
+
+	/// Show other players where they were at `render_time`,
+	/// interpolated between the two frames received around that time.
+	/// If no frame was received after `render_time`, extrapolate based on their last known velocity
+	/// (for at most `MAX_EXTRAPOLATE`).
+	fn interpolate_other_players(&mut self) {
+		let time = self.render_time();
+		for (id, player) in self.world.entities.players.iter_mut() {
+			if id == self.local_player_id || !player.spawned {
+				continue;
+			}
+			let frame = match self.remote_frames.latest(id) {
+				None => continue,
+				Some((latest, frame)) if time > latest => {
+					let dt = f64::min(time - latest, MAX_EXTRAPOLATE) as f32;
+					frame.clone().with(|f| f.position += dt * f.velocity)
+				}
+				Some(_) => self.remote_frames.frame_at(id, time).unwrap(/*latest exists*/),
+			};
+			player.skeleton.set_frame(frame);
+		}
+	}
+
+	//___________________________________________________________________________ effects
+
+	/// Advance visual effects in time.
+	/// This is done locally (after creation,
+	/// visual effects do not need to synchronize over the network).
+	fn tick_effects(&mut self, dt: f32) {
+		Self::update_effects_ttl(&mut self.world.entities.effects, dt);
+	}
+
+	/// Extrapolate projectiles' flight until the server says they exploded,
+	/// leaving a trail behind. Projectiles that hit the map are removed right away,
+	/// those that pass through players keep flying: only the server knows if they hit (see `Projectile::tick`).
+	fn tick_projectiles(&mut self, dt: f32) {
+		let mut projectiles = mem::take(&mut self.world.entities.projectiles);
+		projectiles.retain(|_, p| {
+			let start = p.position;
+			let impact = p.tick(&self.world, dt, false);
+			let weapon = self.world.weapons.get(p.weapon);
+			let color = weapon
+				.color
+				.or_else(|| self.world.entities.players.get(p.owner).map(|owner| owner.team.color_filter()))
+				.unwrap_or(vec3::ONES);
+			self.world.entities.effects.push(Effect::projectile_trail(start, p.position, color));
+			impact.is_none()
+		});
+		self.world.entities.projectiles = projectiles;
+	}
+
+	// decrease effect's TTL by `dt` and remove effects past their TTL.
+	fn update_effects_ttl(effects: &mut Vec<Effect>, dt: f32) {
+		let mut i = 0;
+		while i < effects.len() {
+			effects[i].ttl -= dt;
+			if effects[i].ttl <= 0.0 {
+				effects.swap_remove(i);
+			} else {
+				i += 1;
+			}
+		}
+	}
+
+	// __________________________________________________________ accessors
+
+	/// Number of the latest server tick received (see `ServerMsg::ServerTime`).
+	pub fn server_tick(&self) -> Tick {
+		self.server_tick
+	}
+
+	/// The player controlled by this client.
+	pub fn local_player(&self) -> &Player {
+		&self.world.entities.players[self.local_player_id]
+	}
+
+	pub fn local_player_mut(&mut self) -> &mut Player {
+		&mut self.world.entities.players[self.local_player_id]
+	}
+}
@@ -26,6 +26,10 @@ impl Scores {
 		self.by_player.entry(id).or_default();
 	}
 
+	pub fn drop_player(&mut self, id: ID) {
+		self.by_player.remove(&id);
+	}
+
 	pub fn by_player(&mut self, id: ID) -> &mut Score {
 		self.by_player.entry(id).or_default()
 	}
@@ -0,0 +1,116 @@
+use super::internal::*;
+use std::fs::OpenOptions;
+use std::time::SystemTime;
+
+/// Who may run a server command (see `COMMANDS`).
+/// Players become admin with `login <password>` (see `ServerOpts::admin_password`).
+/// The server console (see `Console`) may run anything.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Privilege {
+	Player,
+	Admin,
+	Console,
+}
+
+/// A text command players can send to the server (`ClientMsg::Command`).
+pub struct CommandSpec {
+	pub name: &'static str,
+	pub usage: &'static str,
+	pub privilege: Privilege,
+	pub secret_args: bool, // never log the arguments (passwords)
+}
+
+use Privilege::*;
+
+pub const COMMANDS: &[CommandSpec] = &[
+	cmd("help", "help: list the commands you can use", Player),
+	cmd("status", "status: map, time left, scores", Player),
+	cmd("players", "players: who's playing, with scores and ping", Player),
+	cmd("maplist", "maplist: maps played in turn", Player),
+	cmd("say", "say <message>", Player),
+	cmd("say_team", "say_team <message>: to your team only", Player),
+	cmd("tell", "tell <player> <message>: to one player only", Player),
+	cmd("name", "name <new_name>: change your name", Player),
+	cmd("team", "team <red|blue|green|auto>: switch teams (auto: the smallest)", Player),
+	CommandSpec {
+		secret_args: true,
+		..cmd("login", "login <password>: become admin", Player)
+	},
+	cmd("logout", "logout: give up admin rights", Player),
+	cmd("switch", "switch [map]: go to the next map, or the given one", Admin),
+	cmd("restart", "restart: start the current map over", Admin),
+	cmd("kill", "kill <player>", Admin),
+	cmd("kick", "kick <player>: remove from the game (they may rejoin)", Admin),
+	cmd("ban", "ban <player>: remove from the game, refuse their IP address until the server restarts", Admin),
+	cmd("fraglimit", "fraglimit <frags>", Admin),
+	cmd("timelimit", "timelimit <seconds>", Admin),
+	cmd("quit", "quit: stop the server", Console),
+];
+
+const fn cmd(name: &'static str, usage: &'static str, privilege: Privilege) -> CommandSpec {
+	CommandSpec {
+		name,
+		usage,
+		privilege,
+		secret_args: false,
+	}
+}
+
+impl CommandSpec {
+	/// The command spec for command line `cmd`, e.g. `kick bob`.
+	pub fn find(cmd: &str) -> Option<&'static Self> {
+		let name = cmd.split_ascii_whitespace().next()?;
+		COMMANDS.iter().find(|spec| spec.name == name)
+	}
+
+	/// The command line as it may be logged, e.g. `login ***`.
+	pub fn redact(&self, cmd: &str) -> String {
+		match self.secret_args {
+			true => format!("{} ***", self.name),
+			false => cmd.into(),
+		}
+	}
+}
+
+/// Record of privileged commands: who ran what, and whether it was allowed.
+/// Goes to the server log (target `audit`), and to a file if configured (see `ServerOpts::audit_log`).
+pub struct AuditLog {
+	file: Option<File>,
+}
+
+impl AuditLog {
+	/// Append to `file`, if any.
+	pub fn open(file: Option<&str>) -> Result<Self> {
+		let file = match file {
+			None => None,
+			Some(path) => Some(OpenOptions::new().create(true).append(true).open(path).map_err(|e| anyhow!("audit log {path}: {e}"))?),
+		};
+		Ok(Self { file })
+	}
+
+	/// E.g. `record("alice (#3)", "kick bob", "ok")`.
+	pub fn record(&mut self, who: &str, cmd: &str, outcome: &str) {
+		let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+		let line = format!("{time} {who}: {cmd}: {outcome}");
+		info!(target: "audit", "{line}");
+		if let Some(file) = &mut self.file {
+			if let Err(e) = writeln!(file, "{line}") {
+				error!("audit log: {e}");
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn find_and_redact() {
+		assert_eq!(CommandSpec::find("kick  bob").unwrap().privilege, Admin);
+		assert_eq!(CommandSpec::find("say hi").unwrap().privilege, Player);
+		assert!(CommandSpec::find("sudo kick bob").is_none());
+		assert_eq!(CommandSpec::find("login hunter2").unwrap().redact("login hunter2"), "login ***");
+		assert_eq!(CommandSpec::find("kick bob").unwrap().redact("kick bob"), "kick bob");
+	}
+}
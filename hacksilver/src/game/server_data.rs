@@ -5,6 +5,16 @@ use ServerMsg::*;
 pub struct ServerData {
 	pub world: DiffWorld,
 	diffs: Diffs,
+	history: FrameHistory,
+	time: f64,
+
+	// Recent snapshots as seen by each client (see `view_of`), and the latest one each acknowledged.
+	tick: Tick,
+	snapshots: HashMap<ID, SnapshotHistory>,
+	acked: HashMap<ID, Tick>,
+	interest_radius: Option<u32>, // in zones, see `perceives`
+
+	pings: HashMap<ID, Duration>, // round-trip time per client
 }
 
 /// Seconds to show HUD announcements like "You fragged Foo".
@@ -12,13 +22,97 @@ const ANNOUNCE_TTL: f32 = 5.0;
 const ANN_VOLUME: f32 = 1.0;
 
 impl ServerData {
-	pub fn new(world: World) -> Self {
+	/// With `interest_radius` (in zones), players only learn about what happens that far from them
+	/// (see `perceives`). `None`: everyone learns about everything.
+	pub fn new(world: World, interest_radius: Option<u32>) -> Self {
 		Self {
 			world: DiffWorld::new(world),
 			diffs: default(),
+			history: default(),
+			time: 0.0,
+			tick: 0,
+			snapshots: default(),
+			acked: default(),
+			interest_radius,
+			pings: default(),
+		}
+	}
+
+	//-------------------------------------------------------------------------------- time
+
+	/// Seconds since the server started (advanced by `tick_time`).
+	pub fn time(&self) -> f64 {
+		self.time
+	}
+
+	/// Start the next tick: stamp this tick's messages with its number, and the time
+	/// at which the moves received so far were recorded. Then advance the server clock.
+	pub fn tick_time(&mut self, dt: f32) {
+		self.tick += 1;
+		self.diffs.push_front(ServerTime(self.tick, self.time).to_all());
+		self.time += dt as f64;
+	}
+
+	/// Number of the current tick (see `ServerMsg::ServerTime`).
+	pub fn tick(&self) -> Tick {
+		self.tick
+	}
+
+	//-------------------------------------------------------------------------------- snapshots
+
+	/// Take a snapshot of all players and send each client
+	/// what changed (as far as they can tell, see `view_of`) since the last snapshot they acknowledged.
+	/// At most one per tick (`tick_time`).
+	pub fn push_snapshots(&mut self) {
+		let snapshot = Snapshot {
+			tick: self.tick,
+			players: self.world.entities.players.clone(),
+		};
+
+		for id in self.players() {
+			let view = self.view_of(&snapshot, id);
+			let history = self.snapshots.entry(id).or_default();
+			let baseline = self.acked.get(&id).and_then(|&tick| history.get(tick));
+			let delta = SnapshotDelta::new(baseline, &view, id);
+			self.diffs.push(UpdatePlayers(delta).to_just(id));
+			history.push(view, MAX_SNAPSHOTS);
 		}
 	}
 
+	// `snapshot` as seen by player `viewer`: players they cannot perceive (see `perceives`)
+	// are shown despawned, without their position. So a player leaving the viewer's interest
+	// disappears from their view, and reappears (with `spawned` set) when coming back.
+	fn view_of(&self, snapshot: &Snapshot, viewer: ID) -> Snapshot {
+		snapshot.clone().with(|view| {
+			for (id, player) in view.players.iter_mut() {
+				if id != viewer && !self.perceives(viewer, &Place::point(player.center())) {
+					player.spawned = false;
+					player.skeleton.set_frame(Frame::default());
+				}
+			}
+		})
+	}
+
+	/// Can player `viewer` see or hear what happens at `place`?
+	/// Spectators perceive everything: they fly around freely, the server does not know where.
+	pub fn perceives(&self, viewer: ID, place: &Place) -> bool {
+		let viewpoint = self.player(viewer).filter(|p| !p.spectator).map(|p| p.camera().position);
+		match (self.interest_radius, viewpoint) {
+			(Some(radius), Some(viewpoint)) => place.perceivable_from(viewpoint, radius),
+			_ => true,
+		}
+	}
+
+	/// Client `id` received the snapshot at `tick`.
+	/// Acks may arrive out of order, only the latest one counts.
+	pub fn ack_snapshot(&mut self, id: ID, tick: Tick) {
+		if tick > self.tick {
+			return; // bogus, we never sent that.
+		}
+		let acked = self.acked.entry(id).or_default();
+		*acked = Tick::max(*acked, tick);
+	}
+
 	//-------------------------------------------------------------------------------- player
 
 	/// Player by entitiy ID.
@@ -31,6 +125,18 @@ impl ServerData {
 		self.world.entities.players.copied_ids()
 	}
 
+	/// List all player IDs, except spectators (does not borrow).
+	pub fn playing_players(&self) -> impl Iterator<Item = ID> {
+		self.world
+			.entities
+			.players
+			.iter()
+			.filter(|(_, p)| !p.spectator)
+			.map(|(id, _)| id)
+			.collect::<SmallVec<[_; 16]>>()
+			.into_iter()
+	}
+
 	/// List all currently spawned player IDs (does not borrow).
 	pub fn spawned_player_ids(&self) -> impl Iterator<Item = ID> {
 		self.world
@@ -58,6 +164,15 @@ impl ServerData {
 		self.players().find(|&id| self.player_name(id).map(|name| name.eq_ignore_ascii_case(player_name)).unwrap_or(false))
 	}
 
+	pub fn set_ping(&mut self, id: ID, rtt: Duration) {
+		self.pings.insert(id, rtt);
+	}
+
+	/// Round-trip time to a player's client, if measured yet.
+	pub fn ping(&self, id: ID) -> Option<Duration> {
+		self.pings.get(&id).copied()
+	}
+
 	/// Despawn player.
 	pub fn despawn(&mut self, victim: ID) -> Option<()> {
 		trace!("despawn {victim}");
@@ -65,32 +180,91 @@ impl ServerData {
 	}
 
 	pub fn move_player_if_spawned(&mut self, id: ID, frame: Frame) {
-		self.world.move_player_if_spawned(&mut self.diffs, id, frame);
+		self.world.move_player_if_spawned(&mut self.history, self.time, id, frame);
 	}
 
 	pub fn drop_player(&mut self, id: ID) {
-		self.world.drop_player(&mut self.diffs, id)
+		self.history.clear(id);
+		self.acked.remove(&id);
+		self.snapshots.remove(&id);
+		self.pings.remove(&id);
+		self.world.drop_player(id)
+	}
+
+	/// Intersect a ray with the map and all players except `player_id`,
+	/// with players rewound to where they were at server time `time`.
+	/// Returns intersection distance along the ray and the ID of the nearest hit player, if any.
+	pub fn intersect_rewound(&self, player_id: ID, ray: &Ray64, time: f64) -> Option<(f64, Option<ID>)> {
+		let mut nearest = self.world.map.intersect_t(ray).map(|t| (t, None));
+		for (id, player) in self.world.entities.players.iter() {
+			if id == player_id || !player.spawned {
+				continue;
+			}
+			let frame = self.history.frame_at(id, time).unwrap_or_else(|| player.skeleton.frame());
+			let bounds = player.skeleton.clone().with(|s| s.set_frame(frame)).bounds();
+			if let Some(t) = bounds.convert::<f64>().intersect(ray) {
+				if t < nearest.map(|(t, _)| t).unwrap_or(f64::INFINITY) {
+					nearest = Some((t, Some(id)));
+				}
+			}
+		}
+		nearest
+	}
+
+	/// Is `pos` in the head of player `id`, rewound to where they were at server time `time`
+	/// (see `intersect_rewound`)?
+	pub fn hits_head_rewound(&self, id: ID, pos: vec3, time: f64) -> bool {
+		let Some(player) = self.world.entities.players.get(id) else { return false };
+		let frame = self.history.frame_at(id, time).unwrap_or_else(|| player.skeleton.frame());
+		pos.y() >= frame.position.y() + Player::TORSO_HEIGHT
 	}
 
 	/// Apply any change to a player.
 	pub fn apply_to_player_partial<F: Fn(&mut Player)>(&mut self, id: ID, f: F) -> Option<()> {
-		self.world.apply_to_player_partial(&mut self.diffs, id, f)
+		self.world.apply_to_player_partial(id, f)
 	}
 
 	/// Apply any change to a player.
+	/// Forgets the player's frame history, as this may teleport them.
 	pub fn apply_to_player_full<F: Fn(&mut Player)>(&mut self, id: ID, f: F) -> Option<()> {
+		self.history.clear(id);
 		self.world.force_apply_to_full(&mut self.diffs, id, f)
 	}
 
-	pub fn join_new_player(&mut self, spawn_point: &SpawnPoint, join_msg: JoinRequest) -> (ID, MapSwitch) {
-		self.world.join_new_player(&mut self.diffs, &spawn_point, join_msg)
+	pub fn rejoin_player(&mut self, player: Player, map_hash: u64) -> MapSwitch {
+		self.world.rejoin_player(player, map_hash)
+	}
+
+	pub fn join_new_player(&mut self, spawn_point: &SpawnPoint, join_msg: JoinRequest, team: Team, map_hash: u64) -> (ID, MapSwitch) {
+		self.world.join_new_player(&spawn_point, join_msg, team, map_hash)
+	}
+
+	//-------------------------------------------------------------------------------- projectiles
+
+	pub fn add_projectile(&mut self, projectile: Projectile) -> ID {
+		self.world.add_projectile(&mut self.diffs, projectile)
+	}
+
+	/// Move all projectiles by `dt`, return those that exploded (see `DiffWorld::tick_projectiles`).
+	pub fn tick_projectiles(&mut self, dt: f32) -> Vec<(ID, Impact)> {
+		self.world.tick_projectiles(dt)
+	}
+
+	pub fn remove_projectile(&mut self, id: ID) -> Option<Projectile> {
+		self.world.remove_projectile(&mut self.diffs, id)
+	}
+
+	/// Push a player away (see `ServerMsg::Knockback`).
+	/// Their client applies it: players control their own movement.
+	pub fn knockback(&mut self, id: ID, velocity: vec3) {
+		self.diffs.push(Knockback(velocity).to_just(id))
 	}
 
 	//-------------------------------------------------------------------------------- effects
 
-	/// Spawn an effect (for all players).
+	/// Spawn an effect (for all players who can see it).
 	pub fn add_effect(&mut self, effect: Effect) {
-		self.diffs.push(AddEffect(effect).to_all())
+		self.diffs.push(AddEffect(effect.clone()).to(Addressee::Near(effect.place(), None)))
 	}
 
 	pub fn sound_announce(&mut self, to: Addressee, clip_name: &'static str) {
@@ -162,7 +336,7 @@ impl ServerData {
 
 	//-------------------------------------------------------------------------------- map
 
-	pub fn switch_map(&mut self, map: Map) {
-		self.world.switch_map(&mut self.diffs, map)
+	pub fn switch_map(&mut self, map: Map, map_hash: u64) {
+		self.world.switch_map(&mut self.diffs, map, map_hash)
 	}
 }
@@ -13,6 +13,130 @@ pub struct ServerOpts {
 	pub frag_limit: u32,
 
 	pub time_limit: u32,
+
+	#[arg(long, value_enum, default_value_t)]
+	#[serde(default)]
+	pub transport: Transport,
+
+	#[arg(long, default_value_t = 16)]
+	#[serde(default = "default_max_players")]
+	pub max_players: u32,
+
+	/// Keep teams even: when a player leaves a team with 2 or more players less than another,
+	/// move someone over. Refuse `team` commands that would make teams uneven.
+	#[arg(long)]
+	#[serde(default)]
+	pub team_balance: bool,
+
+	/// Server ticks per second: how often the game is updated and sent to clients.
+	#[arg(long, default_value_t = 30)]
+	#[serde(default = "default_tick_rate")]
+	pub tick_rate: u32,
+
+	/// IP addresses not allowed to join.
+	#[arg(long)]
+	#[serde(default)]
+	pub banned: Vec<String>,
+
+	/// Seconds a disconnected player's slot and score are kept for them to reconnect.
+	#[arg(long, default_value_t = 60)]
+	#[serde(default = "default_reconnect_grace")]
+	pub reconnect_grace: u32,
+
+	/// UDP port for status queries and LAN discovery (see `find_servers`).
+	#[arg(long, default_value_t = DEFAULT_QUERY_PORT)]
+	#[serde(default = "default_query_port")]
+	pub query_port: u16,
+
+	/// Largest message accepted from a client, in bytes.
+	#[arg(long, default_value_t = wireformat::DEFAULT_MAX_FRAME)]
+	#[serde(default = "default_max_message_bytes")]
+	pub max_message_bytes: u32,
+
+	/// Clients sending more messages per second (sustained) are disconnected.
+	#[arg(long, default_value_t = 2000)]
+	#[serde(default = "default_max_msgs_per_sec")]
+	pub max_msgs_per_sec: u32,
+
+	/// Clients sending more bytes per second (sustained) are disconnected.
+	#[arg(long, default_value_t = 256 * 1024)]
+	#[serde(default = "default_max_bytes_per_sec")]
+	pub max_bytes_per_sec: u32,
+
+	/// Clients that don't send anything for this many seconds (not even answering pings) are dropped.
+	#[arg(long, default_value_t = 30)]
+	#[serde(default = "default_idle_timeout")]
+	pub idle_timeout: u32,
+
+	/// Simulate a bad network to all clients, for testing (see `NetSimOpts`).
+	#[arg(long)]
+	#[serde(default)]
+	pub netsim: Option<NetSimOpts>,
+
+	/// Players who `login` with this password may use admin commands (switch, kick, ban,...).
+	/// Without a password, nobody can.
+	#[arg(long)]
+	#[serde(default)]
+	pub admin_password: Option<String>,
+
+	/// File to append the audit log of admin commands to (see `AuditLog`).
+	#[arg(long)]
+	#[serde(default)]
+	pub audit_log: Option<String>,
+
+	/// Unix socket to accept console commands on, in addition to stdin (see `Console`).
+	#[arg(long)]
+	#[serde(default)]
+	pub console_socket: Option<String>,
+
+	/// Don't read console commands from stdin,
+	/// e.g. when running in the background (`serve &`), where reading would stop the server.
+	#[arg(long)]
+	#[serde(default)]
+	pub no_stdin_console: bool,
+
+	/// Other players' positions, effects and spatial sounds are only sent to players
+	/// within this many zones (see `ZONE_SIZE`). 0: send to everyone.
+	/// Never less than the longest weapon range (see `Weapon::range`): players must see whom they can shoot.
+	#[arg(long, default_value_t = 32)]
+	#[serde(default = "default_interest_radius")]
+	pub interest_radius: u32,
+}
+
+fn default_max_players() -> u32 {
+	16
+}
+
+fn default_tick_rate() -> u32 {
+	30
+}
+
+fn default_reconnect_grace() -> u32 {
+	60
+}
+
+fn default_query_port() -> u16 {
+	DEFAULT_QUERY_PORT
+}
+
+fn default_max_message_bytes() -> u32 {
+	wireformat::DEFAULT_MAX_FRAME
+}
+
+fn default_max_msgs_per_sec() -> u32 {
+	2000
+}
+
+fn default_max_bytes_per_sec() -> u32 {
+	256 * 1024
+}
+
+fn default_idle_timeout() -> u32 {
+	30
+}
+
+fn default_interest_radius() -> u32 {
+	32 // the rifle's range (4000)
 }
 
 impl Default for ServerOpts {
@@ -22,6 +146,23 @@ impl Default for ServerOpts {
 			maplist: vec![],
 			frag_limit: 20,
 			time_limit: 460,
+			transport: default(),
+			max_players: default_max_players(),
+			team_balance: false,
+			tick_rate: default_tick_rate(),
+			banned: vec![],
+			reconnect_grace: default_reconnect_grace(),
+			query_port: default_query_port(),
+			max_message_bytes: default_max_message_bytes(),
+			max_msgs_per_sec: default_max_msgs_per_sec(),
+			max_bytes_per_sec: default_max_bytes_per_sec(),
+			idle_timeout: default_idle_timeout(),
+			netsim: None,
+			admin_password: None,
+			audit_log: None,
+			console_socket: None,
+			no_stdin_console: false,
+			interest_radius: default_interest_radius(),
 		}
 	}
 }
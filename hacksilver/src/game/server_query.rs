@@ -0,0 +1,338 @@
+use super::internal::*;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::SocketAddr;
+use std::net::ToSocketAddrs;
+use std::net::UdpSocket;
+
+/// UDP port on which servers answer status queries (see `ServerOpts::query_port`).
+/// Clients probe this port on configured servers, and broadcast to it for LAN discovery.
+pub const DEFAULT_QUERY_PORT: u16 = 3345;
+
+// Queries answered per server tick, so that the server can't be used to flood others with (larger) responses.
+const MAX_ANSWERS_PER_TICK: usize = 16;
+
+// Replies sent to any one IP address per `REPLY_WINDOW`
+// (enough for a server browser refreshing: LAN broadcast + configured address, challenge + status each).
+const MAX_REPLIES_PER_ADDR: u32 = 8;
+const REPLY_WINDOW: Duration = Duration::from_secs(1);
+
+// Queries are padded to at least this size (see `StatusQuery::padding`), smaller datagrams are ignored.
+// This way, a challenge is never larger than the query it answers.
+const MIN_QUERY_SIZE: usize = 64;
+
+// A cookie is valid for one to two of these.
+const COOKIE_LIFETIME_SECS: u64 = 30;
+
+// Large enough for a status with dozens of players.
+const MAX_DATAGRAM: usize = 8192;
+
+/// Asks a server for its `ServerStatus`, without joining.
+///
+/// UDP source addresses are easily spoofed, so a server only sends its (large) status to clients
+/// that echo the `cookie` from a `QueryResponse::Challenge`, proving they receive at that address.
+/// Otherwise, the server could be used to flood a victim with responses.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StatusQuery {
+	pub nonce: u64,       // echoed in the response, to ignore answers to earlier queries.
+	pub cookie: u64,      // 0 at first, then the one from the server's `QueryResponse::Challenge`.
+	pub padding: Vec<u8>, // up to `MIN_QUERY_SIZE`, ignored.
+}
+
+impl StatusQuery {
+	fn new(nonce: u64, cookie: u64) -> Self {
+		Self {
+			nonce,
+			cookie,
+			padding: vec![0; MIN_QUERY_SIZE],
+		}
+	}
+}
+
+/// A server's answer to a `StatusQuery`.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum QueryResponse {
+	/// Ask again, with this cookie.
+	Challenge {
+		nonce: u64,
+		cookie: u64,
+	},
+	Status(ServerStatus),
+}
+
+/// What a server tells anyone who asks (see `StatusQuery`).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServerStatus {
+	pub nonce: u64,
+	pub protocol_version: u32,
+	pub game_port: u16, // port to connect to for playing, on the same host.
+	pub transport: Transport,
+	pub map_name: String,
+	pub players: Vec<PlayerStatus>,
+	pub team_scores: [i32; NUM_TEAMS],
+	pub time_remaining: f32, // seconds
+	pub max_players: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlayerStatus {
+	pub name: String,
+	pub team: Team,
+	pub spectator: bool,
+	pub frags: u32,
+	pub deaths: u32,
+	pub ping_ms: Option<u32>, // round-trip time to the server, if measured yet
+}
+
+/// A server that answered a `StatusQuery`.
+#[derive(Clone, Debug)]
+pub struct ServerInfo {
+	pub addr: String, // game address, e.g. `192.168.0.1:3344`, can be used in `network.servers`.
+	pub ping: Duration,
+	pub status: ServerStatus,
+}
+
+/// Server side: answers status queries on a non-blocking UDP socket,
+/// polled from the server loop.
+pub struct QueryListener {
+	socket: UdpSocket,
+	cookie_key: RandomState, // secret, so that cookies can't be guessed.
+	started: Instant,
+	replies: HashMap<IpAddr, (Instant, u32)>, // start of the current `REPLY_WINDOW`, replies sent in it.
+}
+
+impl QueryListener {
+	/// Listen for queries and LAN broadcasts on all interfaces.
+	pub fn bind(port: u16) -> Result<Self> {
+		let socket = UdpSocket::bind(("0.0.0.0", port)).map_err(|e| anyhow!("bind query port {port}: {e}"))?;
+		socket.set_nonblocking(true)?;
+		Ok(Self {
+			socket,
+			cookie_key: RandomState::new(),
+			started: Instant::now(),
+			replies: default(),
+		})
+	}
+
+	pub fn port(&self) -> Result<u16> {
+		Ok(self.socket.local_addr()?.port())
+	}
+
+	/// Answer pending queries (non-blocking).
+	/// `status` is only called if someone is asking, with a valid cookie.
+	pub fn answer(&mut self, status: impl Fn() -> ServerStatus) {
+		let now = Instant::now();
+		self.replies.retain(|_, (start, _)| now - *start < REPLY_WINDOW);
+
+		let mut buf = [0u8; MAX_DATAGRAM];
+		for _ in 0..MAX_ANSWERS_PER_TICK {
+			let (n, from) = match self.socket.recv_from(&mut buf) {
+				Ok(received) => received,
+				Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+				Err(e) => return error!("query: {e}"),
+			};
+			if n < MIN_QUERY_SIZE {
+				trace!("query from {from}: {n} bytes is too short");
+				continue;
+			}
+			let query: StatusQuery = match wireformat::deserialize_from(&buf[..n]) {
+				Ok(query) => query,
+				Err(e) => {
+					trace!("query from {from}: {e}");
+					continue;
+				}
+			};
+			let (_, sent) = self.replies.entry(from.ip()).or_insert((now, 0));
+			if *sent >= MAX_REPLIES_PER_ADDR {
+				trace!("query from {from}: too many queries");
+				continue;
+			}
+			*sent += 1;
+
+			let response = match self.cookie_ok(from.ip(), query.cookie) {
+				true => QueryResponse::Status(status().with(|s| s.nonce = query.nonce)),
+				false => QueryResponse::Challenge {
+					nonce: query.nonce,
+					cookie: self.cookie(from.ip(), self.cookie_epoch()),
+				},
+			};
+			let mut out = vec![];
+			if let Err(e) = wireformat::serialize_into(&mut out, &response).and_then(|()| Ok(self.socket.send_to(&out, from)?)) {
+				error!("query: answer {from}: {e}");
+			}
+		}
+	}
+
+	// Cookies change every `COOKIE_LIFETIME_SECS`, the previous one is still accepted.
+	fn cookie_ok(&self, ip: IpAddr, cookie: u64) -> bool {
+		let epoch = self.cookie_epoch();
+		cookie == self.cookie(ip, epoch) || (epoch > 0 && cookie == self.cookie(ip, epoch - 1))
+	}
+
+	fn cookie(&self, ip: IpAddr, epoch: u64) -> u64 {
+		self.cookie_key.hash_one((ip, epoch))
+	}
+
+	fn cookie_epoch(&self) -> u64 {
+		self.started.elapsed().as_secs() / COOKIE_LIFETIME_SECS
+	}
+}
+
+/// Client side: find servers on the LAN (broadcast) and ask the `configured` ones (e.g. `network.servers`) for their status.
+/// Waits `timeout` for answers. Servers that don't answer in time are left out.
+///
+/// This is what a server browser is built on.
+pub fn find_servers(configured: &[String], query_port: u16, timeout: Duration) -> Result<Vec<ServerInfo>> {
+	let socket = UdpSocket::bind("0.0.0.0:0")?;
+	socket.set_broadcast(true)?;
+
+	let nonce = rand::random();
+	let mut query = vec![];
+	wireformat::serialize_into(&mut query, &StatusQuery::new(nonce, 0))?;
+
+	let start = Instant::now();
+	// Not every network allows broadcast, carry on with the configured servers.
+	if let Err(e) = socket.send_to(&query, (Ipv4Addr::BROADCAST, query_port)) {
+		LOG.write(format!("LAN discovery: {e}"));
+	}
+	for server in configured {
+		match query_addr(server, query_port) {
+			Ok(addr) => {
+				if let Err(e) = socket.send_to(&query, addr) {
+					LOG.write(format!("query {server}: {e}"));
+				}
+			}
+			Err(e) => LOG.write(format!("query {server}: {e:#}")),
+		}
+	}
+
+	let mut found = Vec::<ServerInfo>::new();
+	let mut asked = HashMap::<SocketAddr, Instant>::default();
+	let mut buf = [0u8; MAX_DATAGRAM];
+	while let Some(remaining) = timeout.checked_sub(start.elapsed()).filter(|t| !t.is_zero()) {
+		socket.set_read_timeout(Some(remaining))?;
+		let (n, from) = match socket.recv_from(&mut buf) {
+			Ok(received) => received,
+			Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+			Err(e) => return Err(e.into()),
+		};
+		let status = match wireformat::deserialize_from(&buf[..n]) {
+			Ok(QueryResponse::Status(status)) if status.nonce == nonce => status,
+			Ok(QueryResponse::Challenge { nonce: n, cookie }) if n == nonce => {
+				// ask again, with the cookie.
+				let mut query = vec![];
+				wireformat::serialize_into(&mut query, &StatusQuery::new(nonce, cookie))?;
+				socket.send_to(&query, from)?;
+				asked.insert(from, Instant::now());
+				continue;
+			}
+			_ => continue, // not for us
+		};
+		// the ping is measured on the second round trip, with the cookie.
+		let ping = asked.get(&from).unwrap_or(&start).elapsed();
+		let addr = SocketAddr::new(from.ip(), status.game_port).to_string();
+		// a configured server on the LAN answers twice.
+		if !found.iter().any(|s| s.addr == addr) {
+			found.push(ServerInfo { addr, ping, status })
+		}
+	}
+
+	found.sort_by_key(|s| s.ping);
+	Ok(found)
+}
+
+// Query address of a game server address like `host:3344`: same host, on the query port.
+fn query_addr(server: &str, query_port: u16) -> Result<SocketAddr> {
+	let host = server.rsplit_once(':').map(|(host, _port)| host).unwrap_or(server);
+	(host, query_port).to_socket_addrs()?.next().ok_or_else(|| anyhow!("cannot resolve {host}"))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn status() -> ServerStatus {
+		ServerStatus {
+			nonce: 0,
+			protocol_version: PROTOCOL_VERSION,
+			game_port: 3344,
+			transport: Transport::Tcp,
+			map_name: "deck".into(),
+			players: vec![PlayerStatus {
+				name: "alice".into(),
+				team: Team::Red,
+				spectator: false,
+				frags: 3,
+				deaths: 1,
+				ping_ms: Some(20),
+			}],
+			team_scores: [3, 0, 0],
+			time_remaining: 100.0,
+			max_players: 16,
+		}
+	}
+
+	#[test]
+	fn query_status() {
+		let mut listener = QueryListener::bind(0).unwrap();
+		let port = listener.port().unwrap();
+		let server = thread::spawn(move || {
+			let start = Instant::now();
+			while start.elapsed() < Duration::from_millis(500) {
+				listener.answer(status);
+				thread::sleep(Duration::from_millis(1));
+			}
+		});
+
+		let found = find_servers(&["127.0.0.1:3344".into()], port, Duration::from_millis(300)).unwrap();
+		server.join().unwrap();
+
+		// may also be found via LAN broadcast, on another interface.
+		let server = found.iter().find(|s| s.addr == "127.0.0.1:3344").unwrap();
+		assert_eq!(server.status.map_name, "deck");
+		assert_eq!(server.status.players[0].name, "alice");
+	}
+
+	// Status is only sent to clients that echo a cookie, so that it can't be sent to a spoofed address,
+	// and never more than `MAX_REPLIES_PER_ADDR` times per second to the same address.
+	#[test]
+	fn no_amplification() {
+		let mut listener = QueryListener::bind(0).unwrap();
+		let server = (Ipv4Addr::LOCALHOST, listener.port().unwrap());
+		let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+		client.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+
+		let mut ask = |query: &[u8]| -> Option<(usize, QueryResponse)> {
+			client.send_to(query, server).unwrap();
+			thread::sleep(Duration::from_millis(10));
+			listener.answer(status);
+			let mut buf = [0u8; MAX_DATAGRAM];
+			let n = client.recv(&mut buf).ok()?;
+			Some((n, wireformat::deserialize_from(&buf[..n]).unwrap()))
+		};
+		let query = |cookie| {
+			let mut buf = vec![];
+			wireformat::serialize_into(&mut buf, &StatusQuery::new(1, cookie)).unwrap();
+			buf
+		};
+
+		// too small: ignored
+		let mut short = vec![];
+		wireformat::serialize_into(&mut short, &StatusQuery { nonce: 1, cookie: 0, padding: vec![] }).unwrap();
+		assert!(ask(&short).is_none());
+
+		// no (or a wrong) cookie: a challenge, no larger than the query.
+		let cookie = match ask(&query(0)) {
+			Some((n, QueryResponse::Challenge { nonce: 1, cookie })) if n <= query(0).len() => cookie,
+			other => panic!("{other:?}"),
+		};
+		assert!(matches!(ask(&query(cookie + 1)), Some((_, QueryResponse::Challenge { .. }))));
+
+		// with the cookie: the status, until the address used up its replies.
+		let answered = (0..10).filter(|_| matches!(ask(&query(cookie)), Some((_, QueryResponse::Status(_))))).count();
+		assert_eq!(answered, MAX_REPLIES_PER_ADDR as usize - 2);
+	}
+}
@@ -1,4 +1,5 @@
 use super::internal::*;
+use std::net::IpAddr;
 use Addressee::*;
 use ServerMsg::*;
 
@@ -9,43 +10,109 @@ pub struct ServerState {
 	logic: GameLogic, // -> systems
 
 	data: ServerData,
+
+	admin_password: Option<String>,
+	admins: Set<ID>,
+	addrs: HashMap<ID, IpAddr>, // where players connected from
+	login_lockout: LoginLockout,
+	audit: AuditLog,
+	kicks: Vec<Kick>,
+	chat_flood: ChatFlood,
+	quit: bool, // operator asked to stop the server
+}
+
+/// A player removed from the game by an admin.
+/// The network layer closes their connection (see `ServerState::take_kicks`).
+pub struct Kick {
+	pub player_id: ID,
+	pub reason: String, // told to the player
+	pub ban: bool,      // also refuse their IP address
 }
 
 impl ServerState {
 	pub fn new(opts: ServerOpts) -> Result<Self> {
 		let assets = AssetsDir::find()?;
+		let admin_password = opts.admin_password.clone().filter(|p| !p.is_empty());
+		let audit = AuditLog::open(opts.audit_log.as_deref())?;
+		let weapons = Weapons::load(&assets)?;
+		let interest_radius = (opts.interest_radius != 0).then(|| min_interest_radius(opts.interest_radius, &weapons));
 
 		let logic = GameLogic::new(assets.clone(), opts)?;
 
 		let map = Map::load(&assets, logic.curr_map_name())?;
-		let world = World::new(map, default());
-		let data = ServerData::new(world);
+		let world = World::new(map, default(), weapons);
+		let data = ServerData::new(world, interest_radius);
 
-		Ok(Self { logic, data })
+		Ok(Self {
+			logic,
+			data,
+			admin_password,
+			admins: default(),
+			addrs: default(),
+			login_lockout: default(),
+			audit,
+			kicks: default(),
+			chat_flood: default(),
+			quit: false,
+		})
 	}
 
-	/// Add a new player to the game and return their unique ID.
-	pub fn join_new_player(&mut self, join_msg: JoinRequest) -> (ID, MapSwitch) {
-		let (player_id, map_switch) = self.logic.join_new_player(&mut self.data, join_msg);
-		self.log(format!("{} joined", self.must_name(player_id)));
+	/// Add a new player, connected from `addr`, to the game and return their unique ID,
+	/// or the reason why they cannot join.
+	pub fn join_new_player(&mut self, addr: IpAddr, join_msg: JoinRequest) -> Result<AcceptedMsg, RejectedMsg> {
+		// Reconnecting before we noticed the old connection is gone (e.g. half-open TCP):
+		// drop the old one, so that the new one resumes the session (see `NetServer::add_client`).
+		// Spectators have no slot to resume, they get a new one.
+		if check_protocol_version(join_msg.protocol_version).is_ok() {
+			if let Some(id) = self.logic.session_player(join_msg.session).filter(|&id| self.data.player(id).is_some_and(|p| !p.spectator)) {
+				self.handle_drop_player(id);
+			}
+		}
+		self.logic.check_join(&self.data, &join_msg).map_err(|reason| RejectedMsg { reason })?;
+		let capabilities = join_msg.capabilities.intersection(Capabilities::SUPPORTED);
+		let (player_id, session, map_switch) = match self.logic.resume_player(&mut self.data, join_msg.session) {
+			Some(resumed) => {
+				self.log(format!("{} is back", self.must_name(resumed.0)));
+				resumed
+			}
+			None => {
+				let spectator = join_msg.spectator;
+				let joined = self.logic.join_new_player(&mut self.data, join_msg);
+				match spectator {
+					false => self.log(format!("{} joined", self.must_name(joined.0))),
+					true => self.log(format!("{} is spectating", self.must_name(joined.0))),
+				}
+				joined
+			}
+		};
+		self.addrs.insert(player_id, addr);
 		//self.hud_message(player_id, format!("Welcome to {}.", self.map_name()));
 		self.data.hud_announce(Just(player_id), self.logic.curr_map_name().to_owned());
 		self.push_no_apply(PlaySound(SoundEffect::raw("ann_begin")).to_just(player_id));
-		(player_id, map_switch)
+		Ok(AcceptedMsg {
+			player_id,
+			session,
+			capabilities,
+			map_switch,
+			name: self.must_name(player_id).to_owned(),
+		})
 	}
 
 	//-------------------------------------------------------------------------------- handlers
 
 	pub fn handle_tick(&mut self, dt: f32) -> Diffs {
+		self.data.tick_time(dt);
 		self.logic.tick(&mut self.data, dt);
+		self.data.push_snapshots();
 		self.data.take_diffs()
 	}
 
 	/// Respond to message sent by a player.
 	pub fn handle_client_msg(&mut self, player_id: ID, msg: ClientMsg) {
-		// check that the player has not been disconnected in a network race.
-		// after this check, all downstream methods may safely use `self.player(id)`,
-		// as we will never remove a player while handling client messages.
+		// check that the player is still in the game: they may have been disconnected in a network race,
+		// or removed earlier in the same batch of messages (e.g. kicked after too many failed logins).
+		// after this check, downstream methods may safely use `self.player(id)`,
+		// until they remove the player themselves (see `kick`).
 		if !self.data.world.entities.players.contains(player_id) {
 			return;
 		}
@@ -56,38 +123,65 @@ impl ServerState {
 			ReadyToSpawn => self.handle_ready_to_respawn(player_id),
 			AddEffect(effect) => self.handle_add_effect(player_id, effect),
 			PlaySound(sound) => self.handle_play_sound(player_id, sound),
-			HitPlayer(victim_id) => self.handle_hit_player(player_id, victim_id),
+			HitPlayer(hit) => self.handle_hit_player(player_id, hit),
+			SwitchWeapon(weapon) => self.logic.handle_switch_weapon(&mut self.data, player_id, weapon),
+			FireProjectile(line_of_fire) => self.handle_fire_projectile(player_id, line_of_fire),
 			Command(cmd) => self.handle_command(player_id, cmd),
+			AckSnapshot(tick) => self.data.ack_snapshot(player_id, tick),
+			RequestMap { map_name, have } => self.logic.handle_request_map(&mut self.data, player_id, &map_name, &have),
+			Pong(_) => (/*handled by NetServer*/),
 		};
 	}
 
 	fn handle_move_player_if_spawned(&mut self, player_id: ID, frame: Frame) {
-		self.data.move_player_if_spawned(player_id, frame);
+		self.logic.handle_move_player(&mut self.data, player_id, frame);
+	}
+
+	pub fn handle_hit_player(&mut self, player_id: ID, hit: HitClaim) {
+		self.logic.handle_hit_player(&mut self.data, player_id, hit);
 	}
 
-	pub fn handle_hit_player(&mut self, player_id: ID, victim_id: ID) {
-		self.logic.handle_hit_player(&mut self.data, player_id, victim_id);
+	pub fn handle_fire_projectile(&mut self, player_id: ID, line_of_fire: Ray64) {
+		self.logic.handle_fire_projectile(&mut self.data, player_id, line_of_fire);
 	}
 
 	pub fn handle_ready_to_respawn(&mut self, player_id: ID) {
 		self.logic.handle_ready_to_respawn(&mut self.data, player_id)
 	}
 
-	// Handle a client's AddEffect message: just broadcast to other clients.
+	// Handle a client's AddEffect message: just forward to other clients who can see it.
 	// There is little point in adding visual effects to the server's world.
 	pub fn handle_add_effect(&mut self, player_id: ID, effect: Effect) {
-		self.push_no_apply(AddEffect(effect).to_not(player_id))
+		let to = Near(effect.place(), Some(player_id));
+		self.push_no_apply(AddEffect(effect).to(to))
 	}
 
-	// Handle a client's PlaySound message: just broadcast to other clients.
+	// Handle a client's PlaySound message: just forward to other clients who can hear it.
 	pub fn handle_play_sound(&mut self, player_id: ID, sound: SoundEffect) {
-		self.push_no_apply(PlaySound(sound).to_not(player_id))
+		let to = match sound.place() {
+			Some(place) => Near(place, Some(player_id)),
+			None => Not(player_id),
+		};
+		self.push_no_apply(PlaySound(sound).to(to))
 	}
 
 	pub fn handle_drop_player(&mut self, client_id: ID) {
 		self.log(format!("{} left", &self.must_name(client_id)));
-		self.data.drop_player(client_id);
-		self.logic.drop_player(client_id);
+		self.logic.drop_player(&mut self.data, client_id);
+		self.admins.remove(&client_id);
+		self.addrs.remove(&client_id);
+		self.chat_flood.forget(client_id);
+	}
+
+	/// Has the operator asked to stop the server (`quit`)?
+	pub fn quit_requested(&self) -> bool {
+		self.quit
+	}
+
+	/// Players kicked since the last call, whose connections should be closed.
+	/// They have already been removed from the game.
+	pub fn take_kicks(&mut self) -> Vec<Kick> {
+		mem::take(&mut self.kicks)
 	}
 
 	// --------------------------------------------------------------------------------
@@ -97,7 +191,11 @@ impl ServerState {
 	}
 
 	fn broadcast_sound(&mut self, sound: SoundEffect) {
-		self.push_no_apply(PlaySound(sound).to_all())
+		let to = match sound.place() {
+			Some(place) => Near(place, None),
+			None => All,
+		};
+		self.push_no_apply(PlaySound(sound).to(to))
 	}
 
 	/// Push a message to diffs without applying to the world.
@@ -116,6 +214,21 @@ impl ServerState {
 		self.logic.curr_map_name()
 	}
 
+	/// Round-trip time to a player's client, as measured by the network layer.
+	pub fn set_ping(&mut self, player_id: ID, rtt: Duration) {
+		self.data.set_ping(player_id, rtt)
+	}
+
+	/// Can a player see or hear what happens at `place` (see `Addressee::Near`)?
+	pub fn perceives(&self, player_id: ID, place: &Place) -> bool {
+		self.data.perceives(player_id, place)
+	}
+
+	/// Status shown to anyone asking, without joining (see `QueryListener`).
+	pub fn status(&self) -> ServerStatus {
+		self.logic.status(&self.data)
+	}
+
 	/// Player name or "???".
 	pub fn must_name(&self, player_id: ID) -> &str {
 		self.data.must_name(player_id)
@@ -124,33 +237,261 @@ impl ServerState {
 	// -------------------------------------------------------------------------------- text commands
 
 	fn handle_command(&mut self, client_id: ID, cmd: String) {
-		info!("command from {client_id} ({}): '{cmd}'", self.must_name(client_id));
-		match self.handle_command_with_result(client_id, cmd) {
-			Ok(()) => info!("command ok"),
-			Err(e) => {
-				info!("command error: {e}");
-				self.push_no_apply(Log(format!("error: {}", e)).to_just(client_id));
-			}
+		match self.handle_command_with_result(Caller::Player(client_id), &cmd) {
+			Ok(reply) if reply.is_empty() => (),
+			Ok(reply) => self.push_no_apply(Log(reply).to_just(client_id)),
+			Err(e) => self.push_no_apply(Log(format!("error: {}", e)).to_just(client_id)),
+		}
+	}
+
+	/// Run a command typed by the server operator (see `Console`), with full rights.
+	/// Returns the text to show them.
+	pub fn handle_console_command(&mut self, cmd: &str) -> Result<String> {
+		self.handle_command_with_result(Caller::Console, cmd)
+	}
+
+	// Run a command if the caller has the privilege (see `COMMANDS`),
+	// return the text to show the caller (if any).
+	// Admin commands and logins are audit-logged, whether allowed or not.
+	fn handle_command_with_result(&mut self, caller: Caller, cmd: &str) -> Result<String> {
+		let who = self.caller_name(caller);
+		let logged = CommandSpec::find(cmd).map(|spec| spec.redact(cmd)).unwrap_or_else(|| cmd.into());
+		info!("command from {who}: '{logged}'");
+
+		let spec = CommandSpec::find(cmd).ok_or_else(|| anyhow!("unknown command: {cmd} (try `help`)"))?;
+		let audited = spec.privilege >= Privilege::Admin || spec.secret_args;
+
+		if spec.privilege > self.privilege(caller) {
+			self.audit.record(&who, &logged, "denied");
+			return Err(match spec.privilege {
+				Privilege::Console => anyhow!("`{}` can only be used from the server console", spec.name),
+				_ => anyhow!("`{}` is for admins only (see `login`)", spec.name),
+			});
+		}
+
+		let result = self.run_command(caller, cmd);
+		match &result {
+			Ok(_) => info!("command ok"),
+			Err(e) => info!("command error: {e}"),
+		}
+		if audited {
+			let outcome = match &result {
+				Ok(_) => "ok".into(),
+				Err(e) => format!("error: {e}"),
+			};
+			self.audit.record(&who, &logged, &outcome);
 		}
+		result
 	}
 
-	fn handle_command_with_result(&mut self, client_id: ID, cmd: String) -> Result<()> {
+	// Commands that answer the caller.
+	fn run_command(&mut self, caller: Caller, cmd: &str) -> Result<String> {
 		Ok(match &cmd.split_ascii_whitespace().collect::<Vec<_>>()[..] {
-			["say", ..] => self.handle_say_cmd(client_id, cmd["say".len()..].trim_start()),
+			["help"] => self.handle_help_cmd(caller),
+			["status"] => self.handle_status_cmd(),
+			["players"] => self.handle_players_cmd(),
+			["maplist"] => self.logic.maplist().join(" "),
+			_ => {
+				self.run_action(caller, cmd)?;
+				String::new()
+			}
+		})
+	}
+
+	// Commands that change the game.
+	fn run_action(&mut self, caller: Caller, cmd: &str) -> Result<()> {
+		Ok(match &cmd.split_ascii_whitespace().collect::<Vec<_>>()[..] {
+			["say", ..] => self.handle_chat_cmd(caller, ChatChannel::All, skip_words(cmd, 1))?,
+			["say_team", ..] => self.handle_chat_cmd(caller, ChatChannel::Team(self.team_of(caller)?), skip_words(cmd, 1))?,
+			["tell", name, _, ..] => self.handle_chat_cmd(caller, ChatChannel::Private { to: self.must_find(name)? }, skip_words(cmd, 2))?,
+			["login", password] => self.handle_login_cmd(caller.player_id()?, password)?,
+			["logout"] => self.handle_logout_cmd(caller.player_id()?),
+			["name", new_name] => self.handle_name_cmd(caller.player_id()?, new_name)?,
+			["team", team] => self.logic.switch_team_cmd(&mut self.data, caller.player_id()?, Team::parse_choice(team)?)?,
 			["switch"] => self.logic.switch_next_map(&mut self.data),
 			["switch", map_name] => self.logic.switch_map_cmd(&mut self.data, map_name)?,
+			["restart"] => self.logic.restart_map(&mut self.data)?,
 			["kill", victim_name] => self.handle_kill_cmd(victim_name)?,
-			_ => return Err(anyhow!("unknown command: {}", cmd)),
+			["kick", victim_name] => self.handle_kick_cmd(caller, victim_name, false)?,
+			["ban", victim_name] => self.handle_kick_cmd(caller, victim_name, true)?,
+			["fraglimit", frags] => self.logic.set_frag_limit(&mut self.data, frags.parse()?),
+			["timelimit", seconds] => self.logic.set_time_limit(&mut self.data, seconds.parse()?),
+			["quit"] => self.quit = true,
+			_ => return Err(anyhow!("usage: {}", CommandSpec::find(cmd).map(|spec| spec.usage).unwrap_or("help"))),
 		})
 	}
 
+	fn privilege(&self, caller: Caller) -> Privilege {
+		match caller {
+			Caller::Console => Privilege::Console,
+			Caller::Player(id) if self.admins.contains(&id) => Privilege::Admin,
+			Caller::Player(_) => Privilege::Player,
+		}
+	}
+
+	// E.g. "alice (#3)", for logs.
+	fn caller_name(&self, caller: Caller) -> String {
+		match caller {
+			Caller::Console => "console".into(),
+			Caller::Player(id) => format!("{} ({id})", self.must_name(id)),
+		}
+	}
+
+	fn handle_help_cmd(&self, caller: Caller) -> String {
+		let privilege = self.privilege(caller);
+		COMMANDS.iter().filter(|spec| spec.privilege <= privilege).map(|spec| spec.usage).collect::<Vec<_>>().join("\n")
+	}
+
+	fn handle_status_cmd(&self) -> String {
+		let status = self.status();
+		let playing = status.players.iter().filter(|p| !p.spectator).count();
+		let [red, blue, green] = status.team_scores;
+		format!(
+			"map {}, {:.0}s left, {playing}/{} players, {} spectators, scores: red {red}, blue {blue}, green {green}",
+			status.map_name,
+			status.time_remaining,
+			status.max_players,
+			status.players.len() - playing
+		)
+	}
+
+	fn handle_players_cmd(&self) -> String {
+		self.status()
+			.players
+			.iter()
+			.map(|p| {
+				let ping = p.ping_ms.map(|ms| format!("{ms} ms")).unwrap_or("? ms".into());
+				let role = match (p.spectator, self.data.player_by_name(&p.name).map(|id| self.admins.contains(&id))) {
+					(true, _) => " (spectating)",
+					(false, Some(true)) => " (admin)",
+					_ => "",
+				};
+				format!("{:<16} {:<6} {:>3} frags {:>3} deaths {ping:>7}{role}", p.name, p.team, p.frags, p.deaths)
+			})
+			.collect::<Vec<_>>()
+			.join("\n")
+	}
+
+	fn handle_login_cmd(&mut self, client_id: ID, password: &str) -> Result<()> {
+		let admin_password = self.admin_password.as_deref().ok_or_else(|| anyhow!("this server has no admin password"))?;
+		let addr = *self.addrs.get(&client_id).ok_or_else(|| anyhow!("no address for {client_id}"))?;
+		let now = self.data.time();
+		let result = self.login_lockout.check(addr, now, password, admin_password);
+		if self.login_lockout.locked_out(addr, now) {
+			self.kick(client_id, "too many failed logins".into(), false);
+		}
+		result?;
+		self.admins.insert(client_id);
+		self.push_no_apply(Log("you are now admin".into()).to_just(client_id));
+		Ok(())
+	}
+
+	fn handle_logout_cmd(&mut self, client_id: ID) {
+		self.admins.remove(&client_id);
+	}
+
+	fn handle_kick_cmd(&mut self, caller: Caller, victim_name: &str, ban: bool) -> Result<()> {
+		let victim_id = self.data.player_by_name(victim_name).ok_or(anyhow!("no such player"))?;
+		let by = match caller {
+			Caller::Console => "the server",
+			Caller::Player(id) => self.must_name(id),
+		};
+		let reason = format!("{} by {by}", if ban { "banned" } else { "kicked" });
+		self.kick(victim_id, reason, ban);
+		Ok(())
+	}
+
+	// Remove a player from the game for good, and have the network layer disconnect them.
+	fn kick(&mut self, player_id: ID, reason: String, ban: bool) {
+		self.log(format!("{}: {reason}", self.must_name(player_id)));
+		self.logic.kick_player(&mut self.data, player_id);
+		self.admins.remove(&player_id);
+		self.addrs.remove(&player_id);
+		self.kicks.push(Kick { player_id, reason, ban });
+	}
+
 	fn handle_kill_cmd(&mut self, victim_name: &str) -> Result<()> {
 		let victim_id = self.data.player_by_name(victim_name).ok_or(anyhow!("no such player"))?;
 		self.data.despawn(victim_id).ok_or(anyhow!("Failed (maybe monad bailed out)"))
 	}
 
-	fn handle_say_cmd(&mut self, player_id: ID, msg: &str) {
-		let msg = format!("{}: {}", self.must_name(player_id), msg);
-		self.push_no_apply(Log(msg).to_all());
+	// Send a chat line to everyone on `channel`. Players who chat too much are told to wait (see `ChatFlood`).
+	fn handle_chat_cmd(&mut self, caller: Caller, channel: ChatChannel, text: &str) -> Result<()> {
+		let text = sanitize_chat(text);
+		if text.is_empty() {
+			return Err(anyhow!("nothing to say"));
+		}
+		if let Caller::Player(id) = caller {
+			self.chat_flood.spend(id, self.data.time())?;
+		}
+		let sender = caller.player_id().ok();
+		let msg = Chat(ChatMsg { sender, channel, text });
+		match channel {
+			ChatChannel::All => self.push_no_apply(msg.to_all()),
+			ChatChannel::Team(team) => {
+				for id in self.data.players().filter(|&id| self.data.player(id).map(|p| p.team) == Some(team)).collect::<Vec<_>>() {
+					self.push_no_apply(msg.clone().to_just(id))
+				}
+			}
+			ChatChannel::Private { to } => {
+				self.push_no_apply(msg.clone().to_just(to));
+				if let Some(sender) = sender.filter(|&id| id != to) {
+					self.push_no_apply(msg.to_just(sender)) // so they see what they said.
+				}
+			}
+		}
+		Ok(())
+	}
+
+	// Renames count against the chat flood limit: they are announced to everyone.
+	fn handle_name_cmd(&mut self, player_id: ID, new_name: &str) -> Result<()> {
+		self.chat_flood.spend(player_id, self.data.time())?;
+		self.logic.rename_cmd(&mut self.data, player_id, new_name)
+	}
+
+	fn team_of(&self, caller: Caller) -> Result<Team> {
+		let player = self.data.player(caller.player_id()?).ok_or(anyhow!("no such player"))?;
+		match player.spectator {
+			true => Err(anyhow!("spectators are not on a team")),
+			false => Ok(player.team),
+		}
+	}
+
+	fn must_find(&self, name: &str) -> Result<ID> {
+		self.data.player_by_name(name).ok_or(anyhow!("no such player: {name}"))
+	}
+}
+
+// `radius`, or more if needed to see players at the longest weapon range (no shooting the invisible).
+fn min_interest_radius(radius: u32, weapons: &Weapons) -> u32 {
+	let min = (weapons.max_range() / ZONE_SIZE as f32).ceil() as u32;
+	if radius < min {
+		warn!("interest_radius {radius} is less than the longest weapon range ({}), using {min}", weapons.max_range());
+	}
+	u32::max(radius, min)
+}
+
+// `cmd` without its first `n` words, e.g. `skip_words("tell bob hi there", 2) == "hi there"`.
+fn skip_words(cmd: &str, n: usize) -> &str {
+	let mut rest = cmd.trim_start();
+	for _ in 0..n {
+		rest = rest.trim_start_matches(|c: char| !c.is_ascii_whitespace()).trim_start();
+	}
+	rest
+}
+
+// Who runs a text command.
+#[derive(Copy, Clone)]
+enum Caller {
+	Player(ID),
+	Console,
+}
+
+impl Caller {
+	fn player_id(self) -> Result<ID> {
+		match self {
+			Caller::Player(id) => Ok(id),
+			Caller::Console => Err(anyhow!("only for players")),
+		}
 	}
 }
@@ -0,0 +1,270 @@
+use super::internal::*;
+use std::collections::VecDeque;
+
+/// Server tick number. Increases by one every server tick.
+pub type Tick = u64;
+
+/// The replicated player state at a given server tick.
+///
+/// Server and clients keep a few recent snapshots,
+/// so that the server only needs to send what changed since the last snapshot acknowledged by the client
+/// (see `SnapshotDelta`).
+#[derive(Clone, Default)]
+pub struct Snapshot {
+	pub tick: Tick,
+	pub players: Players,
+}
+
+/// The difference between a client's acknowledged `baseline` snapshot and the snapshot at `tick`.
+///
+/// `baseline: None` means the delta is against an empty snapshot
+/// (i.e. carries the full state: first snapshot, or the baseline is too old).
+///
+/// Deltas may be lost or arrive out of order:
+/// each one is relative to a snapshot the client is known to have,
+/// so any single delta that arrives can be applied.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SnapshotDelta {
+	pub tick: Tick,
+	pub baseline: Option<Tick>,
+	pub players: Vec<PlayerDelta>,
+	pub removed: Vec<ID>,
+}
+
+/// The fields of a `Player` that changed since the baseline.
+/// `None` means unchanged.
+///
+/// `LocalState` is never sent. Neither is the recipient's own frame,
+/// which it controls locally.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PlayerDelta {
+	pub id: ID,
+	pub name: Option<String>,
+	pub avatar_id: Option<u8>,
+	pub team: Option<Team>,
+	pub health: Option<i32>,
+	pub spawned: Option<bool>,
+	pub spectator: Option<bool>,
+	pub invulnerability_ttl: Option<Option<f32>>,
+	pub weapon: Option<u8>,
+	pub frame: Option<Frame>,
+}
+
+/// Number of recent snapshots kept as possible baselines (about 1s of ticks).
+/// Clients that have not acknowledged a snapshot for longer get the full state.
+pub const MAX_SNAPSHOTS: usize = 32;
+
+/// Recent snapshots, oldest first.
+#[derive(Default)]
+pub struct SnapshotHistory(VecDeque<Snapshot>);
+
+impl SnapshotDelta {
+	/// The changes from `baseline` to `current`, as seen by client `recipient`
+	/// (who does not need their own frame).
+	pub fn new(baseline: Option<&Snapshot>, current: &Snapshot, recipient: ID) -> Self {
+		let empty = Players::default();
+		let base_players = baseline.map(|b| &b.players).unwrap_or(&empty);
+		Self {
+			tick: current.tick,
+			baseline: baseline.map(|b| b.tick),
+			players: current
+				.players
+				.iter()
+				.filter_map(|(id, player)| PlayerDelta::new(base_players.get(id), player, id != recipient))
+				.collect(),
+			removed: base_players.ids().filter(|&id| !current.players.contains(id)).collect(),
+		}
+	}
+
+	/// Reconstruct the snapshot at `self.tick`.
+	/// `baseline` must be the snapshot at `self.baseline`.
+	pub fn apply(&self, baseline: Option<&Snapshot>) -> Snapshot {
+		debug_assert_eq!(baseline.map(|b| b.tick), self.baseline);
+		let mut players = baseline.map(|b| b.players.clone()).unwrap_or_default();
+		for id in &self.removed {
+			players.remove(*id);
+		}
+		for delta in &self.players {
+			let player = match players.get(delta.id) {
+				Some(player) => player.clone(),
+				None => Player::new(delta.id, default(), default(), default(), default(), Team::Red),
+			};
+			players.insert(delta.id, delta.apply(player));
+		}
+		Snapshot { tick: self.tick, players }
+	}
+}
+
+impl PlayerDelta {
+	/// The fields that changed from `old` to `new` (all fields if `old` is `None`),
+	/// or `None` if nothing changed.
+	pub fn new(old: Option<&Player>, new: &Player, with_frame: bool) -> Option<Self> {
+		fn changed<T: PartialEq + Clone>(old: Option<&T>, new: &T) -> Option<T> {
+			match old {
+				Some(old) if old == new => None,
+				_ => Some(new.clone()),
+			}
+		}
+
+		let delta = Self {
+			id: new.id,
+			name: changed(old.map(|p| &p.name), &new.name),
+			avatar_id: changed(old.map(|p| &p.avatar_id), &new.avatar_id),
+			team: changed(old.map(|p| &p.team), &new.team),
+			health: changed(old.map(|p| &p.health), &new.health),
+			spawned: changed(old.map(|p| &p.spawned), &new.spawned),
+			spectator: changed(old.map(|p| &p.spectator), &new.spectator),
+			invulnerability_ttl: changed(old.map(|p| &p.invulnerability_ttl), &new.invulnerability_ttl),
+			weapon: changed(old.map(|p| &p.weapon), &new.weapon),
+			frame: match with_frame {
+				true => changed(old.map(|p| p.skeleton.frame()).as_ref(), &new.skeleton.frame()),
+				false => None,
+			},
+		};
+
+		match (old, delta.is_empty()) {
+			(Some(_), true) => None,
+			_ => Some(delta),
+		}
+	}
+
+	fn is_empty(&self) -> bool {
+		let Self {
+			id: _,
+			name,
+			avatar_id,
+			team,
+			health,
+			spawned,
+			spectator,
+			invulnerability_ttl,
+			weapon,
+			frame,
+		} = self;
+		name.is_none() && avatar_id.is_none() && team.is_none() && health.is_none() && spawned.is_none() && spectator.is_none() && invulnerability_ttl.is_none() && weapon.is_none() && frame.is_none()
+	}
+
+	/// Overwrite the changed fields.
+	pub fn apply(&self, mut player: Player) -> Player {
+		if let Some(name) = &self.name {
+			player.name = name.clone();
+		}
+		if let Some(avatar_id) = self.avatar_id {
+			player.avatar_id = avatar_id;
+		}
+		if let Some(team) = self.team {
+			player.team = team;
+		}
+		if let Some(health) = self.health {
+			player.health = health;
+		}
+		if let Some(spawned) = self.spawned {
+			player.spawned = spawned;
+		}
+		if let Some(spectator) = self.spectator {
+			player.spectator = spectator;
+		}
+		if let Some(invulnerability_ttl) = self.invulnerability_ttl {
+			player.invulnerability_ttl = invulnerability_ttl;
+		}
+		if let Some(weapon) = self.weapon {
+			player.weapon = weapon;
+		}
+		if let Some(frame) = &self.frame {
+			player.skeleton.set_frame(frame.clone());
+		}
+		player
+	}
+}
+
+impl SnapshotHistory {
+	/// Add the newest snapshot, forgetting the oldest ones if more than `max_len` are kept.
+	pub fn push(&mut self, snapshot: Snapshot, max_len: usize) {
+		debug_assert!(self.latest().map(|s| s.tick < snapshot.tick).unwrap_or(true));
+		self.0.push_back(snapshot);
+		while self.0.len() > max_len {
+			self.0.pop_front();
+		}
+	}
+
+	/// Snapshot at `tick`, if still kept.
+	pub fn get(&self, tick: Tick) -> Option<&Snapshot> {
+		self.0.iter().find(|s| s.tick == tick)
+	}
+
+	pub fn latest(&self) -> Option<&Snapshot> {
+		self.0.back()
+	}
+
+	/// Forget all snapshots older than `tick`.
+	pub fn forget_before(&mut self, tick: Tick) {
+		while self.0.front().map(|s| s.tick < tick).unwrap_or(false) {
+			self.0.pop_front();
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn player(id: ID, x: f32) -> Player {
+		Player::new(id, vec3(x, 0.0, 0.0), default(), format!("player{x}"), 1, Team::Blue)
+	}
+
+	fn snapshot(tick: Tick, players: &[Player]) -> Snapshot {
+		Snapshot {
+			tick,
+			players: Players::default().with(|pp| players.iter().for_each(|p| pp.insert(p.id, p.clone()))),
+		}
+	}
+
+	#[test]
+	fn only_changes() {
+		let mut entities = Entities::default();
+		let (a, b) = (entities.join_new_player(&default(), join("a"), Team::Blue), entities.join_new_player(&default(), join("b"), Team::Blue));
+		let s1 = snapshot(1, &[player(a, 1.0), player(b, 2.0)]);
+		let s2 = snapshot(2, &[player(a, 1.0).with(|p| p.health = 50)]);
+
+		let delta = SnapshotDelta::new(Some(&s1), &s2, b);
+		assert_eq!(delta.removed, vec![b]);
+		assert_eq!(delta.players.len(), 1);
+		assert_eq!(delta.players[0].health, Some(50));
+		assert!(delta.players[0].name.is_none());
+		assert!(delta.players[0].frame.is_none());
+
+		let unchanged = SnapshotDelta::new(Some(&s2), &s2.clone().with(|s| s.tick = 3), b);
+		assert!(unchanged.players.is_empty() && unchanged.removed.is_empty());
+	}
+
+	#[test]
+	fn apply_reconstructs() {
+		let mut entities = Entities::default();
+		let (a, b) = (entities.join_new_player(&default(), join("a"), Team::Blue), entities.join_new_player(&default(), join("b"), Team::Blue));
+		let observer = entities.join_new_player(&default(), join("c"), Team::Blue);
+		let s1 = snapshot(1, &[player(a, 1.0)]);
+		let s2 = snapshot(2, &[player(a, 3.0).with(|p| p.spawned = true), player(b, 2.0)]);
+
+		for baseline in [None, Some(&s1)] {
+			let got = SnapshotDelta::new(baseline, &s2, observer).apply(baseline);
+			assert_eq!(got.tick, 2);
+			for id in [a, b] {
+				assert_eq!(got.players[id].name, s2.players[id].name);
+				assert_eq!(got.players[id].spawned, s2.players[id].spawned);
+				assert_eq!(got.players[id].skeleton.frame(), s2.players[id].skeleton.frame());
+			}
+		}
+	}
+
+	fn join(name: &str) -> JoinRequest {
+		JoinRequest {
+			protocol_version: PROTOCOL_VERSION,
+			capabilities: Capabilities::NONE,
+			name: name.into(),
+			avatar_id: 1,
+			team: Some(Team::Blue),
+			spectator: false,
+			session: None,
+		}
+	}
+}
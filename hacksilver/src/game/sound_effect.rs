@@ -27,7 +27,12 @@ impl SoundEffect {
 		}
 	}
 
-	pub fn spatial(clip_name: &'static str, location: vec3, volume: f32) -> Self {
+	/// Where the sound is heard, unless it's heard everywhere (see `Addressee::Near`).
+	pub fn place(&self) -> Option<Place> {
+		self.spatial.as_ref().map(|s| Place::point(s.location))
+	}
+
+	pub fn spatial(clip_name: impl Into<CowStr>, location: vec3, volume: f32) -> Self {
 		Self {
 			clip_name: clip_name.into(),
 			volume,
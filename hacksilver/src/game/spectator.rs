@@ -0,0 +1,75 @@
+use super::internal::*;
+use std::iter;
+
+/// What a spectator is looking at (see `JoinRequest::spectator`).
+///
+/// Spectators never spawn. Their local player is just a camera:
+/// either flying around freely, or following another player's view.
+/// MOUSE1 / MOUSE2 cycle forward / backward through: free camera, player 1, player 2, ...
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SpectatorCam {
+	#[default]
+	Free,
+	Follow(ID),
+}
+
+const FLY_SPEED: f32 = 60.0;
+
+impl SpectatorCam {
+	/// Fly the spectator's own (never spawned) player around, or switch cameras.
+	pub fn control(&mut self, spectator: &mut Player, inputs: &Inputs, world: &World, dt: f32) {
+		// followed player left: continue free flying from where they were.
+		if let Follow(id) = *self {
+			if !world.entities.players.contains(id) {
+				*self = Free;
+			}
+		}
+
+		if inputs.is_pressed(Button::MOUSE1) {
+			self.cycle(spectator, world, 1);
+		}
+		if inputs.is_pressed(Button::MOUSE2) {
+			self.cycle(spectator, world, -1);
+		}
+
+		if *self == Free {
+			spectator.set_orientation(inputs);
+			spectator.skeleton.position += FLY_SPEED * dt * fly_dir(spectator.orientation().yaw, inputs);
+		}
+	}
+
+	// Switch to the next (`step` = 1) or previous (`step` = -1) camera.
+	fn cycle(&mut self, spectator: &mut Player, world: &World, step: isize) {
+		let players = world.entities.players.iter().filter(|(_, p)| !p.spectator).map(|(id, _)| id).collect::<Vec<_>>().with(|v| v.sort());
+		let cams = iter::once(Free).chain(players.into_iter().map(Follow)).collect::<Vec<_>>();
+		let curr = cams.iter().position(|cam| cam == self).unwrap_or(0) as isize;
+		let next = cams[(curr + step).rem_euclid(cams.len() as isize) as usize];
+
+		// when going back to free flying, start from the followed player's point of view.
+		if let (Follow(id), Free) = (*self, next) {
+			if let Some(followed) = world.entities.players.get(id) {
+				spectator.skeleton.position = followed.position();
+				spectator.skeleton.orientation = followed.orientation();
+			}
+		}
+		*self = next;
+	}
+
+	/// The player whose point of view is shown.
+	pub fn player_id(&self, spectator: ID) -> ID {
+		match *self {
+			Free => spectator,
+			Follow(id) => id,
+		}
+	}
+
+	/// E.g. "spectating Arne".
+	pub fn describe(&self, world: &World) -> String {
+		match *self {
+			Free => "spectating: free camera".into(),
+			Follow(id) => format!("spectating {}", world.entities.players.get(id).map(|p| p.name.as_str()).unwrap_or("?")),
+		}
+	}
+}
+
+use SpectatorCam::*;
@@ -12,6 +12,16 @@ pub const NUM_TEAMS: usize = 3;
 
 //use Team::*;
 impl Team {
+	pub const ALL: [Team; NUM_TEAMS] = [Team::Red, Team::Blue, Team::Green];
+
+	/// Parse a team, or `auto`: `None`, join the smallest team.
+	pub fn parse_choice(s: &str) -> Result<Option<Self>> {
+		match s {
+			"auto" => Ok(None),
+			team => team.parse().map(Some).map_err(|_| anyhow!("unknown team `{team}`, options: `red`, `blue`, `green`, `auto`")),
+		}
+	}
+
 	/// To be multiplied by colors to make them team-color like.
 	pub fn color_filter(self) -> vec3 {
 		match self {
@@ -0,0 +1,196 @@
+use super::internal::*;
+
+/// A weapon, as defined in `assets/weapons.toml`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Weapon {
+	pub name: String,
+	pub cooldown: f32,        // seconds between shots
+	pub damage: i32,          // per pellet
+	pub headshot_damage: i32, // per pellet
+	#[serde(default)]
+	pub spread: f32, // degrees: pellets fly at most this far off the line of fire
+	#[serde(default = "default_pellets")]
+	pub pellets: u32, // per shot
+	pub range: f32,
+	#[serde(default)]
+	pub kind: WeaponKind,
+	#[serde(default)]
+	pub speed: f32, // projectiles only: launch speed
+	#[serde(default)]
+	pub gravity: f32, // projectiles only: fraction of the gravity players feel (0: flies straight)
+	#[serde(default)]
+	pub splash_radius: f32, // projectiles only: players this close to the explosion get hurt
+	#[serde(default)]
+	pub knockback: f32, // projectiles only: speed given to players at the center of the explosion
+	#[serde(default)]
+	pub color: Option<vec3>, // of the beam, default: the shooter's team color
+	pub fire_sounds: Vec<String>,   // one is picked at random
+	pub impact_sounds: Vec<String>, // where a shot hits
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WeaponKind {
+	#[default]
+	Hitscan,
+	Projectile,
+}
+
+fn default_pellets() -> u32 {
+	1
+}
+
+/// All weapons, in the order of their number keys (see `Player::weapon`).
+#[derive(Clone, Debug)]
+pub struct Weapons(Vec<Weapon>);
+
+// Number keys 1-9.
+pub const MAX_WEAPONS: usize = 9;
+
+impl Weapons {
+	pub fn load(assets: &AssetsDir) -> Result<Self> {
+		let file = assets.weapons_file();
+		Self::parse(&std::fs::read_to_string(&file).map_err(|e| anyhow!("load {file:?}: {e}"))?)
+	}
+
+	pub fn parse(toml: &str) -> Result<Self> {
+		#[derive(Deserialize)]
+		struct File {
+			weapon: Vec<Weapon>,
+		}
+		let weapons = toml::from_str::<File>(toml).map_err(|e| anyhow!("load weapons: {e:#}"))?.weapon;
+		if weapons.is_empty() || weapons.len() > MAX_WEAPONS {
+			return Err(anyhow!("load weapons: need 1 to {MAX_WEAPONS} weapons, got {}", weapons.len()));
+		}
+		for w in &weapons {
+			if !(w.cooldown > 0.0 && w.range > 0.0 && w.pellets > 0 && w.spread >= 0.0) {
+				return Err(anyhow!("weapon {}: cooldown, range and pellets must be positive", w.name));
+			}
+			if w.fire_sounds.is_empty() || w.impact_sounds.is_empty() {
+				return Err(anyhow!("weapon {}: needs fire_sounds and impact_sounds", w.name));
+			}
+			if w.kind == WeaponKind::Projectile && !(w.speed > 0.0 && w.splash_radius > 0.0 && w.gravity >= 0.0 && w.knockback >= 0.0 && w.pellets == 1) {
+				return Err(anyhow!("weapon {}: projectiles need a positive speed and splash_radius, and fire one per shot", w.name));
+			}
+		}
+		Ok(Self(weapons))
+	}
+
+	/// The weapon with index `weapon`, or the first one if out of range
+	/// (e.g. a client with a different `weapons.toml`).
+	pub fn get(&self, weapon: u8) -> &Weapon {
+		self.0.get(weapon as usize).unwrap_or(&self.0[0])
+	}
+
+	pub fn len(&self) -> usize {
+		self.0.len()
+	}
+
+	/// How far the longest-range weapon shoots.
+	pub fn max_range(&self) -> f32 {
+		self.0.iter().map(|w| w.range).fold(0.0, f32::max)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty() // never, see `parse`
+	}
+}
+
+impl Weapon {
+	/// A random pellet direction, at most `spread` degrees away from `dir` (normalized).
+	pub fn pellet_dir(&self, dir: vec3) -> vec3 {
+		if self.spread == 0.0 {
+			return dir;
+		}
+		let mut rng = rand::thread_rng();
+		// uniformly distributed over a disk perpendicular to `dir`.
+		let off = self.spread * DEG * rng.gen_range(0.0f32..1.0).sqrt();
+		let around = rng.gen_range(0.0..2.0 * PI);
+		let u = dir.cross(if dir.y().abs() < 0.9 { vec3::EY } else { vec3::EX }).normalized();
+		let v = dir.cross(u);
+		(off.cos() * dir + off.sin() * (around.cos() * u + around.sin() * v)).normalized()
+	}
+
+	pub fn fire_sound(&self) -> &str {
+		pick_random(&self.fire_sounds).unwrap()
+	}
+
+	pub fn impact_sound(&self) -> &str {
+		pick_random(&self.impact_sounds).unwrap()
+	}
+
+	/// Seconds until a projectile explodes on its own: when it would have flown `range` in a straight line.
+	pub fn fuse(&self) -> f32 {
+		self.range / self.speed
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	const WEAPONS: &str = r#"
+		[[weapon]]
+		name = "gun"
+		cooldown = 0.5
+		damage = 50
+		headshot_damage = 100
+		range = 1000
+		fire_sounds = ["bang1"]
+		impact_sounds = ["ricochet1"]
+
+		[[weapon]]
+		name = "shotgun"
+		cooldown = 1.0
+		damage = 10
+		headshot_damage = 20
+		spread = 5
+		pellets = 8
+		range = 200
+		color = [1.0, 0.5, 0.0]
+		fire_sounds = ["shoot1"]
+		impact_sounds = ["ricochet1", "ricochet2"]
+
+		[[weapon]]
+		name = "grenades"
+		cooldown = 1.0
+		damage = 80
+		headshot_damage = 100
+		range = 200
+		kind = "projectile"
+		speed = 100
+		gravity = 1
+		splash_radius = 30
+		knockback = 100
+		fire_sounds = ["fly"]
+		impact_sounds = ["bang1"]
+	"#;
+
+	#[test]
+	fn parse() {
+		let weapons = Weapons::parse(WEAPONS).unwrap();
+		assert_eq!(weapons.len(), 3);
+		assert_eq!(weapons.get(0).pellets, 1);
+		assert_eq!(weapons.get(0).kind, WeaponKind::Hitscan);
+		assert_eq!(weapons.get(1).color, Some(vec3(1.0, 0.5, 0.0)));
+		assert_eq!(weapons.get(2).kind, WeaponKind::Projectile);
+		assert_eq!(weapons.get(2).fuse(), 2.0);
+		assert_eq!(weapons.get(7).name, "gun");
+
+		assert!(Weapons::parse("weapon = []").is_err());
+		assert!(Weapons::parse(&WEAPONS.replace("pellets = 8", "pellets = 0")).is_err());
+		assert!(Weapons::parse(&WEAPONS.replace("speed = 100", "")).is_err());
+	}
+
+	#[test]
+	fn pellet_dir() {
+		let weapons = Weapons::parse(WEAPONS).unwrap();
+		let dir = vec3(1.0, 2.0, 3.0).normalized();
+		assert_eq!(weapons.get(0).pellet_dir(dir), dir);
+		for _ in 0..100 {
+			let pellet = weapons.get(1).pellet_dir(dir);
+			assert!((pellet.len() - 1.0).abs() < 1e-5);
+			assert!(pellet.dot(dir) >= (5.0 * DEG).cos() - 1e-5);
+		}
+	}
+}
@@ -8,11 +8,14 @@ pub struct World {
 	// The entities are the "dynamic" contents of the world (players, effects, pick-ups).
 	// Entities move around, get created and destroyed during gameplay.
 	pub entities: Entities,
+
+	// What players can shoot with (see `Player::weapon`).
+	pub weapons: Weapons,
 }
 
 impl World {
-	pub fn new(map: Map, entities: Entities) -> Self {
-		Self { map, entities }
+	pub fn new(map: Map, entities: Entities, weapons: Weapons) -> Self {
+		Self { map, entities, weapons }
 	}
 
 	/// Intersect a ray (e.g. a line of sight) with the map and players except `player_id`
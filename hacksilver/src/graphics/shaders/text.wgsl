@@ -3,8 +3,8 @@
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) color: vec3<f32>, // stored in the normal, see `layout_text_colored`
     // unused:
-    @location(2) normal: vec3<f32>,
     @location(3) lm_coords: vec2<f32>,
     @location(4) tangent_u: vec3<f32>,
     @location(5) tangent_v: vec3<f32>,
@@ -13,12 +13,14 @@ struct VertexInput {
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec3<f32>,
 };
 
 @vertex
 fn vs_main(model: VertexInput) -> VertexOutput {
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
+    out.color = model.color;
     out.clip_position = vec4<f32>(model.position, 1.0);
     return out;
 }
@@ -33,5 +35,5 @@ var s_diffuse: sampler;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    return textureSample(t_diffuse, s_diffuse, in.tex_coords) * vec4<f32>(in.color, 1.0);
 }
\ No newline at end of file
@@ -12,6 +12,11 @@ use super::internal::*;
 ///  +----------------+
 ///
 pub fn layout_text(viewport_size: uvec2, pos: uvec2, text: &str) -> MeshBuffer {
+	layout_text_colored(viewport_size, pos, text, vec3::ONES)
+}
+
+/// Like `layout_text`, but tinted with `color` (multiplies the white font).
+pub fn layout_text_colored(viewport_size: uvec2, pos: uvec2, text: &str, color: vec3) -> MeshBuffer {
 	let char_size = EMBEDDED_CHAR_SIZE;
 
 	let mut buf = MeshBuffer::new();
@@ -31,7 +36,7 @@ pub fn layout_text(viewport_size: uvec2, pos: uvec2, text: &str) -> MeshBuffer {
 			char_pos[1] += char_size.y();
 		}
 
-		buf.append(&blit_chr(viewport_size, char_pos, byte));
+		buf.append(&blit_chr(viewport_size, char_pos, byte, color));
 
 		char_pos[0] += char_size.x();
 	}
@@ -80,12 +85,13 @@ pub fn text_size_pix(text: &str) -> uvec2 {
 }
 
 /// A mesh for copying a single character to the screen.
-fn blit_chr(scrn_pixels: uvec2, scrn_pos: uvec2, char: u8) -> MeshBuffer {
+/// The text shader takes the color from the (otherwise unused) vertex normal.
+fn blit_chr(scrn_pixels: uvec2, scrn_pos: uvec2, char: u8, color: vec3) -> MeshBuffer {
 	let tex_pixels = EMBEDDED_FONTMAP_SIZE;
 	let sprite_pixels = EMBEDDED_CHAR_SIZE;
 	let tex_pos = chr_tex_pos_16x8(char, sprite_pixels);
 
-	blit(tex_pixels, tex_pos, sprite_pixels, scrn_pixels, scrn_pos)
+	blit(tex_pixels, tex_pos, sprite_pixels, scrn_pixels, scrn_pos).with(|buf| buf.vertices.iter_mut().for_each(|v| v.normal = color))
 }
 
 /// Pixel position (top-left corner) of an ascii character in the embedded font map.
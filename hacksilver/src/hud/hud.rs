@@ -1,8 +1,13 @@
 use super::internal::*;
+use std::collections::VecDeque;
 
 pub struct HUD {
 	ctx: Arc<GraphicsCtx>,
 	slots: [Slot; 7],
+	chat: VecDeque<(Slot, vec3)>, // recent chat lines, oldest first, with their color
+	health: Option<i32>,          // local player's health, None when not playing
+	weapon: String,               // shown next to the health
+	damage_flash: f32,            // seconds left to show health and crosshair in red after taking damage
 	pub crosshair: bool,
 	cache: Cache<Object>,
 }
@@ -13,6 +18,14 @@ struct Slot {
 	ttl_secs: f32,
 }
 
+// Chat lines shown at once, and for how long.
+const CHAT_LINES: usize = 6;
+const CHAT_TTL: f32 = 10.0;
+
+const DAMAGE_FLASH_TTL: f32 = 0.4;
+const LOW_HEALTH: i32 = 25;
+const DAMAGE_COLOR: vec3 = vec3(1.0, 0.2, 0.2);
+
 #[derive(Copy, Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
 #[repr(u8)]
 pub enum HUDPos {
@@ -32,6 +45,10 @@ impl HUD {
 		Self {
 			ctx: ctx.clone(),
 			slots: default(),
+			chat: default(),
+			health: None,
+			weapon: default(),
+			damage_flash: 0.0,
 			crosshair: true,
 			cache: default(),
 		}
@@ -45,6 +62,43 @@ impl HUD {
 		Some(())
 	}
 
+	/// Show a chat line (above the bottom left text), in `color`.
+	pub fn chat(&mut self, text: impl Into<String>, color: vec3) {
+		if self.chat.len() == CHAT_LINES {
+			self.chat.pop_front();
+		}
+		self.chat.push_back((
+			Slot {
+				text: text.into(),
+				ttl_secs: CHAT_TTL,
+			},
+			color,
+		));
+		self.cache.clear();
+	}
+
+	/// Show the local player's health (None: not playing).
+	/// Flashes when it went down since the last call.
+	pub fn set_health(&mut self, health: Option<i32>) {
+		if let (Some(old), Some(new)) = (self.health, health) {
+			if new < old {
+				self.damage_flash = DAMAGE_FLASH_TTL;
+			}
+		}
+		if health != self.health {
+			self.health = health;
+			self.cache.clear();
+		}
+	}
+
+	/// Show the name of the local player's weapon (next to their health).
+	pub fn set_weapon(&mut self, name: &str) {
+		if name != self.weapon {
+			self.weapon = name.into();
+			self.cache.clear();
+		}
+	}
+
 	pub fn show_info(&mut self, text: impl Into<String>) {
 		self.set_text(HUDPos::TopLeft, text, 5.0)
 	}
@@ -65,6 +119,20 @@ impl HUD {
 				}
 			}
 		}
+		if self.damage_flash > 0.0 {
+			self.damage_flash -= dt;
+			if self.damage_flash <= 0.0 {
+				self.cache.clear();
+			}
+		}
+		for (slot, _) in &mut self.chat {
+			slot.ttl_secs -= dt;
+		}
+		let n = self.chat.len();
+		self.chat.retain(|(slot, _)| slot.ttl_secs > 0.0);
+		if self.chat.len() != n {
+			self.cache.clear();
+		}
 	}
 
 	pub fn draw_on(&self, sg: &mut SceneGraph) {
@@ -83,6 +151,16 @@ impl HUD {
 		buf.append(&layout_text_right(viewport, text(TopRight)));
 		buf.append(&layout_text_bottom(viewport, text(BottomLeft)));
 
+		{
+			// chat lines above the bottom left text (one line, e.g. spectating info)
+			let lines = self.chat.iter().map(|(slot, _)| text_height_chars(&slot.text)).sum::<u32>() + 2;
+			let mut pos = uvec2(0, viewport.y().saturating_sub(lines * EMBEDDED_CHAR_SIZE.y()));
+			for (slot, color) in &self.chat {
+				buf.append(&layout_text_colored(viewport, pos, &slot.text, *color));
+				pos[1] += text_height_chars(&slot.text) * EMBEDDED_CHAR_SIZE.y();
+			}
+		}
+
 		{
 			let text = text(Center);
 			// some fixed-point arithmetic to get the text about 20% above the crosshairs
@@ -102,6 +180,14 @@ impl HUD {
 			buf.append(&layout_text(viewport, pos, text));
 		}
 
+		if let Some(health) = self.health {
+			// bottom center, red when low or just hit.
+			let text = format!("health {health}  {}", self.weapon);
+			let color = if self.damage_flash > 0.0 || health <= LOW_HEALTH { DAMAGE_COLOR } else { vec3::ONES };
+			let pos = (viewport - text_size_pix(&text)) / uvec2(2, 1);
+			buf.append(&layout_text_colored(viewport, pos, &text, color));
+		}
+
 		{
 			let text = text(BottomRight);
 			let pos = viewport - text_size_pix(text);
@@ -116,8 +202,9 @@ impl HUD {
 
 	fn draw_crosshair(&self, sg: &mut SceneGraph) {
 		let center = sg.viewport / 2 - EMBEDDED_CHAR_SIZE / 2;
+		let color = if self.damage_flash > 0.0 { DAMAGE_COLOR } else { vec3::ONES };
 		sg.push(Object::new(
-			&Arc::new(self.ctx.upload_meshbuffer(&layout_text(sg.viewport, center, EMBEDDED_CROSSHAIR))),
+			&Arc::new(self.ctx.upload_meshbuffer(&layout_text_colored(sg.viewport, center, EMBEDDED_CROSSHAIR, color))),
 			self.ctx.shader_pack.text(),
 		));
 	}
@@ -14,6 +14,7 @@ pub use image::RgbImage;
 pub use log::error;
 pub use log::info;
 pub use log::trace;
+pub use log::warn;
 pub use rand::{prelude::StdRng, Rng};
 pub use rand_xoshiro::rand_core::SeedableRng;
 pub use rand_xoshiro::Xoshiro256PlusPlus;
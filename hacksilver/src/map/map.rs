@@ -4,22 +4,35 @@ use super::internal::*;
 pub struct Map {
 	name: String,
 	data: MapData,
-	face_tree: Node<Face>,
-	block_tree: Node<Block>,
+	face_tree: Option<Node<Face>>, // None: no blocks
+	block_tree: Option<Node<Block>>,
 }
 
 impl Map {
 	/// Load a Map from directory `assets/maps/map_name.hx`.
 	pub fn load(assets: &AssetsDir, map_name: &str) -> Result<Self> {
-		let data = MapData::load(&assets.map_dir(map_name))?;
+		Self::load_dir(&assets.map_dir(map_name), map_name)
+	}
+
+	/// Load a Map from any directory (e.g. a downloaded map, see `AssetsDir::cached_map_dir`).
+	pub fn load_dir(map_dir: &MapDir, map_name: &str) -> Result<Self> {
+		Ok(Self::new(map_name, MapData::load(map_dir)?))
+	}
+
+	/// A map without any blocks, to stand in while the real one is not loaded yet (e.g. downloading).
+	pub fn empty(map_name: &str) -> Self {
+		Self::new(map_name, default())
+	}
+
+	fn new(map_name: &str, data: MapData) -> Self {
 		let face_tree = Self::face_tree(&data);
 		let block_tree = Self::block_tree(&data);
-		Ok(Self {
+		Self {
 			name: map_name.into(),
 			data,
 			face_tree,
 			block_tree,
-		})
+		}
 	}
 
 	pub fn data(&self) -> &MapData {
@@ -30,29 +43,34 @@ impl Map {
 		&self.name
 	}
 
-	fn face_tree(map_data: &MapData) -> Node<Face> {
+	fn face_tree(map_data: &MapData) -> Option<Node<Face>> {
 		let faces = map_data.blocks().map(|b| b.faces()).flatten().collect::<Vec<_>>();
 		let faces = optimize_faces(faces);
-		Node::build_tree(faces)
+		(!faces.is_empty()).then(|| Node::build_tree(faces))
 	}
 
-	fn block_tree(map_data: &MapData) -> Node<Block> {
-		Node::build_tree(map_data.blocks().collect())
+	fn block_tree(map_data: &MapData) -> Option<Node<Block>> {
+		let blocks = map_data.blocks().collect::<Vec<_>>();
+		(!blocks.is_empty()).then(|| Node::build_tree(blocks))
 	}
 
 	/// Where does a ray intersect the map, if any.
 	pub fn intersect_t(&self, ray: &Ray64) -> Option<f64> {
 		// TODO: decide on precision
 		let ray = Ray::new(ray.start.to_f32(), ray.dir.to_f32());
-		self.face_tree.intersection(&ray).maybe_t().map(|t| t as f64)
+		self.intersect(&ray).maybe_t().map(|t| t as f64)
 	}
 
 	pub fn intersect(&self, ray: &Ray32) -> HitRecord<f32, (Vector3<f32>, Vector2<f32>, MatID)> {
 		// TODO: decide on precision
-		self.face_tree.intersection(&ray)
+		match &self.face_tree {
+			Some(face_tree) => face_tree.intersection(ray),
+			None => HitRecord::new(),
+		}
 	}
 
 	pub fn bumps(&self, bounds: &BoundingBox<f32>) -> bool {
+		let Some(block_tree) = &self.block_tree else { return false };
 		// *****************************
 		// TODO: don' truncate to int!!
 		// *****************************
@@ -64,7 +82,7 @@ impl Map {
 				for ix in imin.x()..=imax.x() {
 					let pos = ivec3(ix, iy, iz);
 					// TODO: more precise
-					if self.block_tree.contains(pos.to_f32()) {
+					if block_tree.contains(pos.to_f32()) {
 						return true;
 					}
 				}
@@ -73,3 +91,16 @@ impl Map {
 		false
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn empty() {
+		let map = Map::empty("deck");
+		assert_eq!(map.name(), "deck");
+		assert_eq!(map.intersect_t(&Ray64::new(dvec3(-1.0, 0.5, 0.5), dvec3(1.0, 0.0, 0.0))), None);
+		assert!(!map.bumps(&BoundingBox::new(vec3(-1.0, -1.0, -1.0), vec3(1.0, 1.0, 1.0))));
+	}
+}
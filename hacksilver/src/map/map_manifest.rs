@@ -0,0 +1,105 @@
+use super::internal::*;
+use std::fs;
+use std::hash::Hasher;
+
+/// List of the files that make up a map directory (blocks, palette, metadata, mesh, lightmaps),
+/// with their content hashes.
+///
+/// Used to check that a client has the exact same map as the server,
+/// and to download only the files that differ.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MapManifest {
+	pub files: Vec<MapFile>, // sorted by path
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MapFile {
+	pub path: String, // relative to the map directory, '/'-separated.
+	pub size: u64,
+	pub hash: u64,
+}
+
+impl MapManifest {
+	/// Hash the content files of a map directory.
+	/// Missing files are skipped (e.g. lightmaps not baked yet).
+	pub fn scan(map_dir: &MapDir) -> Result<Self> {
+		let mut paths = vec!["blocks.bincode.gz".to_owned(), "mesh.bincode.gz".into(), "metadata.json".into(), "palette.json".into()];
+		if map_dir.lightmap_dir().exists() {
+			for name in read_dir_names(&map_dir.lightmap_dir())? {
+				paths.push(format!("lm/{}", name.to_string_lossy()));
+			}
+		}
+
+		let mut files = vec![];
+		for path in paths {
+			let file = map_dir.file(&path);
+			if !file.exists() {
+				continue;
+			}
+			let data = fs::read(&file).map_err(|e| anyhow!("read {file:?}: {e}"))?;
+			files.push(MapFile {
+				size: data.len() as u64,
+				hash: hash_bytes(&data),
+				path,
+			})
+		}
+		files.sort_by(|a, b| a.path.cmp(&b.path));
+
+		Ok(Self { files })
+	}
+
+	/// Content hash of the entire map directory.
+	pub fn hash(&self) -> u64 {
+		let mut h = fnv::FnvHasher::default();
+		for f in &self.files {
+			h.write(f.path.as_bytes());
+			h.write_u64(f.size);
+			h.write_u64(f.hash);
+		}
+		h.finish()
+	}
+
+	/// Our files that are missing or different in `other`.
+	pub fn missing_in(&self, other: &MapManifest) -> Vec<MapFile> {
+		self.files.iter().filter(|f| !other.files.contains(f)).cloned().collect()
+	}
+
+	/// Whether `path` is one of our files.
+	pub fn contains(&self, path: &str) -> bool {
+		self.files.iter().any(|f| f.path == path)
+	}
+}
+
+fn hash_bytes(data: &[u8]) -> u64 {
+	let mut h = fnv::FnvHasher::default();
+	h.write(data);
+	h.finish()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn file(path: &str, data: &[u8]) -> MapFile {
+		MapFile {
+			path: path.into(),
+			size: data.len() as u64,
+			hash: hash_bytes(data),
+		}
+	}
+
+	#[test]
+	fn missing_in() {
+		let server = MapManifest {
+			files: vec![file("blocks.bincode.gz", b"blocks"), file("lm/amb1.png", b"new"), file("palette.json", b"{}")],
+		};
+		let client = MapManifest {
+			files: vec![file("blocks.bincode.gz", b"blocks"), file("lm/amb1.png", b"old"), file("lm/amb2.png", b"stale")],
+		};
+
+		let missing = server.missing_in(&client).into_iter().map(|f| f.path).collect::<Vec<_>>();
+		assert_eq!(missing, vec!["lm/amb1.png", "palette.json"]);
+		assert_ne!(server.hash(), client.hash());
+		assert_eq!(server.hash(), server.clone().hash());
+	}
+}
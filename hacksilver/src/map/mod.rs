@@ -11,6 +11,7 @@ mod face;
 mod host_object;
 mod map;
 mod map_data;
+mod map_manifest;
 mod mat_id;
 mod metadata;
 mod palette;
@@ -25,6 +26,7 @@ pub use face::*;
 pub use host_object::*;
 pub use map::*;
 pub use map_data::*;
+pub use map_manifest::*;
 pub use mat_id::*;
 pub use metadata::*;
 pub use palette::*;
@@ -45,6 +45,12 @@ where
 	trunc_to_zone(b.ibounds().min)
 }
 
+/// Distance between the zones `a` and `b` are in, counted in zones.
+/// Neighbouring zones (also diagonally) are 1 apart.
+pub fn zones_apart(a: ivec3, b: ivec3) -> u32 {
+	(trunc_to_zone(a) - trunc_to_zone(b)).map(|v| (v / ZONE_ISIZE).unsigned_abs()).reduce(u32::max)
+}
+
 pub fn is_zone_aligned(p: ivec3) -> bool {
 	p == trunc_to_zone(p)
 }
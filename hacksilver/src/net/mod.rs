@@ -1,6 +1,12 @@
 mod internal;
 
+pub mod net_sim;
 pub mod netpipe;
+pub mod rate_limit;
+pub mod udp;
 pub mod wireformat;
 
+pub use net_sim::*;
 pub use netpipe::*;
+pub use rate_limit::*;
+pub use udp::*;
@@ -0,0 +1,235 @@
+use super::internal::*;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::mpsc::RecvTimeoutError;
+
+/// Bad network conditions to simulate on a `NetPipe` (see `NetPipe::simulate`), for testing the netcode.
+///
+/// Applies to both directions: messages we send and messages we receive.
+/// Loss and reordering only apply to unreliable messages (see `Delivery`),
+/// reliable messages are delayed but stay in order.
+///
+/// Parsed from e.g. `latency=80,jitter=20,loss=0.05,reorder=0.02,bandwidth=64000,disconnect=0.01,seed=1`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct NetSimOpts {
+	pub latency_ms: u32, // added one-way delay
+	pub jitter_ms: u32,  // random extra delay, between 0 and this
+	pub loss: f64,       // probability of dropping an unreliable message
+	pub reorder: f64,    // probability of holding back an unreliable message behind later ones
+	pub bandwidth_bytes_per_sec: Option<u32>,
+	pub disconnect: f64, // probability per second of the connection dropping
+	pub seed: u64,       // for reproducible runs
+}
+
+impl FromStr for NetSimOpts {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		let mut opts = Self::default();
+		for kv in s.split(',').map(str::trim).filter(|kv| !kv.is_empty()) {
+			let (key, value) = kv.split_once('=').ok_or_else(|| anyhow!("netsim: expected key=value, got `{kv}`"))?;
+			let err = |e: &dyn fmt::Display| anyhow!("netsim: {key}: {e}");
+			match key {
+				"latency" => opts.latency_ms = value.parse().map_err(|e| err(&e))?,
+				"jitter" => opts.jitter_ms = value.parse().map_err(|e| err(&e))?,
+				"loss" => opts.loss = value.parse().map_err(|e| err(&e))?,
+				"reorder" => opts.reorder = value.parse().map_err(|e| err(&e))?,
+				"bandwidth" => opts.bandwidth_bytes_per_sec = Some(value.parse().map_err(|e| err(&e))?),
+				"disconnect" => opts.disconnect = value.parse().map_err(|e| err(&e))?,
+				"seed" => opts.seed = value.parse().map_err(|e| err(&e))?,
+				_ => return Err(anyhow!("netsim: unknown option `{key}` (have: latency, jitter, loss, reorder, bandwidth, disconnect, seed)")),
+			}
+		}
+		Ok(opts)
+	}
+}
+
+/// Spawn a thread passing messages from `input` to `output` as a bad network would.
+/// `info` tells whether a message is reliable, and its size in bytes (for the bandwidth cap).
+/// On a simulated disconnect, `on_disconnect` (if any) is delivered and the link is cut.
+pub(crate) fn spawn_sim_link<M>(opts: NetSimOpts, seed: u64, input: Receiver<M>, output: Sender<M>, info: impl Fn(&M) -> (bool, usize) + Send + 'static, on_disconnect: Option<fn() -> M>)
+where
+	M: Send + 'static,
+{
+	thread::spawn(move || SimLink::new(opts, seed).run(input, output, info, on_disconnect));
+}
+
+struct SimLink<M> {
+	opts: NetSimOpts,
+	rng: Xoshiro256PlusPlus,
+	queue: BTreeMap<(Instant, u64), M>, // by delivery time, then arrival order
+	seq: u64,
+	busy_until: Instant,    // end of the transmission of the previous message (bandwidth cap)
+	last_reliable: Instant, // delivery time of the last reliable message (to keep them in order)
+	disconnect_at: Option<Instant>,
+}
+
+impl<M> SimLink<M> {
+	fn new(opts: NetSimOpts, seed: u64) -> Self {
+		let now = Instant::now();
+		// Drawn once, from a separate stream: the other draws must not depend on how often the loop wakes up.
+		let disconnect_at = (opts.disconnect > 0.0).then(|| {
+			let mut rng = Xoshiro256PlusPlus::seed_from_u64(seed).with(|rng| rng.long_jump());
+			let secs = -f64::ln(1.0 - rng.gen::<f64>()) / opts.disconnect; // exponentially distributed
+			now.checked_add(Duration::from_secs_f64(f64::min(secs, 1e9)))
+		});
+		Self {
+			opts,
+			rng: Xoshiro256PlusPlus::seed_from_u64(seed),
+			queue: default(),
+			seq: 0,
+			busy_until: now,
+			last_reliable: now,
+			disconnect_at: disconnect_at.flatten(),
+		}
+	}
+
+	fn run(mut self, input: Receiver<M>, output: Sender<M>, info: impl Fn(&M) -> (bool, usize), on_disconnect: Option<fn() -> M>) {
+		let mut input_closed = false;
+		loop {
+			let timeout = match self.queue.keys().next() {
+				Some((deliver_at, _)) => deliver_at.saturating_duration_since(Instant::now()),
+				None if input_closed => return, // all delivered
+				None => Duration::from_millis(100),
+			};
+			match input_closed {
+				true => thread::sleep(timeout),
+				false => match input.recv_timeout(timeout) {
+					Ok(msg) => {
+						let (reliable, size) = info(&msg);
+						self.schedule(msg, reliable, size)
+					}
+					Err(RecvTimeoutError::Timeout) => (),
+					Err(RecvTimeoutError::Disconnected) => input_closed = true,
+				},
+			}
+
+			let now = Instant::now();
+			if let (Some(on_disconnect), Some(disconnect_at)) = (on_disconnect, self.disconnect_at) {
+				if now >= disconnect_at {
+					let _ = output.send(on_disconnect());
+					return;
+				}
+			}
+
+			while let Some(entry) = self.queue.first_entry() {
+				if entry.key().0 > now {
+					break;
+				}
+				if output.send(entry.remove()).is_err() {
+					return; // receiving end hung up
+				}
+			}
+		}
+	}
+
+	fn schedule(&mut self, msg: M, reliable: bool, size: usize) {
+		let opts = &self.opts;
+		if !reliable && self.rng.gen::<f64>() < opts.loss {
+			return;
+		}
+
+		let now = Instant::now();
+		let transmit = opts
+			.bandwidth_bytes_per_sec
+			.map(|bw| Duration::from_secs_f64(size as f64 / f64::max(1.0, bw as f64)))
+			.unwrap_or_default();
+		self.busy_until = Instant::max(now, self.busy_until) + transmit;
+
+		let jitter = self.rng.gen_range(0..=opts.jitter_ms);
+		let mut delay = Duration::from_millis((opts.latency_ms + jitter) as u64);
+		if !reliable && self.rng.gen::<f64>() < opts.reorder {
+			delay += Duration::from_millis((opts.latency_ms + opts.jitter_ms + 1) as u64);
+		}
+		let mut deliver_at = self.busy_until + delay;
+		if reliable {
+			deliver_at = Instant::max(deliver_at, self.last_reliable);
+			self.last_reliable = deliver_at;
+		}
+
+		self.seq += 1;
+		self.queue.insert((deliver_at, self.seq), msg);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn parse() {
+		let opts: NetSimOpts = "latency=80, jitter=20,loss=0.5,bandwidth=1000,seed=3".parse().unwrap();
+		assert_eq!(opts.latency_ms, 80);
+		assert_eq!(opts.jitter_ms, 20);
+		assert_eq!(opts.loss, 0.5);
+		assert_eq!(opts.bandwidth_bytes_per_sec, Some(1000));
+		assert_eq!(opts.seed, 3);
+		assert!("latency=fast".parse::<NetSimOpts>().is_err());
+		assert!("speed=1".parse::<NetSimOpts>().is_err());
+	}
+
+	// messages: (reliable, number)
+	fn run_link(opts: NetSimOpts, msgs: &[(bool, u32)]) -> Vec<(bool, u32)> {
+		run_link_with(opts, msgs, Duration::ZERO)
+	}
+
+	// Sending a message every `interval`. A disconnect is delivered as `(true, u32::MAX)`.
+	fn run_link_with(opts: NetSimOpts, msgs: &[(bool, u32)], interval: Duration) -> Vec<(bool, u32)> {
+		let (send, input) = channel();
+		let (output, recv) = channel();
+		spawn_sim_link(opts, 1, input, output, |&(reliable, _)| (reliable, 10), Some(|| (true, u32::MAX)));
+		for &msg in msgs {
+			let _ = send.send(msg); // link may be cut already
+			thread::sleep(interval);
+		}
+		drop(send);
+		recv.iter().collect()
+	}
+
+	#[test]
+	fn reliable_in_order() {
+		let msgs = (0..100).map(|i| (true, i)).collect::<Vec<_>>();
+		let opts = NetSimOpts {
+			latency_ms: 5,
+			jitter_ms: 20,
+			loss: 1.0,
+			reorder: 1.0,
+			..default()
+		};
+		assert_eq!(run_link(opts, &msgs), msgs);
+	}
+
+	#[test]
+	fn unreliable_lost() {
+		let msgs = (0..100).map(|i| (i % 2 == 0, i)).collect::<Vec<_>>();
+		let opts = NetSimOpts { loss: 1.0, ..default() };
+		let got = run_link(opts, &msgs);
+		assert_eq!(got, msgs.into_iter().filter(|(reliable, _)| *reliable).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn reproducible() {
+		// same seed, same losses: however often the link happens to wake up.
+		let msgs = (0..50).map(|i| (false, i)).collect::<Vec<_>>();
+		let opts = NetSimOpts { loss: 0.5, ..default() };
+		let got = run_link_with(opts.clone(), &msgs, Duration::ZERO);
+		assert_eq!(run_link_with(opts, &msgs, Duration::from_millis(3)), got);
+		assert!(got.len() < msgs.len() && !got.is_empty());
+	}
+
+	#[test]
+	fn disconnect() {
+		let opts = NetSimOpts { disconnect: 1000.0, ..default() };
+		let got = run_link_with(opts, &[(true, 1), (true, 2)], Duration::from_millis(100));
+		assert_eq!(got.last(), Some(&(true, u32::MAX)));
+	}
+
+	#[test]
+	fn latency() {
+		let start = Instant::now();
+		let opts = NetSimOpts { latency_ms: 50, ..default() };
+		assert_eq!(run_link(opts, &[(true, 1)]), vec![(true, 1)]);
+		assert!(start.elapsed() >= Duration::from_millis(50));
+	}
+}
@@ -1,9 +1,12 @@
+use super::net_sim::*;
+use super::udp::*;
 use super::wireformat::*;
 
 use anyhow::anyhow;
 use anyhow::Result;
 
 use serde::de::DeserializeOwned;
+use serde::Deserialize;
 use serde::Serialize;
 
 use std::io::Write;
@@ -24,15 +27,45 @@ where
 	S: Serialize + DeserializeOwned + Send + 'static,
 	R: Serialize + DeserializeOwned + Send + 'static,
 {
-	pub fn new(tcp_stream: TcpStream) -> Self {
+	/// A NetPipe over TCP. Received messages larger than `max_frame` bytes are refused (see `wireformat`).
+	pub fn new(tcp_stream: TcpStream, max_frame: u32) -> Self {
 		Self {
 			send: NetSender::new(tcp_stream.try_clone().expect("clone TCP stream")),
-			recv: NetReceiver::new(tcp_stream.try_clone().expect("clone TCP stream")),
+			recv: NetReceiver::new(tcp_stream.try_clone().expect("clone TCP stream"), max_frame),
 		}
 	}
 
 	pub fn connect(server: &str) -> Result<Self> {
-		Ok(NetPipe::new(TcpStream::connect(server)?))
+		Ok(NetPipe::new(TcpStream::connect(server)?, DEFAULT_MAX_FRAME))
+	}
+
+	/// A NetPipe over UDP. Messages are sent reliably or not depending on their `Delivery`.
+	pub fn udp(conn: UdpConn, max_frame: u32) -> Self
+	where
+		S: Delivery,
+	{
+		Self {
+			send: NetSender::udp(conn.clone()),
+			recv: NetReceiver::udp(conn, max_frame),
+		}
+	}
+
+	/// A pair of NetPipes connected to each other in-process, without sockets or threads.
+	/// Messages are not serialized, and arrive as soon as they are sent.
+	/// For running a server in the same process as the client (single player, tests).
+	pub fn loopback() -> (Self, NetPipe<R, S>) {
+		let (send_s, recv_s) = channel();
+		let (send_r, recv_r) = channel();
+		(
+			Self {
+				send: NetSender(Upload::Loopback(send_s)),
+				recv: NetReceiver { recv: recv_r, bytes_received: 0 },
+			},
+			NetPipe {
+				send: NetSender(Upload::Loopback(send_r)),
+				recv: NetReceiver { recv: recv_s, bytes_received: 0 },
+			},
+		)
 	}
 
 	pub fn send(&mut self, msg: S) -> Result<()> {
@@ -42,15 +75,57 @@ where
 	pub fn try_recv(&mut self) -> Option<Result<R>> {
 		self.recv.try_recv()
 	}
+
+	/// Total size on the wire of the messages received so far.
+	pub fn bytes_received(&self) -> u64 {
+		self.recv.bytes_received
+	}
+
+	/// Pass messages in both directions through a simulated bad network (see `NetSimOpts`).
+	pub fn simulate(self, opts: &NetSimOpts) -> Self
+	where
+		S: Delivery,
+		R: Delivery,
+	{
+		Self {
+			send: self.send.simulate(opts.clone(), opts.seed),
+			recv: self.recv.simulate(opts.clone(), opts.seed.wrapping_add(1)),
+		}
+	}
+}
+
+/// Which messages need to arrive, on transports that can drop messages (UDP).
+pub trait Delivery {
+	/// Reliable messages arrive exactly once and in order.
+	/// Unreliable messages may get lost, and are dropped if they arrive after a later unreliable message.
+	/// (E.g. position updates, which are superseded by the next one anyway).
+	fn is_reliable(&self) -> bool;
+}
+
+/// Transport protocol used by `NetPipe`s.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+	#[default]
+	Tcp,
+	Udp,
 }
 
 /// A typed, non-blocking network conduit.
 /// Sends messages of type `T`.
-pub struct NetSender<T>(Sender<T>);
+pub struct NetSender<T>(Upload<T>);
+
+enum Upload<T> {
+	Worker(Sender<T>),                    // to a thread serializing onto the wire
+	Loopback(Sender<Result<(T, usize)>>), // directly to the `NetReceiver` on the other end
+}
 
 /// A typed, non-blocking network conduit.
 /// Receives messages of type `T`.
-pub struct NetReceiver<T>(Receiver<Result<T>>);
+pub struct NetReceiver<T> {
+	recv: Receiver<Result<(T, usize)>>, // message, size on the wire
+	bytes_received: u64,
+}
 
 impl<T> NetSender<T>
 where
@@ -60,12 +135,47 @@ where
 		tcp_stream.set_nodelay(true).expect("set TCP no delay");
 		let (send, worker_recv) = channel();
 		start_upload(tcp_stream, worker_recv);
-		Self(send)
+		Self(Upload::Worker(send))
+	}
+
+	pub fn udp(conn: UdpConn) -> Self
+	where
+		T: Delivery,
+	{
+		let (send, worker_recv) = channel();
+		start_udp_upload(conn, worker_recv);
+		Self(Upload::Worker(send))
 	}
 
 	/// Attempts to send a Message.
 	pub fn send(&mut self, msg: T) -> Result<()> {
-		self.0.send(msg).map_err(|err| anyhow!("netpipe: {}", err))
+		match &self.0 {
+			Upload::Worker(send) => send.send(msg).map_err(|err| anyhow!("netpipe: {}", err)),
+			Upload::Loopback(send) => send.send(Ok((msg, 0))).map_err(|err| anyhow!("netpipe: {}", err)),
+		}
+	}
+
+	fn simulate(self, opts: NetSimOpts, seed: u64) -> Self
+	where
+		T: Delivery,
+	{
+		let size = |msg: &T| bincode::serialized_size(msg).unwrap_or_default() as usize;
+		match self.0 {
+			Upload::Worker(output) => {
+				let (send, sim_recv) = channel();
+				spawn_sim_link(opts, seed, sim_recv, output, move |msg| (msg.is_reliable(), size(msg)), None);
+				Self(Upload::Worker(send))
+			}
+			Upload::Loopback(output) => {
+				let (send, sim_recv) = channel();
+				let info = move |msg: &Result<(T, usize)>| match msg {
+					Ok((msg, _)) => (msg.is_reliable(), size(msg)),
+					Err(_) => (true, 0),
+				};
+				spawn_sim_link(opts, seed, sim_recv, output, info, None);
+				Self(Upload::Loopback(send))
+			}
+		}
 	}
 }
 
@@ -73,20 +183,43 @@ impl<T> NetReceiver<T>
 where
 	T: Serialize + DeserializeOwned + Send + 'static,
 {
-	pub fn new(tcp_stream: TcpStream) -> Self {
+	pub fn new(tcp_stream: TcpStream, max_frame: u32) -> Self {
 		tcp_stream.set_nodelay(true).expect("set TCP no delay");
 		let (worker_send, recv) = channel();
-		start_download(tcp_stream, worker_send);
-		Self(recv)
+		start_download(tcp_stream, max_frame, worker_send);
+		Self { recv, bytes_received: 0 }
+	}
+
+	pub fn udp(conn: UdpConn, max_frame: u32) -> Self {
+		let (worker_send, recv) = channel();
+		start_udp_download(conn, max_frame, worker_send);
+		Self { recv, bytes_received: 0 }
 	}
 
 	pub fn try_recv(&mut self) -> Option<Result<T>> {
-		match self.0.try_recv() {
-			Ok(result) => Some(result),
+		match self.recv.try_recv() {
+			Ok(Ok((msg, size))) => {
+				self.bytes_received += size as u64;
+				Some(Ok(msg))
+			}
+			Ok(Err(e)) => Some(Err(e)),
 			Err(TryRecvError::Empty) => None,
 			Err(e) => Some(Err(Box::new(e).into())),
 		}
 	}
+
+	fn simulate(self, opts: NetSimOpts, seed: u64) -> Self
+	where
+		T: Delivery,
+	{
+		let (sim_send, recv) = channel();
+		let info = |msg: &Result<(T, usize)>| match msg {
+			Ok((msg, size)) => (msg.is_reliable(), *size),
+			Err(_) => (true, 0),
+		};
+		spawn_sim_link(opts, seed, self.recv, sim_send, info, Some(|| Err(anyhow!("simulated disconnect"))));
+		Self { recv, ..self }
+	}
 }
 
 // Spawn a loop taking messages from `worker_recv` and serializing them to `tcp_stream`.
@@ -125,25 +258,68 @@ where
 }
 
 // Spawn a loop deserializing messages from `tcp_stream` and sending them to `worker_send`.
-// Drops `worker_send` on error, causing future `recv` calls to error out.
-fn start_download<T>(tcp_stream: TcpStream, worker_send: Sender<Result<T>>)
+// On error, sends the error (explaining e.g. why a message was refused)
+// and drops `worker_send`, causing future `recv` calls to error out.
+fn start_download<T>(tcp_stream: TcpStream, max_frame: u32, worker_send: Sender<Result<(T, usize)>>)
 where
 	T: DeserializeOwned + Send + 'static,
 {
 	let mut buf = BufReader::new(tcp_stream.try_clone().expect("clone TCP steam"));
 	spawn(move || loop {
-		//match bincode::deserialize_from(&mut buf) {
-		match deserialize_from(&mut buf) {
+		match deserialize_limited(&mut buf, max_frame) {
 			Ok(msg) => {
 				if let Err(_e) = worker_send.send(Ok(msg)) {
 					let _ = tcp_stream.shutdown(Shutdown::Both);
 					return;
 				}
 			}
-			Err(_e) => {
+			Err(e) => {
+				let _ = worker_send.send(Err(e));
 				let _ = tcp_stream.shutdown(Shutdown::Both);
 				return;
 			}
 		}
 	});
 }
+
+// Spawn a loop taking messages from `worker_recv` and sending them over `conn`,
+// over the reliable or unreliable channel depending on the message.
+// Like `start_upload`, errors are to be handled on receive.
+fn start_udp_upload<T>(conn: UdpConn, worker_recv: Receiver<T>)
+where
+	T: Serialize + Delivery + Send + 'static,
+{
+	spawn(move || {
+		let _ = || -> Result<()> {
+			loop {
+				let msg = worker_recv.recv()?;
+				let mut buf = Vec::new();
+				serialize_into(&mut buf, &msg)?;
+				match msg.is_reliable() {
+					true => conn.send_reliable(&buf)?,
+					false => conn.send_unreliable(&buf)?,
+				}
+			}
+		}();
+	});
+}
+
+// Spawn a loop receiving messages from `conn` and sending them to `worker_send`.
+// Like `start_download`, sends the error and drops `worker_send` on error.
+fn start_udp_download<T>(conn: UdpConn, max_frame: u32, worker_send: Sender<Result<(T, usize)>>)
+where
+	T: DeserializeOwned + Send + 'static,
+{
+	spawn(move || loop {
+		let msg = match conn.recv().and_then(|buf| deserialize_limited(&buf[..], max_frame)) {
+			Ok(msg) => msg,
+			Err(e) => {
+				let _ = worker_send.send(Err(e));
+				return;
+			}
+		};
+		if worker_send.send(Ok(msg)).is_err() {
+			return;
+		}
+	});
+}
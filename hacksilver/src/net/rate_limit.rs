@@ -0,0 +1,71 @@
+use super::internal::*;
+
+/// Limits the number of messages and bytes per second received from a peer.
+///
+/// A token bucket: unused allowance is saved up for at most `BURST` seconds,
+/// so that short bursts (e.g. after a hitch on the client) are fine.
+pub struct RateLimit {
+	max_msgs: f64,  // per second
+	max_bytes: f64, // per second
+	msgs: f64,      // allowance left
+	bytes: f64,
+	last: Instant,
+}
+
+/// Seconds of unused allowance that can be saved up.
+const BURST: f64 = 2.0;
+
+impl RateLimit {
+	/// Full allowance at time `now`.
+	pub fn new(now: Instant, max_msgs_per_sec: u32, max_bytes_per_sec: u32) -> Self {
+		let (max_msgs, max_bytes) = (max_msgs_per_sec as f64, max_bytes_per_sec as f64);
+		Self {
+			max_msgs,
+			max_bytes,
+			msgs: max_msgs * BURST,
+			bytes: max_bytes * BURST,
+			last: now,
+		}
+	}
+
+	/// Spend allowance for receiving `msgs` messages totalling `bytes` at time `now`.
+	/// Returns an error explaining which limit was exceeded.
+	pub fn spend(&mut self, now: Instant, msgs: u32, bytes: u64) -> Result<()> {
+		let dt = now.saturating_duration_since(self.last).as_secs_f64();
+		self.last = Instant::max(self.last, now);
+		self.msgs = f64::min(self.msgs + self.max_msgs * dt, self.max_msgs * BURST) - msgs as f64;
+		self.bytes = f64::min(self.bytes + self.max_bytes * dt, self.max_bytes * BURST) - bytes as f64;
+
+		if self.msgs < 0.0 {
+			return Err(anyhow!("more than {} messages per second", self.max_msgs));
+		}
+		if self.bytes < 0.0 {
+			return Err(anyhow!("more than {} bytes per second", self.max_bytes));
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn steady_rate_is_ok() {
+		let start = Instant::now();
+		let mut limit = RateLimit::new(start, 100, 1000);
+		for i in 1..1000 {
+			limit.spend(start + Duration::from_millis(10 * i), 1, 10).unwrap();
+		}
+	}
+
+	#[test]
+	fn flood_is_not_ok() {
+		let start = Instant::now();
+		let mut limit = RateLimit::new(start, 100, 1000);
+		assert!((1..1000).any(|i| limit.spend(start + Duration::from_millis(i), 1, 1).is_err()));
+
+		let mut limit = RateLimit::new(start, 100, 1000);
+		assert!((1..1000).any(|i| limit.spend(start + Duration::from_millis(10 * i), 1, 100).is_err()));
+	}
+}
@@ -0,0 +1,507 @@
+use super::internal::*;
+
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::net::ToSocketAddrs;
+use std::net::UdpSocket;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Weak;
+
+/// A message-oriented connection to a single peer over UDP,
+/// with two channels:
+///
+///   * reliable: messages arrive exactly once, in order (resent until acknowledged).
+///   * unreliable: messages may get lost, late messages are dropped (sequenced).
+///
+/// The two channels are not ordered with respect to each other.
+///
+/// Cheap to clone: clones share the same connection.
+#[derive(Clone)]
+pub struct UdpConn {
+	inner: Arc<Inner>,
+}
+
+/// Accepts incoming `UdpConn`s on a single UDP socket, demultiplexing by peer address.
+/// The UDP equivalent of a `TcpListener`.
+pub struct UdpListener {
+	socket: Arc<UdpSocket>,
+}
+
+// Largest payload sent in one datagram.
+// Larger reliable messages are split in fragments,
+// larger unreliable messages are sent reliably instead.
+const MAX_PAYLOAD: usize = 1024;
+
+// Unacknowledged reliable packets are re-sent after this time.
+const RESEND_INTERVAL: Duration = Duration::from_millis(100);
+
+// Send a keep-alive packet if we did not send anything for this long,
+// so that an idle connection does not time out.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_millis(500);
+
+// The connection is considered dead after not hearing from the peer for this long.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+// Reliable packets further ahead than this are dropped (the peer will re-send them),
+// so that a peer cannot fill up our re-ordering buffer.
+const MAX_EARLY: u32 = 1024;
+
+// New connections accepted per source IP address per `NEW_CONN_WINDOW`, and connections in total.
+// (Source addresses can be spoofed: each new connection costs threads until it times out.)
+const MAX_NEW_CONNS_PER_IP: u32 = 8;
+const NEW_CONN_WINDOW: Duration = Duration::from_secs(1);
+const MAX_PEERS: usize = 1024;
+
+// How often a `UdpListener` forgets connections that were dropped.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+// Largest reliable message we re-assemble: a frame (see `wireformat`), plus some slack for its header.
+const MAX_MESSAGE: usize = MAX_FRAME as usize + MAX_PAYLOAD;
+
+/// What goes over the wire in a single datagram.
+#[derive(Serialize, Deserialize)]
+enum Packet {
+	Reliable { seq: u32, last_fragment: bool, payload: Vec<u8> },
+	Unreliable { seq: u32, payload: Vec<u8> },
+	// All reliable packets up to and including `seq` have been received.
+	Ack(u32),
+	KeepAlive,
+	Close,
+}
+
+struct Inner {
+	socket: Arc<UdpSocket>,
+	peer: SocketAddr,
+	incoming: Mutex<Receiver<Vec<u8>>>, // raw datagrams from `peer`
+	send: Mutex<SendState>,
+	recv: Mutex<RecvState>,
+}
+
+struct SendState {
+	next_reliable: u32,
+	next_unreliable: u32,
+	unacked: VecDeque<(u32, Vec<u8>, Instant)>, // encoded packets, time last sent
+	last_sent: Instant,
+}
+
+#[derive(Default)]
+struct RecvState {
+	next_reliable: u32,
+	early: BTreeMap<u32, (bool, Vec<u8>)>, // reliable packets received out of order
+	fragments: Vec<u8>,                    // reliable message being re-assembled
+	last_unreliable: Option<u32>,
+	ready: VecDeque<Vec<u8>>, // complete messages not yet returned by `recv`
+}
+
+impl UdpConn {
+	/// Connect to a server's `UdpListener`.
+	/// Nothing is sent until the first message.
+	pub fn connect(server: &str) -> Result<Self> {
+		let peer = server.to_socket_addrs()?.next().ok_or_else(|| anyhow!("cannot resolve {server}"))?;
+		let bind = if peer.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+		let socket = Arc::new(UdpSocket::bind(bind)?);
+		socket.set_read_timeout(Some(RESEND_INTERVAL))?;
+
+		let (send, recv) = channel();
+		let conn = Self::new(socket.clone(), peer, recv);
+		spawn_client_download(socket, peer, send, Arc::downgrade(&conn.inner));
+		Ok(conn)
+	}
+
+	// Connection to `peer` over `socket`, whose incoming datagrams are sent to `incoming`.
+	fn new(socket: Arc<UdpSocket>, peer: SocketAddr, incoming: Receiver<Vec<u8>>) -> Self {
+		let inner = Arc::new(Inner {
+			socket,
+			peer,
+			incoming: Mutex::new(incoming),
+			send: Mutex::new(SendState {
+				next_reliable: 0,
+				next_unreliable: 0,
+				unacked: default(),
+				last_sent: Instant::now(),
+			}),
+			recv: default(),
+		});
+		spawn_resend_loop(Arc::downgrade(&inner));
+		Self { inner }
+	}
+
+	pub fn peer_addr(&self) -> SocketAddr {
+		self.inner.peer
+	}
+
+	/// Send a message that must arrive.
+	pub fn send_reliable(&self, msg: &[u8]) -> Result<()> {
+		let mut state = self.inner.send.lock().unwrap();
+		let mut chunks = msg.chunks(MAX_PAYLOAD).peekable();
+		if msg.is_empty() {
+			return self.inner.send_reliable_packet(&mut state, true, vec![]);
+		}
+		while let Some(chunk) = chunks.next() {
+			let last_fragment = chunks.peek().is_none();
+			self.inner.send_reliable_packet(&mut state, last_fragment, chunk.to_vec())?;
+		}
+		Ok(())
+	}
+
+	/// Send a message that may get lost, or dropped if it arrives after a later one.
+	pub fn send_unreliable(&self, msg: &[u8]) -> Result<()> {
+		if msg.len() > MAX_PAYLOAD {
+			return self.send_reliable(msg);
+		}
+		let mut state = self.inner.send.lock().unwrap();
+		let seq = state.next_unreliable;
+		state.next_unreliable = seq.wrapping_add(1);
+		self.inner.send_packet(&mut state, &Packet::Unreliable { seq, payload: msg.to_vec() })
+	}
+
+	/// Block until the next message arrives (from either channel).
+	/// Errors out when the peer closed the connection or timed out.
+	pub fn recv(&self) -> Result<Vec<u8>> {
+		self.recv_timeout(TIMEOUT)
+	}
+
+	/// Like `recv`, but give up if the peer sends nothing for `timeout`.
+	pub fn recv_timeout(&self, timeout: Duration) -> Result<Vec<u8>> {
+		let incoming = self.inner.incoming.lock().unwrap();
+		loop {
+			if let Some(msg) = self.inner.recv.lock().unwrap().ready.pop_front() {
+				return Ok(msg);
+			}
+			let datagram = match incoming.recv_timeout(timeout) {
+				Ok(datagram) => datagram,
+				Err(RecvTimeoutError::Timeout) => return Err(anyhow!("udp: {}: timeout", self.inner.peer)),
+				Err(RecvTimeoutError::Disconnected) => return Err(anyhow!("udp: {}: closed", self.inner.peer)),
+			};
+			// stray or corrupted datagrams happen on the internet, they should not end the connection.
+			match bincode::deserialize(&datagram) {
+				Ok(packet) => self.inner.handle_packet(packet)?,
+				Err(e) => warn!("udp: {}: dropping malformed datagram ({} bytes): {e}", self.inner.peer, datagram.len()),
+			}
+		}
+	}
+}
+
+impl Inner {
+	fn send_reliable_packet(&self, state: &mut SendState, last_fragment: bool, payload: Vec<u8>) -> Result<()> {
+		let seq = state.next_reliable;
+		state.next_reliable = seq.wrapping_add(1);
+		let packet = bincode::serialize(&Packet::Reliable { seq, last_fragment, payload })?;
+		self.socket.send_to(&packet, self.peer)?;
+		state.last_sent = Instant::now();
+		state.unacked.push_back((seq, packet, Instant::now()));
+		Ok(())
+	}
+
+	fn send_packet(&self, state: &mut SendState, packet: &Packet) -> Result<()> {
+		self.socket.send_to(&bincode::serialize(packet)?, self.peer)?;
+		state.last_sent = Instant::now();
+		Ok(())
+	}
+
+	// Re-send reliable packets that have not been acknowledged in time,
+	// send a keep-alive if idle.
+	fn resend_stale(&self) -> Result<()> {
+		let mut state = self.send.lock().unwrap();
+		let now = Instant::now();
+		let mut resent = false;
+		for (_, packet, sent) in state.unacked.iter_mut() {
+			if now - *sent > RESEND_INTERVAL {
+				self.socket.send_to(packet, self.peer)?;
+				*sent = now;
+				resent = true;
+			}
+		}
+		if resent {
+			state.last_sent = now;
+		}
+		if now - state.last_sent > KEEPALIVE_INTERVAL {
+			self.send_packet(&mut state, &Packet::KeepAlive)?;
+		}
+		Ok(())
+	}
+
+	fn handle_packet(&self, packet: Packet) -> Result<()> {
+		match packet {
+			Packet::Reliable { seq, last_fragment, payload } => self.handle_reliable(seq, last_fragment, payload),
+			Packet::Unreliable { seq, payload } => {
+				self.handle_unreliable(seq, payload);
+				Ok(())
+			}
+			Packet::Ack(seq) => {
+				self.handle_ack(seq);
+				Ok(())
+			}
+			Packet::KeepAlive => Ok(()),
+			Packet::Close => Err(anyhow!("udp: {}: closed by peer", self.peer)),
+		}
+	}
+
+	fn handle_reliable(&self, seq: u32, last_fragment: bool, payload: Vec<u8>) -> Result<()> {
+		let mut state = self.recv.lock().unwrap();
+
+		// sequence numbers compare with wrap-around
+		let in_window = seq_after(seq, state.next_reliable) && !seq_after(seq, state.next_reliable.wrapping_add(MAX_EARLY));
+		if in_window || seq == state.next_reliable {
+			state.early.insert(seq, (last_fragment, payload));
+		}
+
+		// deliver all packets that are now in order
+		loop {
+			let next = state.next_reliable;
+			match state.early.remove(&next) {
+				None => break,
+				Some((last_fragment, payload)) => {
+					state.next_reliable = next.wrapping_add(1);
+					state.fragments.extend_from_slice(&payload);
+					if state.fragments.len() > MAX_MESSAGE {
+						return Err(anyhow!("udp: {}: message exceeds {MAX_MESSAGE} bytes", self.peer));
+					}
+					if last_fragment {
+						let msg = take(&mut state.fragments);
+						state.ready.push_back(msg);
+					}
+				}
+			}
+		}
+
+		// (re-)acknowledge everything received so far, also for duplicates
+		// (our previous ack may have been lost).
+		let ack = state.next_reliable.wrapping_sub(1);
+		drop(state);
+		self.send_packet(&mut self.send.lock().unwrap(), &Packet::Ack(ack))
+	}
+
+	fn handle_unreliable(&self, seq: u32, payload: Vec<u8>) {
+		let mut state = self.recv.lock().unwrap();
+		match state.last_unreliable {
+			Some(last) if !seq_after(seq, last) => (/* late or duplicate: drop */),
+			_ => {
+				state.last_unreliable = Some(seq);
+				state.ready.push_back(payload);
+			}
+		}
+	}
+
+	fn handle_ack(&self, seq: u32) {
+		let mut state = self.send.lock().unwrap();
+		while let Some((front, _, _)) = state.unacked.front() {
+			if seq_after(*front, seq) {
+				break;
+			}
+			state.unacked.pop_front();
+		}
+	}
+}
+
+impl Drop for Inner {
+	// Let the peer know we're gone, so it does not need to wait for a timeout.
+	fn drop(&mut self) {
+		if let Ok(packet) = bincode::serialize(&Packet::Close) {
+			let _ = self.socket.send_to(&packet, self.peer);
+		}
+	}
+}
+
+// Is sequence number `a` after `b` (with wrap-around)?
+fn seq_after(a: u32, b: u32) -> bool {
+	(a.wrapping_sub(b) as i32) > 0
+}
+
+impl UdpListener {
+	pub fn bind(address: &str) -> Result<Self> {
+		Ok(Self {
+			socket: Arc::new(UdpSocket::bind(address)?),
+		})
+	}
+
+	pub fn local_addr(&self) -> Result<SocketAddr> {
+		Ok(self.socket.local_addr()?)
+	}
+
+	/// Iterate over incoming connections (blocking).
+	///
+	/// A new connection starts when a yet unknown peer sends the first packet of a reliable message
+	/// (at most `MAX_NEW_CONNS_PER_IP` per `NEW_CONN_WINDOW`, the others are ignored).
+	/// Other stray datagrams from unknown peers (e.g. from a connection that was already dropped) are ignored.
+	pub fn incoming(self) -> impl Iterator<Item = UdpConn> {
+		let mut peers = HashMap::<SocketAddr, (Sender<Vec<u8>>, Weak<Inner>)>::default();
+		let mut new_conns = HashMap::<IpAddr, (Instant, u32)>::default(); // start of the window, connections started
+		let mut last_sweep = Instant::now();
+		let mut buf = vec![0u8; MAX_DATAGRAM];
+		// wake up to sweep even if nobody sends anything.
+		if let Err(e) = self.socket.set_read_timeout(Some(SWEEP_INTERVAL)) {
+			error!("udp: {e}");
+		}
+		std::iter::from_fn(move || loop {
+			if last_sweep.elapsed() >= SWEEP_INTERVAL {
+				peers.retain(|_, (_, conn)| conn.strong_count() != 0);
+				new_conns.retain(|_, (since, _)| since.elapsed() < NEW_CONN_WINDOW);
+				last_sweep = Instant::now();
+			}
+
+			let (n, peer) = match self.socket.recv_from(&mut buf) {
+				Ok(v) => v,
+				Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => continue,
+				Err(e) => {
+					error!("udp: recv: {e}");
+					continue;
+				}
+			};
+			let datagram = buf[..n].to_vec();
+
+			if let Some((conn, _)) = peers.get(&peer) {
+				if conn.send(datagram).is_ok() {
+					continue;
+				}
+				// connection was dropped, peer may be re-connecting
+				peers.remove(&peer);
+				continue;
+			}
+
+			if !matches!(bincode::deserialize(&datagram), Ok(Packet::Reliable { seq: 0, .. })) {
+				continue;
+			}
+
+			let (since, count) = new_conns.entry(peer.ip()).or_insert((Instant::now(), 0));
+			if since.elapsed() >= NEW_CONN_WINDOW {
+				(*since, *count) = (Instant::now(), 0);
+			}
+			if *count >= MAX_NEW_CONNS_PER_IP || peers.len() >= MAX_PEERS {
+				continue;
+			}
+			*count += 1;
+
+			let (send, recv) = channel();
+			send.send(datagram).expect("send to new channel");
+			let conn = UdpConn::new(self.socket.clone(), peer, recv);
+			peers.insert(peer, (send, Arc::downgrade(&conn.inner)));
+			return Some(conn);
+		})
+	}
+}
+
+// Spawn a loop forwarding datagrams from `peer` to `send`,
+// until the connection is dropped.
+fn spawn_client_download(socket: Arc<UdpSocket>, peer: SocketAddr, send: Sender<Vec<u8>>, conn: Weak<Inner>) {
+	spawn(move || {
+		let mut buf = vec![0u8; MAX_DATAGRAM];
+		loop {
+			match socket.recv_from(&mut buf) {
+				Ok((n, from)) => {
+					if from == peer && send.send(buf[..n].to_vec()).is_err() {
+						return;
+					}
+				}
+				Err(_timeout) => {
+					if conn.strong_count() == 0 {
+						return;
+					}
+				}
+			}
+		}
+	});
+}
+
+// Spawn a loop that periodically re-sends unacknowledged packets,
+// until the connection is dropped.
+fn spawn_resend_loop(conn: Weak<Inner>) {
+	spawn(move || loop {
+		thread::sleep(RESEND_INTERVAL / 2);
+		match conn.upgrade() {
+			None => return,
+			Some(conn) => {
+				if let Err(e) = conn.resend_stale() {
+					error!("udp: {}: {e}", conn.peer);
+				}
+			}
+		}
+	});
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_seq_after() {
+		assert!(seq_after(1, 0));
+		assert!(!seq_after(0, 0));
+		assert!(!seq_after(0, 1));
+		assert!(seq_after(0, u32::MAX));
+		assert!(!seq_after(u32::MAX, 0));
+	}
+
+	#[test]
+	fn reliable_in_order() {
+		let listener = UdpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.socket.local_addr().unwrap().to_string();
+		let client = UdpConn::connect(&addr).unwrap();
+
+		let big = (0..5000).map(|i| i as u8).collect::<Vec<_>>();
+		client.send_reliable(b"hello").unwrap();
+		client.send_reliable(&big).unwrap();
+
+		// the listener needs to keep running to forward datagrams to the connection.
+		let (send, recv) = channel();
+		spawn(move || listener.incoming().for_each(|conn| send.send(conn).unwrap()));
+		let server = recv.recv().unwrap();
+		assert_eq!(server.recv().unwrap(), b"hello");
+		assert_eq!(server.recv().unwrap(), big);
+
+		server.send_unreliable(b"world").unwrap();
+		assert_eq!(client.recv().unwrap(), b"world");
+	}
+
+	#[test]
+	fn new_connections_rate_limited() {
+		let listener = UdpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.socket.local_addr().unwrap();
+		let (send, recv) = channel();
+		spawn(move || listener.incoming().for_each(|conn| send.send(conn).unwrap()));
+
+		// many "clients" from the same IP address.
+		let hello = bincode::serialize(&Packet::Reliable {
+			seq: 0,
+			last_fragment: true,
+			payload: b"hello".to_vec(),
+		})
+		.unwrap();
+		let clients = (0..3 * MAX_NEW_CONNS_PER_IP).map(|_| UdpSocket::bind("127.0.0.1:0").unwrap()).collect::<Vec<_>>();
+		for client in &clients {
+			client.send_to(&hello, addr).unwrap();
+		}
+		let conns = std::iter::from_fn(|| recv.recv_timeout(Duration::from_millis(300)).ok()).collect::<Vec<_>>();
+		assert_eq!(conns.len(), MAX_NEW_CONNS_PER_IP as usize);
+
+		// until a while later.
+		thread::sleep(NEW_CONN_WINDOW);
+		clients.last().unwrap().send_to(&hello, addr).unwrap();
+		assert!(recv.recv_timeout(Duration::from_secs(1)).is_ok());
+	}
+
+	#[test]
+	fn malformed_datagram_is_dropped() {
+		let listener = UdpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.socket.local_addr().unwrap().to_string();
+		let server_socket = listener.socket.clone();
+		let client = UdpConn::connect(&addr).unwrap();
+		client.send_reliable(b"hello").unwrap();
+
+		let (send, recv) = channel();
+		spawn(move || listener.incoming().for_each(|conn| send.send(conn).unwrap()));
+		let server = recv.recv().unwrap();
+		assert_eq!(server.recv().unwrap(), b"hello");
+
+		// garbage and a truncated packet from the server's address, then a valid message.
+		let client_addr = client.inner.socket.local_addr().unwrap();
+		server_socket.send_to(&[0xff; 7], client_addr).unwrap();
+		let packet = bincode::serialize(&Packet::Unreliable { seq: 99, payload: vec![1; 100] }).unwrap();
+		server_socket.send_to(&packet[..50], client_addr).unwrap();
+		server.send_unreliable(b"world").unwrap();
+		assert_eq!(client.recv().unwrap(), b"world");
+	}
+}
@@ -1,25 +1,116 @@
 use super::internal::*;
+use bincode::Options;
 
-const MAGIC: u64 = 0xff53434154480005;
+// Marks every message on the wire, to detect connections that do not speak this protocol at all.
+// (The protocol version itself is negotiated in the join handshake).
+const MAGIC: u64 = 0xff53434154480006;
+
+/// Every message goes on the wire as a frame:
+///
+///   magic number (u64) | payload length (u32) | payload (bincode)
+///
+/// so that the receiver can refuse oversized messages before reading (and allocating) them.
+const HEADER_LEN: usize = 12;
+
+/// Default limit on the payload size of a single message.
+/// Large enough for a map download chunk or a `MapSwitch`, see `ServerOpts::max_message_bytes`.
+pub const DEFAULT_MAX_FRAME: u32 = 1 << 20;
+
+/// Hard limit on the payload size of a single message, whatever the configured limit.
+/// Bounds the memory a peer can make us allocate while reassembling a message (see `UdpConn`).
+pub const MAX_FRAME: u32 = 16 << 20;
+
+// bincode 1's default encoding (as used by `bincode::serialize`),
+// which unlike `bincode::options()` can be given a size limit.
+fn bincode_opts() -> impl Options {
+	bincode::options().with_fixint_encoding()
+}
 
 pub fn serialize_into<W, T>(mut w: W, msg: &T) -> Result<()>
 where
 	T: Serialize + Send + 'static,
 	W: Write,
 {
-	bincode::serialize_into(&mut w, &MAGIC)?;
-	bincode::serialize_into(&mut w, msg)?;
+	let payload = bincode_opts().serialize(msg)?;
+	if payload.len() > MAX_FRAME as usize {
+		return Err(anyhow!("message of {} bytes exceeds the limit of {MAX_FRAME}", payload.len()));
+	}
+	w.write_all(&MAGIC.to_le_bytes())?;
+	w.write_all(&(payload.len() as u32).to_le_bytes())?;
+	w.write_all(&payload)?;
 	Ok(())
 }
 
-pub fn deserialize_from<R, T>(mut r: R) -> Result<T>
+/// Read a message of at most `DEFAULT_MAX_FRAME` bytes.
+pub fn deserialize_from<R, T>(r: R) -> Result<T>
 where
 	T: DeserializeOwned + Send + 'static,
 	R: Read,
 {
-	let magic: u64 = bincode::deserialize_from(&mut r)?;
+	deserialize_limited(r, DEFAULT_MAX_FRAME).map(|(msg, _)| msg)
+}
+
+/// Read a message of at most `max_frame` bytes.
+/// Also returns the number of bytes read from the wire (e.g. for rate limiting).
+pub fn deserialize_limited<R, T>(r: R, max_frame: u32) -> Result<(T, usize)>
+where
+	T: DeserializeOwned + Send + 'static,
+	R: Read,
+{
+	let payload = read_frame(r, max_frame)?;
+	Ok((decode(&payload)?, HEADER_LEN + payload.len()))
+}
+
+/// Read the payload of a frame of at most `max_frame` bytes, without decoding it
+/// (e.g. to inspect it first, see `JoinRequest::decode`).
+pub fn read_frame<R: Read>(mut r: R, max_frame: u32) -> Result<Vec<u8>> {
+	let mut header = [0u8; HEADER_LEN];
+	r.read_exact(&mut header)?;
+	let magic = u64::from_le_bytes(header[..8].try_into().unwrap());
 	if magic != MAGIC {
-		return Err(anyhow!("server-client version mismatch: want {:x}, got {:x}", MAGIC, magic));
+		return Err(anyhow!("bad magic number: want {:x}, got {:x}: not a game connection?", MAGIC, magic));
+	}
+	let len = u32::from_le_bytes(header[8..].try_into().unwrap());
+	if len > u32::min(max_frame, MAX_FRAME) {
+		return Err(anyhow!("message of {len} bytes exceeds the limit of {max_frame}"));
+	}
+
+	let mut payload = vec![0u8; len as usize];
+	r.read_exact(&mut payload)?;
+	Ok(payload)
+}
+
+/// Decode a frame's payload (see `read_frame`).
+pub fn decode<T: DeserializeOwned>(payload: &[u8]) -> Result<T> {
+	// the limit also stops a (small) message from claiming a huge String or Vec.
+	bincode_opts().with_limit(payload.len() as u64).deserialize(payload).map_err(|e| anyhow!("bad message: {e}"))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn round_trip() {
+		let mut buf = vec![];
+		serialize_into(&mut buf, &("hello".to_string(), 42u32)).unwrap();
+		let (msg, n): ((String, u32), usize) = deserialize_limited(&buf[..], 100).unwrap();
+		assert_eq!(msg, ("hello".to_string(), 42));
+		assert_eq!(n, buf.len());
+	}
+
+	#[test]
+	fn oversized() {
+		let mut buf = vec![];
+		serialize_into(&mut buf, &"x".repeat(100)).unwrap();
+		assert!(deserialize_limited::<_, String>(&buf[..], 50).is_err());
+	}
+
+	#[test]
+	fn huge_length_claim() {
+		// a tiny frame claiming to hold a 4 GB string.
+		let payload = u64::MAX.to_le_bytes();
+		let buf = [&MAGIC.to_le_bytes()[..], &(payload.len() as u32).to_le_bytes(), &payload].concat();
+		assert!(deserialize_limited::<_, String>(&buf[..], 100).is_err());
 	}
-	Ok(bincode::deserialize_from(&mut r)?)
 }
@@ -1,8 +1,23 @@
 use super::internal::*;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
 pub struct Frame {
 	pub position: vec3,
 	pub velocity: vec3,
 	pub orientation: Orientation,
 }
+
+impl Frame {
+	/// Linear interpolation between frames `a` (t=0) and `b` (t=1).
+	/// Yaw is interpolated along the shortest arc.
+	pub fn lerp(a: &Frame, b: &Frame, t: f32) -> Frame {
+		Frame {
+			position: lerp(a.position, b.position, t),
+			velocity: lerp(a.velocity, b.velocity, t),
+			orientation: Orientation {
+				yaw: wrap_angle(a.orientation.yaw + t * wrap_angle(b.orientation.yaw - a.orientation.yaw)),
+				pitch: lerp(a.orientation.pitch, b.orientation.pitch, t),
+			},
+		}
+	}
+}
@@ -9,9 +9,12 @@ pub struct Skeleton {
 	pub orientation: Orientation,
 }
 
-const G: f32 = 100.0; // TODO
+pub const G: f32 = 100.0; // TODO
 const STAIRCLIMB_SPEED: f32 = 20.0; // TODO
 
+// players walking into a step at most this high climb on top of it.
+pub const STAIR_HEIGHT: f32 = 4.2;
+
 // consider an entity standing "on the ground" if the floor is not more than this distance below.
 pub const GROUND_PROBE_DIST: f32 = 0.05;
 
@@ -69,7 +72,7 @@ impl Skeleton {
 
 		// stair climbing
 		if (xbump || zbump) && self.velocity.y() >= 0.0 {
-			let probe_pos = self.position + vec3(delta.x(), 4.2, delta.z()); // what if we kept moving horizontally and took one step up?
+			let probe_pos = self.position + vec3(delta.x(), STAIR_HEIGHT, delta.z()); // what if we kept moving horizontally and took one step up?
 			if self.pos_ok(world, probe_pos) {
 				self.position += vec3(delta.x(), 0.0, delta.z());
 			} else {
@@ -9,7 +9,7 @@ use super::internal::*;
 ///     +------|-------|-------|------->
 ///    t=0    t=1     t=2     t=3
 ///   
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Ray<T>
 where
 	T: Float,
@@ -10,6 +10,15 @@ pub struct AssetsDir(PathBuf);
 // Typestate pattern ensures correct use.
 pub struct MapDir(PathBuf);
 
+/// Map names end up in file paths, and come over the network (`MapSwitch::map_name`):
+/// only letters, digits, `_` and `-` are allowed, so that a name cannot point outside of the maps directory.
+pub fn check_map_name(map_name: &str) -> Result<()> {
+	match !map_name.is_empty() && map_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+		true => Ok(()),
+		false => Err(anyhow!("invalid map name {map_name:?}: only letters, digits, `_` and `-` allowed")),
+	}
+}
+
 impl AssetsDir {
 	/// Find the absolute path of the assets directory.
 	/// Search in the current working directory and the executable's directory.
@@ -77,6 +86,14 @@ impl AssetsDir {
 		MapDir(self.dir().join("maps").join(map_name.to_string() + ".hx"))
 	}
 
+	/// Where maps downloaded from a server are kept (see `MapDownload`).
+	/// Separate from `map_dir`, so that downloads never overwrite the player's own maps.
+	/// Errors out on names that could point outside of the cache (see `check_map_name`).
+	pub fn cached_map_dir(&self, map_name: &str) -> Result<MapDir> {
+		check_map_name(map_name)?;
+		Ok(MapDir(self.dir().join("maps").join("cache").join(map_name.to_string() + ".hx")))
+	}
+
 	pub fn audio_dir(&self) -> PathBuf {
 		self.0.join("audio")
 	}
@@ -85,6 +102,10 @@ impl AssetsDir {
 		self.0.join("materials").join(resolution.to_string())
 	}
 
+	pub fn weapons_file(&self) -> PathBuf {
+		self.0.join("weapons.toml")
+	}
+
 	pub fn settings_file(&self, file: &str) -> Result<PathBuf> {
 		Ok(self.0.parent().ok_or(anyhow!("assets parent directory not found"))?.join(file))
 	}
@@ -124,6 +145,11 @@ impl MapDir {
 		self.0.join("rec")
 	}
 
+	/// A file in this directory, by '/'-separated relative path (see `MapManifest`).
+	pub fn file(&self, relative: &str) -> PathBuf {
+		relative.split('/').fold(self.0.clone(), |path, part| path.join(part))
+	}
+
 	pub fn exists(&self) -> bool {
 		self.0.exists()
 	}
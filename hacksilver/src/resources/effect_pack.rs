@@ -26,6 +26,8 @@ pub struct EffectPack {
 /// Number of particles per unit of particle beam length.
 const PARTICLE_BEAM_DENSITY: u32 = 2;
 const PARTICLE_EXPLOSION_N: u32 = 3000;
+// How far explosion particles fly (see `particle_explosion_vao`).
+const PARTICLE_EXPLOSION_RADIUS: f32 = 22.5;
 
 impl EffectPack {
 	pub fn new(ctx: &Arc<GraphicsCtx>, assets: &AssetsDir) -> Result<Self> {
@@ -56,7 +58,15 @@ impl EffectPack {
 	}
 
 	pub fn particle_explosion(&self, pos: vec3, phase: f32) -> Object {
-		let transf = translation_matrix(pos);
+		self.explosion_with(translation_matrix(pos), phase)
+	}
+
+	/// A particle explosion scaled to fill a sphere of `radius`.
+	pub fn explosion(&self, pos: vec3, radius: f32, phase: f32) -> Object {
+		self.explosion_with(translation_matrix(pos) * scale_matrix(radius / PARTICLE_EXPLOSION_RADIUS), phase)
+	}
+
+	fn explosion_with(&self, transf: mat4, phase: f32) -> Object {
 		let vao = &self.particle_explosion;
 		// decrease number of particles over time
 		let phase = 0.8 * phase.sqrt() + 0.2 * phase;
@@ -1,4 +1,6 @@
 use super::internal::*;
+use crate::net::NetSimOpts;
+use crate::net::Transport;
 
 /// All user-controlled settings, read from "settings.toml".
 #[derive(Deserialize)]
@@ -37,7 +39,11 @@ impl Default for Controls {
 pub struct PlayerOpts {
 	pub name: String,
 	pub avatar: u8,
+	/// `red`, `blue`, `green`, or `auto`: the smallest team.
 	pub team: String,
+	/// Join as spectator: watch without playing.
+	#[serde(default)]
+	pub spectate: bool,
 }
 
 #[derive(Deserialize)]
@@ -48,6 +54,19 @@ pub struct SoundOpts {
 #[derive(Deserialize)]
 pub struct NetworkOpts {
 	pub servers: Vec<String>,
+	#[serde(default)]
+	pub transport: Transport,
+	/// Other players are shown this far in the past, so that their movement can be interpolated
+	/// between frames received from the server. Should exceed a few server ticks plus jitter.
+	#[serde(default = "default_interpolation_delay_ms")]
+	pub interpolation_delay_ms: u32,
+	/// Simulate a bad network connection, for testing (see `NetSimOpts`).
+	#[serde(default)]
+	pub sim: Option<NetSimOpts>,
+}
+
+fn default_interpolation_delay_ms() -> u32 {
+	100
 }
 
 pub fn load_toml<T: DeserializeOwned>(path: &Path) -> Result<T> {
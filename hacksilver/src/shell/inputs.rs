@@ -135,6 +135,15 @@ impl Inputs {
 		self.buttons_pressed.iter().next().copied()
 	}
 
+	/// The number key (1-9) pressed right before this tick, if any.
+	pub fn number_pressed(&self) -> Option<u8> {
+		use VirtualKeyCode::*;
+		[Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9]
+			.iter()
+			.position(|&key| self.is_pressed(Button::Key(key)))
+			.map(|i| i as u8 + 1)
+	}
+
 	/// The relative mouse movement since the last tick.
 	pub fn mouse_delta(&self) -> vec2 {
 		self.mouse_delta.convert()
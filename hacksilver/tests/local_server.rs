@@ -0,0 +1,854 @@
+//! Drive a real `NetServer` (and the `ServerState` behind it) in-process, over loopback `NetPipe`s.
+
+use hacksilver::game::*;
+use hacksilver::internal::*;
+use hacksilver::net::wireformat;
+use hacksilver::resources::*;
+use std::iter;
+
+type Conn = hacksilver::net::NetPipe<ClientMsg, ServerMsg>;
+
+fn server() -> NetServer {
+	server_with(default())
+}
+
+// Server on `deck`. Tests run from the package directory, assets are one up.
+fn server_with(opts: ServerOpts) -> NetServer {
+	std::env::set_current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/..")).unwrap();
+	NetServer::local(ServerOpts { maplist: vec!["deck".into()], ..opts }).unwrap()
+}
+
+// Server on `deck`, listening on a free TCP port of localhost, ticking on a background thread.
+// Returns the port.
+fn network_server() -> u16 {
+	std::env::set_current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/..")).unwrap();
+	let server = NetServer::bind(ServerOpts {
+		addr: "127.0.0.1:0".into(),
+		query_port: 0,
+		no_stdin_console: true,
+		maplist: vec!["deck".into()],
+		..default()
+	})
+	.unwrap();
+	let port = server.game_port();
+	server.spawn();
+	port
+}
+
+fn join_req(name: &str) -> JoinRequest {
+	JoinRequest {
+		protocol_version: PROTOCOL_VERSION,
+		capabilities: Capabilities::SUPPORTED,
+		name: name.into(),
+		avatar_id: 0,
+		team: "red".parse().ok(),
+		spectator: false,
+		session: None,
+	}
+}
+
+fn join(server: &mut NetServer, name: &str) -> (Conn, AcceptedMsg) {
+	match server.join_local(join_req(name)) {
+		(conn, JoinResponse::Accepted(accepted)) => (conn, accepted),
+		(_, JoinResponse::Rejected(rejected)) => panic!("{name} rejected: {}", rejected.reason),
+	}
+}
+
+fn drain(conn: &mut Conn) -> Vec<ServerMsg> {
+	iter::from_fn(|| conn.try_recv()).collect::<Result<_>>().unwrap()
+}
+
+// Run a command and return what the server logged back to us.
+fn command(server: &mut NetServer, conn: &mut Conn, cmd: &str) -> Vec<String> {
+	drain(conn);
+	conn.send(ClientMsg::Command(cmd.into())).unwrap();
+	server.tick().unwrap();
+	drain(conn)
+		.into_iter()
+		.filter_map(|msg| match msg {
+			ServerMsg::Log(line) => Some(line),
+			_ => None,
+		})
+		.collect()
+}
+
+#[test]
+fn join_and_spawn() {
+	let mut server = server();
+	let (mut alice, acc) = join(&mut server, "alice");
+	let (mut bob, _) = join(&mut server, "bob");
+	assert_eq!(acc.map_switch.map_name, "deck");
+
+	alice.send(ClientMsg::ReadyToSpawn).unwrap();
+	for _ in 0..3 {
+		server.tick().unwrap();
+	}
+
+	let msgs = drain(&mut bob);
+	assert!(msgs.iter().any(|msg| matches!(msg, ServerMsg::ServerTime(..))));
+	let alice_spawned = msgs.iter().any(|msg| match msg {
+		ServerMsg::UpdatePlayers(delta) => delta.players.iter().any(|p| p.id == acc.player_id && p.spawned == Some(true)),
+		_ => false,
+	});
+	assert!(alice_spawned);
+	assert!(!drain(&mut alice).is_empty());
+}
+
+#[test]
+fn name_clash() {
+	let mut server = server();
+	let (_alice, acc) = join(&mut server, "alice");
+	assert_eq!(acc.name, "alice");
+	let (_alice2, acc) = join(&mut server, "Alice");
+	assert_eq!(acc.name, "Alice2");
+	let (_bob, acc) = join(&mut server, " bob\x07 the\tbuilder with a very long name");
+	assert_eq!(acc.name, "bob_thebuilder_w");
+	let (_nobody, acc) = join(&mut server, "\n");
+	assert_eq!(acc.name, "player");
+}
+
+#[test]
+fn rename() {
+	let mut server = server();
+	let (mut alice, _) = join(&mut server, "alice");
+	let (mut bob, _) = join(&mut server, "bob");
+
+	command(&mut server, &mut bob, "name alice");
+	let mut names = teams(&mut server).into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+	names.sort();
+	assert_eq!(names, ["alice", "alice2"]);
+	assert!(drain(&mut alice).iter().any(|msg| matches!(msg, ServerMsg::Log(line) if line == "bob is now known as alice2")));
+
+	let refused = command(&mut server, &mut bob, "name alice2");
+	assert!(refused.iter().any(|line| line.contains("already named")), "{refused:?}");
+}
+
+// Older clients must still understand why they were rejected: variants keep their index.
+#[test]
+fn reject_reason_encoding() {
+	let mut buf = vec![];
+	wireformat::serialize_into(&mut buf, &RejectReason::Banned).unwrap();
+	let payload = wireformat::read_frame(&buf[..], wireformat::DEFAULT_MAX_FRAME).unwrap();
+	assert_eq!(payload, 4u32.to_le_bytes());
+}
+
+// Clients of other versions may send a differently laid out `JoinRequest`,
+// they are still told why they cannot join.
+#[test]
+fn version_checked_before_decoding() {
+	let port = network_server();
+	let join = |request: &(u32, Vec<f64>, String)| {
+		let mut conn = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+		wireformat::serialize_into(&mut conn, request).unwrap();
+		match wireformat::deserialize_from(&mut conn).unwrap() {
+			JoinResponse::Rejected(rejected) => rejected.reason,
+			JoinResponse::Accepted(_) => panic!("accepted"),
+		}
+	};
+	let future = (PROTOCOL_VERSION + 1, vec![0.5; 3], "new fields".to_string());
+	assert!(matches!(join(&future), RejectReason::VersionTooNew { client, .. } if client == PROTOCOL_VERSION + 1));
+	let ancient = (MIN_PROTOCOL_VERSION - 1, vec![], String::new());
+	assert!(matches!(join(&ancient), RejectReason::VersionTooOld { .. }));
+}
+
+// A client that connects but never sends its join request does not hold up the server,
+// and is dropped after a while.
+#[test]
+fn silent_client() {
+	std::env::set_current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/..")).unwrap();
+	let mut server = NetServer::bind(ServerOpts {
+		addr: "127.0.0.1:0".into(),
+		query_port: 0,
+		no_stdin_console: true,
+		maplist: vec!["deck".into()],
+		..default()
+	})
+	.unwrap();
+	let addr = ("127.0.0.1", server.game_port());
+	let mut silent = std::net::TcpStream::connect(addr).unwrap();
+	thread::sleep(Duration::from_millis(100));
+
+	// another client joins while the first one is still silent.
+	let start = Instant::now();
+	let mut conn = std::net::TcpStream::connect(addr).unwrap();
+	wireformat::serialize_into(&mut conn, &join_req("alice")).unwrap();
+	conn.set_nonblocking(true).unwrap();
+	let mut ticks = 0;
+	while conn.peek(&mut [0]).is_err() {
+		assert!(start.elapsed() < Duration::from_secs(1), "server stuck after {ticks} ticks");
+		server.tick().unwrap();
+		ticks += 1;
+		thread::sleep(Duration::from_millis(10));
+	}
+	conn.set_nonblocking(false).unwrap();
+	assert!(matches!(wireformat::deserialize_from(&mut conn).unwrap(), JoinResponse::Accepted(_)));
+
+	silent.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	assert_eq!(std::io::Read::read(&mut silent, &mut [0]).unwrap(), 0, "silent client not dropped");
+
+	// nor is one that trickles in its join request a byte at a time.
+	let mut request = vec![];
+	wireformat::serialize_into(&mut request, &join_req("bob")).unwrap();
+	let start = Instant::now();
+	let mut trickle = std::net::TcpStream::connect(addr).unwrap();
+	for byte in request.chunks(1) {
+		if std::io::Write::write_all(&mut trickle, byte).is_err() {
+			break;
+		}
+		thread::sleep(Duration::from_millis(300));
+	}
+	assert!(start.elapsed() < Duration::from_secs(5), "trickling client not dropped");
+
+	// only a few connections per address may wait to join at once.
+	let waiting = (0..4).map(|_| std::net::TcpStream::connect(addr).unwrap()).collect::<Vec<_>>();
+	thread::sleep(Duration::from_millis(100));
+	let mut refused = std::net::TcpStream::connect(addr).unwrap();
+	refused.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+	assert_eq!(std::io::Read::read(&mut refused, &mut [0]).unwrap(), 0, "too many handshakes allowed");
+	drop(waiting);
+}
+
+// The console socket replaces a stale socket from a previous run, but nothing else.
+#[cfg(unix)]
+#[test]
+fn console_socket() {
+	std::env::set_current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/..")).unwrap();
+	let path = std::env::temp_dir().join(format!("hacksilver-console-{}.sock", std::process::id()));
+	let bind = || {
+		NetServer::bind(ServerOpts {
+			addr: "127.0.0.1:0".into(),
+			query_port: 0,
+			no_stdin_console: true,
+			console_socket: Some(path.to_string_lossy().into()),
+			maplist: vec!["deck".into()],
+			..default()
+		})
+	};
+
+	std::fs::write(&path, "precious").unwrap();
+	assert!(bind().is_err());
+	assert_eq!(std::fs::read_to_string(&path).unwrap(), "precious");
+	std::fs::remove_file(&path).unwrap();
+
+	drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+	assert!(path.exists());
+	let _server = bind().unwrap();
+
+	// full rights over the server: only for our own user.
+	use std::os::unix::fs::PermissionsExt;
+	assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+	std::os::unix::net::UnixStream::connect(&path).unwrap();
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn map_names_checked() {
+	std::env::set_current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/..")).unwrap();
+	let assets = AssetsDir::find().unwrap();
+	for name in ["../../../tmp/evil", "/tmp/evil", "a/b", "..", "deck.hx", ""] {
+		assert!(check_map_name(name).is_err(), "{name:?}");
+		assert!(assets.cached_map_dir(name).is_err(), "{name:?}");
+		let map_switch = MapSwitch {
+			map_name: name.into(),
+			map_hash: 0,
+			entities: default(),
+		};
+		assert!(find_map_dir(&assets, &map_switch).is_none(), "{name:?}");
+		assert!(MapDownload::start(&assets, map_switch).is_err(), "{name:?}");
+	}
+	for name in ["deck", "the_climb", "map-2"] {
+		assert!(check_map_name(name).is_ok(), "{name:?}");
+	}
+	assert!(NetServer::local(ServerOpts {
+		maplist: vec!["../maps/deck".into()],
+		..default()
+	})
+	.is_err());
+}
+
+#[test]
+fn connector() {
+	let server = server();
+	let connector = server.connector();
+	server.spawn();
+
+	let (mut conn, response) = connector.join(join_req("alice")).unwrap();
+	assert!(matches!(response, JoinResponse::Accepted(_)));
+	let start = Instant::now();
+	while !drain(&mut conn).iter().any(|msg| matches!(msg, ServerMsg::ServerTime(..))) {
+		assert!(start.elapsed() < Duration::from_secs(5), "no updates from local server");
+		thread::sleep(Duration::from_millis(10));
+	}
+}
+
+#[test]
+fn bots_play() {
+	let mut server = server();
+	let assets = AssetsDir::find().unwrap();
+	let mut bots = (0..3)
+		.map(|i| match server.join_local(Bot::join_request(&format!("bot{i}"), i)) {
+			(conn, JoinResponse::Accepted(accepted)) => Bot::new(&assets, conn, accepted, i).unwrap(),
+			(_, JoinResponse::Rejected(rejected)) => panic!("bot{i} rejected: {}", rejected.reason),
+		})
+		.collect::<Vec<_>>();
+
+	for _ in 0..100 {
+		server.tick().unwrap();
+		for bot in &mut bots {
+			bot.tick(Duration::from_millis(30)).unwrap();
+		}
+	}
+
+	for bot in &bots {
+		assert_eq!(bot.server_ticks(), 100);
+		assert_eq!(bot.replica().world.entities.players.iter().count(), 3);
+	}
+	assert!(bots.iter().any(|bot| bot.replica().local_player().spawned));
+}
+
+#[test]
+fn admin_commands() {
+	let audit_log = std::env::temp_dir().join(format!("hacksilver-audit-{}.log", std::process::id()));
+	let mut server = server_with(ServerOpts {
+		admin_password: Some("secret".into()),
+		audit_log: Some(audit_log.to_string_lossy().into()),
+		..default()
+	});
+	let (mut alice, _) = join(&mut server, "alice");
+	let (mut bob, bob_acc) = join(&mut server, "bob");
+
+	let denied = command(&mut server, &mut bob, "kick alice");
+	assert!(denied.iter().any(|line| line.contains("admins only")), "{denied:?}");
+	let wrong = command(&mut server, &mut bob, "login hunter2");
+	assert!(wrong.iter().any(|line| line.contains("wrong password")), "{wrong:?}");
+	command(&mut server, &mut bob, "login secret");
+	command(&mut server, &mut bob, "kick alice");
+
+	// alice is told why, then disconnected.
+	let mut kicked = false;
+	while let Some(msg) = alice.try_recv() {
+		match msg {
+			Ok(ServerMsg::Log(line)) if line == "kicked by bob" => kicked = true,
+			Ok(_) => (),
+			Err(_) => break,
+		}
+	}
+	assert!(kicked);
+	assert!(alice.try_recv().unwrap().is_err());
+
+	let audit = std::fs::read_to_string(&audit_log).unwrap();
+	std::fs::remove_file(&audit_log).unwrap();
+	let bob = format!("bob ({})", bob_acc.player_id);
+	assert!(audit.contains(&format!("{bob}: kick alice: denied")), "{audit}");
+	assert!(audit.contains(&format!("{bob}: login ***: error: wrong password")), "{audit}");
+	assert!(audit.contains(&format!("{bob}: kick alice: ok")), "{audit}");
+	assert!(!audit.contains("secret") && !audit.contains("hunter2"), "{audit}");
+}
+
+// Failed logins are counted per address: reconnecting does not start over.
+#[test]
+fn login_lockout() {
+	let mut server = server_with(ServerOpts {
+		admin_password: Some("secret".into()),
+		..default()
+	});
+	let (mut mallory, _) = join(&mut server, "mallory");
+	for _ in 0..2 {
+		command(&mut server, &mut mallory, "login hunter2");
+	}
+	mallory.send(ClientMsg::Command("login hunter2".into())).unwrap();
+	server.tick().unwrap();
+	assert!(!server.console_command("players").contains("mallory"));
+
+	// even the right password is refused (and gets them kicked again).
+	let (mut mallory, _) = join(&mut server, "mallory");
+	drain(&mut mallory);
+	mallory.send(ClientMsg::Command("login secret".into())).unwrap();
+	server.tick().unwrap();
+	let msgs = iter::from_fn(|| mallory.try_recv()).take_while(|msg| msg.is_ok()).collect::<Vec<_>>();
+	assert!(msgs.iter().any(|msg| matches!(msg, Ok(ServerMsg::Log(line)) if line == "too many failed logins")));
+	assert!(!server.console_command("players").contains("mallory"));
+}
+
+#[test]
+fn console_commands() {
+	let mut server = server();
+	let (mut alice, _) = join(&mut server, "alice");
+	join(&mut server, "bob");
+
+	let players = server.console_command("players");
+	assert!(players.contains("alice") && players.contains("bob"), "{players}");
+	assert!(server.console_command("login whatever").starts_with("error"));
+	assert!(server.console_command("frobnicate").starts_with("error"));
+
+	assert_eq!(server.console_command("kick alice"), "ok");
+	server.tick().unwrap();
+	let msgs = iter::from_fn(|| alice.try_recv()).take_while(|msg| msg.is_ok()).collect::<Vec<_>>();
+	assert!(msgs.iter().any(|msg| matches!(msg, Ok(ServerMsg::Log(line)) if line == "kicked by the server")));
+	assert!(!server.console_command("players").contains("alice"));
+
+	assert!(!server.quit_requested());
+	server.console_command("quit");
+	assert!(server.quit_requested());
+}
+
+#[test]
+fn effects_only_sent_nearby() {
+	let mut server = server();
+	let (mut alice, alice_acc) = join(&mut server, "alice");
+	let (mut bob, _) = join(&mut server, "bob");
+	server.tick().unwrap();
+
+	let alice_pos = respawn(&mut server, &mut alice, alice_acc.player_id).position();
+	let far_away = alice_pos + vec3((ServerOpts::default().interest_radius + 2) as f32 * ZONE_SIZE as f32, 0.0, 0.0);
+	for pos in [alice_pos, far_away] {
+		bob.send(ClientMsg::AddEffect(Effect::particle_explosion(pos, vec3(1.0, 1.0, 1.0)))).unwrap();
+	}
+	server.tick().unwrap();
+
+	let effects = drain(&mut alice)
+		.into_iter()
+		.filter_map(|msg| match msg {
+			ServerMsg::AddEffect(effect) => Some(effect.place()),
+			_ => None,
+		})
+		.collect::<Vec<_>>();
+	assert_eq!(effects, vec![Place::point(alice_pos)]);
+}
+
+// Far-away players' positions are not sent either (on an empty map, to put players anywhere).
+#[test]
+fn players_only_sent_nearby() {
+	std::env::set_current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/..")).unwrap();
+	let weapons = Weapons::load(&AssetsDir::find().unwrap()).unwrap();
+	let radius = ServerOpts::default().interest_radius;
+	assert!(radius as f32 * ZONE_SIZE as f32 >= weapons.max_range(), "players must see whom they can shoot");
+
+	let mut data = ServerData::new(World::new(Map::empty("empty"), default(), weapons), Some(radius));
+	let mut join = |name: &str, x: f32| {
+		let (id, _) = data.join_new_player(&default(), join_req(name), "red".parse().unwrap(), 0);
+		data.apply_to_player_partial(id, |p| {
+			p.spawned = true;
+			p.skeleton.position = vec3(x, 0.0, 0.0);
+		});
+		id
+	};
+	let alice = join("alice", 0.0);
+	let near = join("near", 4000.0);
+	let far = join("far", (radius + 2) as f32 * ZONE_SIZE as f32);
+
+	data.tick_time(0.03);
+	data.push_snapshots();
+	let seen_by_alice = data
+		.take_diffs()
+		.into_iter()
+		.filter(|msg| matches!(msg.to, Addressee::Just(id) if id == alice))
+		.find_map(|msg| match msg.msg {
+			ServerMsg::UpdatePlayers(delta) => Some(delta),
+			_ => None,
+		})
+		.unwrap();
+	let seen = |id: ID| seen_by_alice.players.iter().find(|p| p.id == id).unwrap().clone();
+	assert_eq!(seen(near).spawned, Some(true));
+	assert!(seen(near).frame.is_some());
+	assert_eq!(seen(far).spawned, Some(false));
+	assert!(seen(far).frame.is_none_or(|f| f.position == vec3::ZERO), "{:?}", seen(far));
+}
+
+#[test]
+fn tick_numbers() {
+	let mut server = server_with(ServerOpts { tick_rate: 10, ..default() });
+	let (mut alice, _) = join(&mut server, "alice");
+
+	let mut stamps = vec![];
+	for _ in 0..3 {
+		server.tick().unwrap();
+		let batch = drain(&mut alice);
+		match (&batch[0], batch.iter().find_map(|msg| if let ServerMsg::UpdatePlayers(delta) = msg { Some(delta.tick) } else { None })) {
+			(&ServerMsg::ServerTime(tick, time), Some(snapshot)) => {
+				assert_eq!(tick, snapshot);
+				stamps.push((tick, time));
+			}
+			_ => panic!("batch does not start with ServerTime"),
+		}
+	}
+	let (tick0, time0) = stamps[0];
+	for (i, &(tick, time)) in stamps.iter().enumerate() {
+		assert_eq!(tick, tick0 + i as u64);
+		assert!((time - time0 - 0.1 * i as f64).abs() < 1e-6, "{stamps:?}");
+	}
+
+	assert!(NetServer::local(ServerOpts { tick_rate: 0, ..default() }).is_err());
+}
+
+// Team of each player, as listed by the `players` command.
+// A client that reconnects before the server noticed its old connection is dead (e.g. half-open TCP)
+// takes over its session: same ID, name and team, and its slot is not taken twice.
+#[test]
+fn reconnect_while_still_connected() {
+	let mut server = server_with(ServerOpts { max_players: 1, ..default() });
+	let (mut old, acc) = match server.join_local(JoinRequest {
+		team: "blue".parse().ok(),
+		..join_req("alice")
+	}) {
+		(conn, JoinResponse::Accepted(acc)) => (conn, acc),
+		_ => panic!("alice rejected"),
+	};
+
+	let (mut new, resumed) = match server.join_local(JoinRequest {
+		session: Some(acc.session),
+		..join_req("alice")
+	}) {
+		(conn, JoinResponse::Accepted(acc)) => (conn, acc),
+		(_, JoinResponse::Rejected(rejected)) => panic!("alice rejected: {}", rejected.reason),
+	};
+	assert_eq!(resumed.player_id, acc.player_id);
+	assert_eq!(resumed.name, "alice");
+	assert_eq!(teams(&mut server), [("alice".to_string(), "Blue".to_string())]);
+
+	server.tick().unwrap();
+	assert!(iter::from_fn(|| old.try_recv()).any(|msg| msg.is_err()), "old connection still open");
+	assert!(!drain(&mut new).is_empty());
+}
+
+fn teams(server: &mut NetServer) -> Vec<(String, String)> {
+	server
+		.console_command("players")
+		.lines()
+		.map(|line| line.split_whitespace().take(2).map(String::from).collect::<Vec<_>>())
+		.map(|cols| (cols[0].clone(), cols[1].clone()))
+		.collect()
+}
+
+fn team_of(server: &mut NetServer, name: &str) -> String {
+	teams(server).into_iter().find(|(n, _)| n == name).unwrap().1
+}
+
+#[test]
+fn auto_team() {
+	let mut server = server();
+	for name in ["a", "b", "c"] {
+		server.join_local(JoinRequest { team: None, ..join_req(name) });
+	}
+	let mut teams = teams(&mut server).into_iter().map(|(_, team)| team).collect::<Vec<_>>();
+	teams.sort();
+	assert_eq!(teams, ["Blue", "Green", "Red"]);
+}
+
+#[test]
+fn team_balance() {
+	let mut server = server_with(ServerOpts { team_balance: true, ..default() });
+	let join_team = |server: &mut NetServer, name: &str, team: &str| match server.join_local(JoinRequest {
+		team: team.parse().ok(),
+		..join_req(name)
+	}) {
+		(conn, JoinResponse::Accepted(_)) => conn,
+		_ => panic!("{name} rejected"),
+	};
+	let _alice = join_team(&mut server, "alice", "red");
+	let _bob = join_team(&mut server, "bob", "red");
+	let _carol = join_team(&mut server, "carol", "blue");
+	let mut dave = join_team(&mut server, "dave", "green");
+
+	// blue is empty now: the newest red player is moved over, and everyone is told.
+	server.console_command("kick carol");
+	assert_eq!(team_of(&mut server, "bob"), "Blue");
+	assert_eq!(team_of(&mut server, "alice"), "Red");
+	server.tick().unwrap();
+	let msgs = drain(&mut dave);
+	assert!(msgs
+		.iter()
+		.any(|msg| matches!(msg, ServerMsg::UpdateHUD(hud) if hud.text == "bob moved to team Blue to balance the teams")));
+	// through a snapshot (bob may be too far away for a full update, see `ServerOpts::interest_radius`).
+	assert!(msgs
+		.iter()
+		.any(|msg| matches!(msg, ServerMsg::UpdatePlayers(delta) if delta.players.iter().any(|p| p.team.is_some() && p.team == "blue".parse().ok()))));
+
+	// switching would leave green empty.
+	let refused = command(&mut server, &mut dave, "team red");
+	assert!(refused.iter().any(|line| line.contains("uneven")), "{refused:?}");
+	assert_eq!(team_of(&mut server, "dave"), "Green");
+	let unknown = command(&mut server, &mut dave, "team purple");
+	assert!(unknown.iter().any(|line| line.contains("unknown team")), "{unknown:?}");
+}
+
+#[test]
+fn switch_team() {
+	let mut server = server();
+	let (mut alice, _) = join(&mut server, "alice");
+	command(&mut server, &mut alice, "team green");
+	assert_eq!(team_of(&mut server, "alice"), "Green");
+	let again = command(&mut server, &mut alice, "team green");
+	assert!(again.iter().any(|line| line.contains("already on team Green")), "{again:?}");
+}
+
+// Chat lines received, as (sender, channel, text).
+fn chats(conn: &mut Conn) -> Vec<(Option<ID>, ChatChannel, String)> {
+	drain(conn)
+		.into_iter()
+		.filter_map(|msg| match msg {
+			ServerMsg::Chat(chat) => Some((chat.sender, chat.channel, chat.text)),
+			_ => None,
+		})
+		.collect()
+}
+
+#[test]
+fn chat_channels() {
+	let mut server = server();
+	let join_team = |server: &mut NetServer, name: &str, team: &str| match server.join_local(JoinRequest {
+		team: team.parse().ok(),
+		..join_req(name)
+	}) {
+		(conn, JoinResponse::Accepted(acc)) => (conn, acc.player_id),
+		_ => panic!("{name} rejected"),
+	};
+	let (mut alice, alice_id) = join_team(&mut server, "alice", "red");
+	let (mut bob, bob_id) = join_team(&mut server, "bob", "red");
+	let (mut carol, carol_id) = join_team(&mut server, "carol", "blue");
+	let red = "red".parse().unwrap();
+
+	command(&mut server, &mut alice, "say_team  go left");
+	assert_eq!(chats(&mut bob), [(Some(alice_id), ChatChannel::Team(red), "go left".to_string())]);
+	assert!(chats(&mut carol).is_empty());
+
+	command(&mut server, &mut carol, "tell alice gg wp");
+	assert_eq!(chats(&mut alice), [(Some(carol_id), ChatChannel::Private { to: alice_id }, "gg wp".to_string())]);
+	alice.send(ClientMsg::Command("tell bob psst".into())).unwrap();
+	server.tick().unwrap();
+	let private = ChatChannel::Private { to: bob_id };
+	assert_eq!(chats(&mut bob), [(Some(alice_id), private, "psst".to_string())]);
+	assert_eq!(chats(&mut alice), [(Some(alice_id), private, "psst".to_string())]);
+	assert!(chats(&mut carol).is_empty());
+
+	// one line only: no faking lines from someone else.
+	command(&mut server, &mut carol, "tell bob hi\nserver: you are banned\x07");
+	let line = "hi server: you are banned".to_string();
+	assert_eq!(chats(&mut bob), [(Some(carol_id), ChatChannel::Private { to: bob_id }, line)]);
+
+	server.console_command("say restarting soon");
+	server.tick().unwrap();
+	assert_eq!(chats(&mut carol), [(None, ChatChannel::All, "restarting soon".to_string())]);
+	assert!(server.console_command("say_team hi").starts_with("error"));
+
+	// flood: alice already said 2 lines.
+	for i in 0..3 {
+		command(&mut server, &mut alice, &format!("say {i}"));
+	}
+	let refused = command(&mut server, &mut alice, "say one too many");
+	assert!(refused.iter().any(|line| line.contains("not so fast")), "{refused:?}");
+}
+
+fn frags(server: &mut NetServer, name: &str) -> u32 {
+	let players = server.console_command("players");
+	let line = players.lines().find(|line| line.split_whitespace().next() == Some(name)).unwrap();
+	line.split_whitespace().nth(2).unwrap().parse().unwrap()
+}
+
+// Spawn a player, return them as spawned by the server.
+fn respawn(server: &mut NetServer, conn: &mut Conn, id: ID) -> Player {
+	conn.send(ClientMsg::ReadyToSpawn).unwrap();
+	server.tick().unwrap();
+	drain(conn)
+		.into_iter()
+		.find_map(|msg| match msg {
+			ServerMsg::UpdatePlayerFull(player) if player.id == id && player.spawned => Some(player),
+			_ => None,
+		})
+		.expect("respawn")
+}
+
+// Latest server time and health of player `id` sent to `conn`.
+fn time_and_health(conn: &mut Conn, id: ID) -> (f64, Option<i32>) {
+	let (mut time, mut health) = (0.0, None);
+	for msg in drain(conn) {
+		match msg {
+			ServerMsg::ServerTime(_, t) => time = t,
+			ServerMsg::UpdatePlayers(delta) => health = delta.players.iter().find(|p| p.id == id).and_then(|p| p.health).or(health),
+			_ => (),
+		}
+	}
+	(time, health)
+}
+
+// Turn the shooter towards `victim`, and claim a hit at `height` above their feet.
+fn shoot(conn: &mut Conn, shooter: &Player, victim: &Player, height: f32, view_time: f64) {
+	let start = shooter.center();
+	let dir = (victim.position() + height * vec3::EY - start).normalized();
+	let orientation = Orientation {
+		yaw: f32::atan2(dir.x(), dir.z()),
+		pitch: -f32::asin(dir.y()),
+	};
+	conn.send(ClientMsg::MovePlayerIfSpawned(shooter.skeleton.frame().with(|f| f.orientation = orientation))).unwrap();
+	let line_of_fire = Ray64::new(start.into(), dir.into());
+	conn.send(ClientMsg::HitPlayer(HitClaim {
+		victim: victim.id,
+		line_of_fire,
+		view_time,
+	}))
+	.unwrap();
+}
+
+#[test]
+fn damage() {
+	let mut server = server();
+	let (mut alice, acc) = join(&mut server, "alice");
+	let alice_id = acc.player_id;
+	let (mut bob, bob_id) = match server.join_local(JoinRequest {
+		team: "blue".parse().ok(),
+		..join_req("bob")
+	}) {
+		(conn, JoinResponse::Accepted(acc)) => (conn, acc.player_id),
+		_ => panic!("bob rejected"),
+	};
+	let wait = |server: &mut NetServer, secs: f32| (0..(secs * 30.0) as usize).for_each(|_| server.tick().unwrap());
+
+	// spawn points are random: respawn until alice has a clear shot at bob.
+	for _ in 0..20 {
+		let shooter = respawn(&mut server, &mut alice, alice_id);
+		let victim = respawn(&mut server, &mut bob, bob_id);
+
+		// invulnerable right after spawning.
+		let (now, _) = time_and_health(&mut alice, bob_id);
+		shoot(&mut alice, &shooter, &victim, 6.0, now);
+		server.tick().unwrap();
+		assert_eq!(time_and_health(&mut bob, bob_id).1, Some(100));
+
+		wait(&mut server, 2.0);
+		let (now, _) = time_and_health(&mut alice, bob_id);
+		shoot(&mut alice, &shooter, &victim, 6.0, now);
+		server.tick().unwrap();
+		if time_and_health(&mut bob, bob_id).1 != Some(50) {
+			server.console_command("kill alice");
+			server.console_command("kill bob");
+			continue; // missed (a wall in between), or hit bob's head from above.
+		}
+
+		// a hit claimed while looking the other way is refused.
+		wait(&mut server, 1.0);
+		let (now, _) = time_and_health(&mut alice, bob_id);
+		let dir = (victim.center() - shooter.center()).normalized();
+		let away = Orientation {
+			yaw: f32::atan2(-dir.x(), -dir.z()),
+			pitch: 0.0,
+		};
+		alice.send(ClientMsg::MovePlayerIfSpawned(shooter.skeleton.frame().with(|f| f.orientation = away))).unwrap();
+		alice
+			.send(ClientMsg::HitPlayer(HitClaim {
+				victim: bob_id,
+				line_of_fire: Ray64::new(shooter.center().into(), dir.into()),
+				view_time: now,
+			}))
+			.unwrap();
+		server.tick().unwrap();
+		assert_eq!(time_and_health(&mut bob, bob_id).1, Some(50));
+
+		// a headshot finishes bob off.
+		let frags_before = frags(&mut server, "alice");
+		wait(&mut server, 1.0);
+		let (now, _) = time_and_health(&mut alice, bob_id);
+		shoot(&mut alice, &shooter, &victim, Player::TORSO_HEIGHT + Player::HEAD_HEIGHT / 2.0, now);
+		server.tick().unwrap();
+		assert_eq!(time_and_health(&mut bob, bob_id).1, Some(-50));
+		assert_eq!(frags(&mut server, "alice"), frags_before + 1);
+
+		assert_eq!(respawn(&mut server, &mut bob, bob_id).health, Player::MAX_HEALTH);
+		return;
+	}
+	panic!("alice never had a clear shot at bob");
+}
+
+#[test]
+fn switch_weapon() {
+	let mut server = server();
+	let (mut alice, acc) = join(&mut server, "alice");
+	let (mut bob, _) = join(&mut server, "bob");
+	let weapon_of_alice = |bob: &mut Conn| {
+		drain(bob).into_iter().fold(None, |weapon, msg| match msg {
+			ServerMsg::UpdatePlayers(delta) => delta.players.iter().find(|p| p.id == acc.player_id).and_then(|p| p.weapon).or(weapon),
+			_ => weapon,
+		})
+	};
+
+	alice.send(ClientMsg::SwitchWeapon(1)).unwrap();
+	server.tick().unwrap();
+	assert_eq!(weapon_of_alice(&mut bob), Some(1));
+
+	// no such weapon (bob acknowledges no snapshots, so gets alice's full state every tick).
+	alice.send(ClientMsg::SwitchWeapon(99)).unwrap();
+	server.tick().unwrap();
+	assert_eq!(weapon_of_alice(&mut bob), Some(1));
+}
+
+// Projectiles, explosions and knockback sent to `conn`.
+fn projectile_msgs(conn: &mut Conn) -> (Vec<ID>, Vec<ID>, Vec<f32>, Vec<vec3>) {
+	let (mut added, mut removed, mut explosions, mut kicks) = (vec![], vec![], vec![], vec![]);
+	for msg in drain(conn) {
+		match msg {
+			ServerMsg::AddProjectile(id, _) => added.push(id),
+			ServerMsg::RemoveProjectile(id) => removed.push(id),
+			ServerMsg::AddEffect(Effect {
+				typ: EffectType::Explosion { radius, .. },
+				..
+			}) => explosions.push(radius),
+			ServerMsg::Knockback(kick) => kicks.push(kick),
+			_ => (),
+		}
+	}
+	(added, removed, explosions, kicks)
+}
+
+#[test]
+fn projectiles() {
+	let mut server = server();
+	let (mut alice, acc) = join(&mut server, "alice");
+	let alice_id = acc.player_id;
+	let (mut bob, bob_id) = match server.join_local(JoinRequest {
+		team: "blue".parse().ok(),
+		..join_req("bob")
+	}) {
+		(conn, JoinResponse::Accepted(acc)) => (conn, acc.player_id),
+		_ => panic!("bob rejected"),
+	};
+	let wait = |server: &mut NetServer, secs: f32| (0..(secs * 30.0) as usize).for_each(|_| server.tick().unwrap());
+	let rockets = 3;
+	alice.send(ClientMsg::SwitchWeapon(rockets)).unwrap();
+
+	// rocket jump: a rocket at your own feet pushes you up, but does not hurt.
+	// Firing again before the cooldown is ignored.
+	let shooter = respawn(&mut server, &mut alice, alice_id);
+	let down = Ray64::new(shooter.center().into(), (-vec3::EY).into());
+	alice.send(ClientMsg::FireProjectile(down.clone())).unwrap();
+	alice.send(ClientMsg::FireProjectile(down)).unwrap();
+	server.tick().unwrap();
+	let (added, _, _, _) = projectile_msgs(&mut alice);
+	assert_eq!(added.len(), 1);
+	wait(&mut server, 0.5);
+	let (_, removed, explosions, kicks) = projectile_msgs(&mut alice);
+	assert_eq!(removed, added);
+	assert_eq!(explosions, vec![30.0]);
+	assert_eq!(kicks.len(), 1);
+	assert!(kicks[0].y() > 0.0, "{kicks:?}");
+	server.tick().unwrap();
+	assert_eq!(time_and_health(&mut alice, alice_id).1, Some(100));
+
+	// spawn points are random: respawn until alice has a clear shot at bob.
+	for _ in 0..100 {
+		let shooter = respawn(&mut server, &mut alice, alice_id);
+		let victim = respawn(&mut server, &mut bob, bob_id);
+		wait(&mut server, 2.0); // invulnerable right after spawning.
+
+		let dir = (victim.center() - shooter.center()).normalized();
+		alice.send(ClientMsg::FireProjectile(Ray64::new(shooter.center().into(), dir.into()))).unwrap();
+		wait(&mut server, 1.0);
+		let (_, health) = time_and_health(&mut bob, bob_id);
+		if health == Some(100) {
+			server.console_command("kill alice");
+			server.console_command("kill bob");
+			continue; // a wall in between.
+		}
+		assert!(health < Some(100), "{health:?}");
+		return;
+	}
+	panic!("alice never had a clear shot at bob");
+}